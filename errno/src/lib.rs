@@ -0,0 +1,243 @@
+#![cfg_attr(not(test), no_std)]
+use core::fmt;
+
+// error numbers as linux on riscv64 has them, a syscall returns one
+// negated in a0 when it fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENXIO = 6,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    ENOTBLK = 15,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    ENFILE = 23,
+    EMFILE = 24,
+    ENOTTY = 25,
+    ETXTBSY = 26,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    EMLINK = 31,
+    EPIPE = 32,
+    EDOM = 33,
+    ERANGE = 34,
+    EDEADLK = 35,
+    ENAMETOOLONG = 36,
+    ENOLCK = 37,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+    EOPNOTSUPP = 95,
+}
+
+use Errno::*;
+
+const ALL: [Errno; 41] = [
+    EPERM,
+    ENOENT,
+    ESRCH,
+    EINTR,
+    EIO,
+    ENXIO,
+    E2BIG,
+    ENOEXEC,
+    EBADF,
+    ECHILD,
+    EAGAIN,
+    ENOMEM,
+    EACCES,
+    EFAULT,
+    ENOTBLK,
+    EBUSY,
+    EEXIST,
+    EXDEV,
+    ENODEV,
+    ENOTDIR,
+    EISDIR,
+    EINVAL,
+    ENFILE,
+    EMFILE,
+    ENOTTY,
+    ETXTBSY,
+    EFBIG,
+    ENOSPC,
+    ESPIPE,
+    EROFS,
+    EMLINK,
+    EPIPE,
+    EDOM,
+    ERANGE,
+    EDEADLK,
+    ENAMETOOLONG,
+    ENOLCK,
+    ENOSYS,
+    ENOTEMPTY,
+    ELOOP,
+    EOPNOTSUPP,
+];
+
+// linux keeps the top 4095 values of a0 for errors
+const MAX_ERRNO: isize = 4095;
+
+impl Errno {
+    pub fn from_raw(n: isize) -> Option<Self> {
+        ALL.iter().copied().find(|&e| e as isize == n)
+    }
+
+    pub fn as_raw(self) -> isize {
+        self as isize
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            EPERM => "Operation not permitted",
+            ENOENT => "No such file or directory",
+            ESRCH => "No such process",
+            EINTR => "Interrupted system call",
+            EIO => "I/O error",
+            ENXIO => "No such device or address",
+            E2BIG => "Argument list too long",
+            ENOEXEC => "Exec format error",
+            EBADF => "Bad file descriptor",
+            ECHILD => "No child processes",
+            EAGAIN => "Try again",
+            ENOMEM => "Out of memory",
+            EACCES => "Permission denied",
+            EFAULT => "Bad address",
+            ENOTBLK => "Block device required",
+            EBUSY => "Device or resource busy",
+            EEXIST => "File exists",
+            EXDEV => "Cross-device link",
+            ENODEV => "No such device",
+            ENOTDIR => "Not a directory",
+            EISDIR => "Is a directory",
+            EINVAL => "Invalid argument",
+            ENFILE => "File table overflow",
+            EMFILE => "Too many open files",
+            ENOTTY => "Not a typewriter",
+            ETXTBSY => "Text file busy",
+            EFBIG => "File too large",
+            ENOSPC => "No space left on device",
+            ESPIPE => "Illegal seek",
+            EROFS => "Read-only file system",
+            EMLINK => "Too many links",
+            EPIPE => "Broken pipe",
+            EDOM => "Math argument out of domain of func",
+            ERANGE => "Math result not representable",
+            EDEADLK => "Resource deadlock would occur",
+            ENAMETOOLONG => "File name too long",
+            ENOLCK => "No record locks available",
+            ENOSYS => "Invalid system call number",
+            ENOTEMPTY => "Directory not empty",
+            ELOOP => "Too many symbolic links encountered",
+            EOPNOTSUPP => "Operation not supported",
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self, self.description())
+    }
+}
+
+// what a syscall leaves in a0 for r
+pub fn encode(r: Result<usize, Errno>) -> isize {
+    match r {
+        Ok(v) => v as isize,
+        Err(e) => -e.as_raw(),
+    }
+}
+
+// the result a syscall left in a0, numbers no errno knows stand for EINVAL
+pub fn decode(ret: isize) -> Result<usize, Errno> {
+    if (-MAX_ERRNO..0).contains(&ret) {
+        Err(Errno::from_raw(-ret).unwrap_or(EINVAL))
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_raw_values() {
+        for (i, e) in ALL[..40].iter().enumerate() {
+            assert_eq!(e.as_raw(), i as isize + 1);
+        }
+        assert_eq!(EOPNOTSUPP.as_raw(), 95);
+        for e in ALL {
+            assert_eq!(Errno::from_raw(e.as_raw()), Some(e));
+        }
+        assert_eq!(Errno::from_raw(0), None);
+        assert_eq!(Errno::from_raw(41), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(encode(Err(ENOENT)), -2);
+        assert_eq!(encode(Ok(7)), 7);
+        for e in ALL {
+            assert_eq!(decode(encode(Err(e))), Err(e));
+        }
+        assert_eq!(decode(0), Ok(0));
+        assert_eq!(decode(-4096), Ok(-4096isize as usize));
+        assert_eq!(decode(-100), Err(EINVAL));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        for n in [
+            isize::MIN,
+            -MAX_ERRNO,
+            -1,
+            0,
+            41,
+            94,
+            96,
+            MAX_ERRNO,
+            isize::MAX,
+        ] {
+            assert_eq!(Errno::from_raw(n), None);
+        }
+        // only the top 4095 values of a0 are errors, all of them decode
+        assert_eq!(decode(-1), Err(EPERM));
+        assert_eq!(decode(-MAX_ERRNO), Err(EINVAL));
+        assert_eq!(decode(-MAX_ERRNO - 1), Ok((-MAX_ERRNO - 1) as usize));
+        assert_eq!(decode(isize::MIN), Ok(isize::MIN as usize));
+        assert_eq!(decode(isize::MAX), Ok(isize::MAX as usize));
+    }
+
+    #[test]
+    fn test_ok_round_trip() {
+        for v in [0, 1, 4096, usize::MAX / 2] {
+            assert_eq!(decode(encode(Ok(v))), Ok(v));
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(EXDEV.to_string(), "EXDEV: Cross-device link");
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use jfs::{BlkDev, IOError, IOResult, SECTOR_SIZE};
+
+// a block device backed by a regular file
+pub struct FileDevice {
+    file: Mutex<File>,
+    sectors: usize,
+}
+
+impl FileDevice {
+    pub fn create(path: &Path, sectors: usize) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((sectors * SECTOR_SIZE) as u64)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            sectors,
+        })
+    }
+
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let sectors = file.metadata()?.len() as usize / SECTOR_SIZE;
+        Ok(Self {
+            file: Mutex::new(file),
+            sectors,
+        })
+    }
+}
+
+impl FileDevice {
+    fn check(&self, blk: usize, len: usize) -> IOResult<()> {
+        if !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(IOError::BadBufSize);
+        }
+        if blk + len / SECTOR_SIZE > self.sectors {
+            return Err(IOError::NoSuchBlock);
+        }
+        Ok(())
+    }
+}
+
+impl BlkDev for FileDevice {
+    fn read(&self, blk: usize, buf: &mut [u8]) -> IOResult<()> {
+        if buf.len() != SECTOR_SIZE {
+            return Err(IOError::BadBufSize);
+        }
+        self.read_blocks(blk, buf)
+    }
+    fn write(&self, blk: usize, buf: &[u8]) -> IOResult<()> {
+        if buf.len() != SECTOR_SIZE {
+            return Err(IOError::BadBufSize);
+        }
+        self.write_blocks(blk, buf)
+    }
+    fn read_blocks(&self, blk: usize, buf: &mut [u8]) -> IOResult<()> {
+        self.check(blk, buf.len())?;
+        let mut f = self.file.lock().unwrap();
+        f.seek(SeekFrom::Start((blk * SECTOR_SIZE) as u64))
+            .and_then(|_| f.read_exact(buf))
+            .map_err(|_| IOError::Unknown)
+    }
+    fn write_blocks(&self, blk: usize, buf: &[u8]) -> IOResult<()> {
+        self.check(blk, buf.len())?;
+        let mut f = self.file.lock().unwrap();
+        f.seek(SeekFrom::Start((blk * SECTOR_SIZE) as u64))
+            .and_then(|_| f.write_all(buf))
+            .map_err(|_| IOError::Unknown)
+    }
+    fn flush(&self) -> IOResult<()> {
+        self.file
+            .lock()
+            .unwrap()
+            .sync_data()
+            .map_err(|_| IOError::Unknown)
+    }
+}
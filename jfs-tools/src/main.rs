@@ -0,0 +1,362 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+};
+
+use device::FileDevice;
+use jfs::{
+    fsck, sync_blocks, BlkDev, FileType, IOError, Inode, MkfsOptions, JFS, JOURNAL_BLOCKS,
+    LABEL_LEN, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE, SECTOR_SIZE,
+};
+
+mod device;
+
+const USAGE: &str = "usage:
+    jfs-tools mkfs <image> <total_blocks> <inode_blocks> [--block-size <n>] [--label <label>] [--extents] [--bitmaps]
+    jfs-tools info <image>
+    jfs-tools pack <image> <dir>
+    jfs-tools ls <image> [path]
+    jfs-tools cat <image> <path>
+    jfs-tools rm <image> <path>
+    jfs-tools fsck <image> [--repair]";
+
+const DEFAULT_BLOCK_SIZE: usize = 4096;
+// packed apps are executable by everyone
+const APP_MODE: u16 = 0o755;
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+fn main() {
+    jfs::set_clock(unix_now);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    match args[..] {
+        ["mkfs", image, total, inode, ref opts @ ..] => {
+            let total = total.parse().map_err(|_| "bad total_blocks")?;
+            let inode = inode.parse().map_err(|_| "bad inode_blocks")?;
+            let mut block_size = DEFAULT_BLOCK_SIZE;
+            let mut label = "";
+            let mut extents = false;
+            let mut bitmaps = false;
+            let mut opts = opts.iter();
+            while let Some(opt) = opts.next() {
+                match *opt {
+                    "--extents" => extents = true,
+                    "--bitmaps" => bitmaps = true,
+                    "--block-size" => {
+                        let n = opts.next().ok_or(USAGE)?;
+                        block_size = n.parse().map_err(|_| "bad block size")?
+                    }
+                    "--label" => label = opts.next().ok_or(USAGE)?,
+                    _ => return Err(USAGE.to_string()),
+                }
+            }
+            let opts = MkfsOptions {
+                block_size,
+                label,
+                extents,
+                bitmaps,
+                ..Default::default()
+            };
+            mkfs(Path::new(image), total, inode, opts)
+        }
+        ["info", image] => {
+            let fs = open(Path::new(image))?;
+            let info = fs.info().map_err(fs_err)?;
+            let st = fs.statfs().map_err(fs_err)?;
+            let uuid: String = info.uuid.iter().map(|b| format!("{:02x}", b)).collect();
+            println!("label:          {}", info.label);
+            println!("uuid:           {}", uuid);
+            println!("block size:     {}", info.block_size);
+            println!("total blocks:   {}", info.total_blocks);
+            println!("inode blocks:   {}", info.inode_blocks);
+            println!("journal blocks: {}", info.journal_blocks);
+            println!("data blocks:    {}", info.data_blocks);
+            println!(
+                "bitmap blocks:  {} inode, {} data",
+                info.inode_bitmap_blocks, info.block_bitmap_blocks
+            );
+            println!("free blocks:    {} of {}", st.free_blocks, st.blocks);
+            println!("free inodes:    {} of {}", st.free_inodes, st.inodes);
+            println!(
+                "features:       compat {:#x} incompat {:#x}",
+                info.feature_compat, info.feature_incompat
+            );
+            println!("mount count:    {}", info.mount_count);
+            println!("clean:          {}", info.clean);
+            Ok(())
+        }
+        ["pack", image, dir] => {
+            let root = open(Path::new(image))?.root_dir();
+            for name in pack(&root, Path::new(dir))? {
+                println!("packed {}", name);
+            }
+            sync()
+        }
+        ["ls", image] | ["ls", image, _] => {
+            let root = open(Path::new(image))?.root_dir();
+            let dir = root.lookup(args.get(2).unwrap_or(&"/")).map_err(fs_err)?;
+            for name in dir.ls().map_err(fs_err)? {
+                let inode = dir.walk(&name, false, |_| Ok(())).map_err(fs_err)?;
+                let st = inode.stat().map_err(fs_err)?;
+                let name = match st.file_type {
+                    FileType::Symlink => {
+                        format!("{} -> {}", name, inode.readlink().map_err(fs_err)?)
+                    }
+                    _ => name,
+                };
+                println!(
+                    "{} {:>4} {:>4} {:>8} {}",
+                    mode_string(st.file_type, st.mode),
+                    st.uid,
+                    st.gid,
+                    st.size,
+                    name
+                );
+            }
+            Ok(())
+        }
+        ["cat", image, path] => {
+            let root = open(Path::new(image))?.root_dir();
+            let data = root
+                .lookup(path)
+                .map_err(fs_err)?
+                .read_all()
+                .map_err(fs_err)?;
+            use std::io::Write;
+            std::io::stdout()
+                .write_all(&data)
+                .map_err(|e| e.to_string())
+        }
+        ["rm", image, path] => {
+            let root = open(Path::new(image))?.root_dir();
+            let (parent, name) = match path.trim_end_matches('/').rsplit_once('/') {
+                Some((parent, name)) => (root.lookup(parent).map_err(fs_err)?, name),
+                None => (root, path),
+            };
+            parent.remove(name).map_err(fs_err)?;
+            sync()
+        }
+        ["fsck", image] => check(Path::new(image), false),
+        ["fsck", image, "--repair"] => check(Path::new(image), true),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// "drwxr-xr-x" as ls prints it
+fn mode_string(file_type: FileType, mode: u16) -> String {
+    let tp = match file_type {
+        FileType::Directory => 'd',
+        FileType::Symlink => 'l',
+        _ => '-',
+    };
+    let bits = (0..9).rev().map(|i| {
+        if mode & (1 << i) == 0 {
+            '-'
+        } else {
+            ['x', 'w', 'r'][i % 3]
+        }
+    });
+    core::iter::once(tp).chain(bits).collect()
+}
+
+fn fs_err(e: IOError) -> String {
+    format!("jfs error: {:?}", e)
+}
+
+fn sync() -> Result<(), String> {
+    sync_blocks().map_err(fs_err)
+}
+
+fn mkfs(
+    image: &Path,
+    total_blocks: u32,
+    inode_blocks: u32,
+    opts: MkfsOptions,
+) -> Result<(), String> {
+    let MkfsOptions {
+        block_size, label, ..
+    } = opts;
+    if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(format!(
+            "block size must be a power of two in [{}, {}]",
+            MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+        ));
+    }
+    if label.len() > LABEL_LEN {
+        return Err(format!("label is longer than {} bytes", LABEL_LEN));
+    }
+    // the super block and the journal come first
+    let reserved = JOURNAL_BLOCKS + 1;
+    if inode_blocks == 0 || inode_blocks + reserved >= total_blocks {
+        return Err(format!(
+            "inode_blocks must be in [1, total_blocks - {})",
+            reserved
+        ));
+    }
+    let sectors = total_blocks as usize * block_size / SECTOR_SIZE;
+    let dev = FileDevice::create(image, sectors).map_err(|e| e.to_string())?;
+    let dev: Arc<dyn BlkDev> = Arc::new(dev);
+    let opts = MkfsOptions {
+        uuid: random_uuid()?,
+        ..opts
+    };
+    JFS::mkfs_with(dev, total_blocks, inode_blocks, &opts).map_err(fs_err)?;
+    sync()
+}
+
+// a random (version 4) uuid
+fn random_uuid() -> Result<[u8; 16], String> {
+    use std::io::Read;
+    let mut uuid = [0; 16];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut uuid))
+        .map_err(|e| format!("/dev/urandom: {}", e))?;
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    Ok(uuid)
+}
+
+fn open(image: &Path) -> Result<Arc<JFS>, String> {
+    let dev = FileDevice::open(image).map_err(|e| format!("{}: {}", image.display(), e))?;
+    let dev: Arc<dyn BlkDev> = Arc::new(dev);
+    JFS::from_dev(dev).map(Arc::new).map_err(fs_err)
+}
+
+fn check(image: &Path, repair: bool) -> Result<(), String> {
+    let report = fsck(&open(image)?, repair).map_err(fs_err)?;
+    for p in report.problems.iter() {
+        println!("{}", p);
+    }
+    if report.is_clean() {
+        println!("clean");
+        return Ok(());
+    }
+    if report.repaired {
+        println!("repaired {} problems", report.problems.len());
+        return sync();
+    }
+    Err(format!("{} problems found", report.problems.len()))
+}
+
+// copy every elf file in dir into the root directory
+fn pack(root: &Inode, dir: &Path) -> Result<Vec<String>, String> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    entries.sort();
+    let mut packed = Vec::new();
+    for path in entries {
+        let data = fs::read(&path).map_err(|e| e.to_string())?;
+        if !data.starts_with(&ELF_MAGIC) {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let inode = match root.find(&name).map_err(fs_err)? {
+            Some(inode) => {
+                inode.resize(0).map_err(fs_err)?;
+                inode
+            }
+            None => root.create(&name, FileType::File).map_err(fs_err)?,
+        };
+        inode.write_at(0, &data).map_err(fs_err)?;
+        inode.chmod(APP_MODE).map_err(fs_err)?;
+        packed.push(name);
+    }
+    Ok(packed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use jfs::MkfsOptions;
+
+    use crate::{mkfs, mode_string, open, pack, run, sync, unix_now};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jfs-tools-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_pack() {
+        jfs::set_clock(unix_now);
+        let dir = temp_dir("pack");
+        let bins = dir.join("bins");
+        fs::create_dir(&bins).unwrap();
+        let mut app = b"\x7fELF".to_vec();
+        app.extend((0..5000).map(|i| i as u8));
+        fs::write(bins.join("app"), &app).unwrap();
+        fs::write(bins.join("app.d"), "not an elf").unwrap();
+        let image = dir.join("fs.img");
+        let opts = MkfsOptions {
+            block_size: 4096,
+            label: "apps",
+            extents: true,
+            bitmaps: true,
+            ..Default::default()
+        };
+        mkfs(&image, 512, 2, opts).unwrap();
+        {
+            let root = open(&image).unwrap().root_dir();
+            assert_eq!(vec!["app".to_string()], pack(&root, &bins).unwrap());
+            // packing twice replaces the old content
+            assert_eq!(vec!["app".to_string()], pack(&root, &bins).unwrap());
+            sync().unwrap();
+        }
+        let root = open(&image).unwrap().root_dir();
+        assert_eq!(vec!["app".to_string()], root.ls().unwrap());
+        assert_eq!(app, root.lookup("app").unwrap().read_all().unwrap());
+        let st = root.lookup("app").unwrap().stat().unwrap();
+        assert_eq!("-rwxr-xr-x", mode_string(st.file_type, st.mode));
+        assert!(st.mtime > 0);
+        let image = image.to_string_lossy().to_string();
+        run(&["rm".to_string(), image.clone(), "app".to_string()]).unwrap();
+        assert!(open(image.as_ref())
+            .unwrap()
+            .root_dir()
+            .ls()
+            .unwrap()
+            .is_empty());
+        run(&["fsck".to_string(), image.clone()]).unwrap();
+        run(&["info".to_string(), image.clone()]).unwrap();
+        let info = open(image.as_ref()).unwrap().info().unwrap();
+        assert_eq!(4096, info.block_size);
+        assert_eq!("apps", info.label);
+        let fs = open(image.as_ref()).unwrap();
+        assert!(fs.uses_extents() && fs.uses_bitmaps());
+        let st = fs.statfs().unwrap();
+        assert_eq!(st.inodes - 1, st.free_inodes);
+        drop(fs);
+        assert!(run(&[
+            "mkfs".to_string(),
+            image.clone(),
+            "64".to_string(),
+            "1".to_string(),
+            "--block-size".to_string(),
+            "3000".to_string()
+        ])
+        .is_err());
+        assert!(run(&["bad".to_string()]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::types::*;
+use crate::jfs::JFS;
+
+// a run of bitmap blocks, a set bit marks a used inode or data block
+pub(crate) struct Bitmap {
+    start_block: u32,
+    bits: u32,
+    // next-fit: searches start where the last allocation ended
+    next: Mutex<u32>,
+}
+
+// the bitmap layout, an alternative to the inode and block free lists
+pub(crate) struct Bitmaps {
+    pub inodes: Bitmap,
+    pub blocks: Bitmap,
+}
+
+// bitmap blocks needed to track bits items
+pub(crate) fn bitmap_blocks(bits: u32, block_size: usize) -> u32 {
+    bits.div_ceil(block_size as u32 * 8)
+}
+
+impl Bitmap {
+    pub fn new(start_block: u32, bits: u32) -> Self {
+        Self {
+            start_block,
+            bits,
+            next: Mutex::new(0),
+        }
+    }
+
+    fn bits_per_block(fs: &JFS) -> u32 {
+        fs.block_size as u32 * 8
+    }
+
+    // mark every bit used, bits past the end stay that way for good
+    pub fn fill(&self, fs: &JFS) -> IOResult<()> {
+        for i in 0..bitmap_blocks(self.bits, fs.block_size) {
+            fs.get_block(self.start_block + i)?
+                .lock()
+                .write_slice(|bytes: &mut [u8]| bytes.fill(0xff));
+        }
+        *self.next.lock() = 0;
+        Ok(())
+    }
+
+    pub fn is_used(&self, fs: &JFS, i: u32) -> IOResult<bool> {
+        let bpb = Self::bits_per_block(fs);
+        let off = (i % bpb) as usize;
+        let blk = fs.get_block(self.start_block + i / bpb)?;
+        let rt = blk
+            .lock()
+            .read_slice(|bytes: &[u8]| bytes[off / 8] & (1 << (off % 8)) != 0);
+        Ok(rt)
+    }
+
+    // mark bit i used or free, returns whether it was used before
+    fn set(&self, fs: &JFS, i: u32, used: bool) -> IOResult<bool> {
+        let bpb = Self::bits_per_block(fs);
+        let off = (i % bpb) as usize;
+        let blk = fs.get_block(self.start_block + i / bpb)?;
+        let rt = blk.lock().write_slice(|bytes: &mut [u8]| {
+            let mask = 1 << (off % 8);
+            let was = bytes[off / 8] & mask != 0;
+            match used {
+                true => bytes[off / 8] |= mask,
+                false => bytes[off / 8] &= !mask,
+            }
+            was
+        });
+        Ok(rt)
+    }
+
+    // the first run of n free bits in [from, to)
+    fn find(&self, fs: &JFS, from: u32, to: u32, n: u32) -> IOResult<Option<u32>> {
+        let bpb = Self::bits_per_block(fs);
+        let (mut i, mut start, mut len) = (from, from, 0);
+        while i < to {
+            let end = to.min((i / bpb + 1) * bpb);
+            let blk = fs.get_block(self.start_block + i / bpb)?;
+            let found = blk.lock().read_slice(|bytes: &[u8]| {
+                while i < end {
+                    let off = (i % bpb) as usize;
+                    // whole used bytes are skipped at once
+                    if off.is_multiple_of(8) && i + 8 <= end && bytes[off / 8] == 0xff {
+                        i += 8;
+                        start = i;
+                        len = 0;
+                        continue;
+                    }
+                    i += 1;
+                    if bytes[off / 8] & (1 << (off % 8)) != 0 {
+                        start = i;
+                        len = 0;
+                    } else {
+                        len += 1;
+                        if len == n {
+                            return true;
+                        }
+                    }
+                }
+                false
+            });
+            if found {
+                return Ok(Some(start));
+            }
+        }
+        Ok(None)
+    }
+
+    // the first free bit from the cursor on, wrapping around once
+    fn next_free(&self, fs: &JFS, cursor: u32) -> IOResult<Option<u32>> {
+        match self.find(fs, cursor, self.bits, 1)? {
+            Some(i) => Ok(Some(i)),
+            None => self.find(fs, 0, cursor.min(self.bits), 1),
+        }
+    }
+
+    // take n bits, as one run from the cursor on when there is such a run,
+    // otherwise bit by bit, none are taken when there are fewer than n free
+    pub fn alloc(&self, fs: &JFS, n: u32) -> IOResult<Vec<u32>> {
+        let mut next = self.next.lock();
+        let cursor = (*next).min(self.bits);
+        let run = match self.find(fs, cursor, self.bits, n)? {
+            Some(i) => Some(i),
+            None => self.find(fs, 0, self.bits, n)?,
+        };
+        let mut rt = Vec::with_capacity(n as usize);
+        if let Some(start) = run {
+            for i in start..start + n {
+                self.set(fs, i, true)?;
+                rt.push(i);
+            }
+        }
+        let mut at = cursor;
+        while rt.len() < n as usize {
+            let Some(i) = self.next_free(fs, at)? else {
+                for &i in rt.iter() {
+                    self.set(fs, i, false)?;
+                }
+                return Err(IOError::DiskFull);
+            };
+            // taken right away, so the next search skips it
+            self.set(fs, i, true)?;
+            rt.push(i);
+            at = i + 1;
+        }
+        *next = rt.last().map_or(cursor, |i| i + 1);
+        Ok(rt)
+    }
+
+    // free bit i, a bit that is already free means the fs is corrupted
+    pub fn free(&self, fs: &JFS, i: u32) -> IOResult<()> {
+        if i >= self.bits {
+            return Err(IOError::NoSuchBlock);
+        }
+        match self.set(fs, i, false)? {
+            true => Ok(()),
+            false => Err(IOError::CorruptedFS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use crate::fsck::{fsck, Problem};
+    use crate::jfs::test::new_device;
+    use crate::jfs::{FileType, MkfsOptions, SuperBlock, JFS};
+    use crate::journal::JOURNAL_BLOCKS;
+    use crate::types::*;
+
+    fn bitmap_fs(
+        total: u32,
+        inode_blocks: u32,
+    ) -> (Box<crate::device::test::MemoryBlockInner>, Arc<JFS>) {
+        let (dev, inner) = new_device(total as usize);
+        let opts = MkfsOptions {
+            bitmaps: true,
+            ..Default::default()
+        };
+        let fs = JFS::mkfs_with(dev, total, inode_blocks, &opts).unwrap();
+        (inner, Arc::new(fs))
+    }
+
+    #[test]
+    fn test_layout() {
+        let (dev, _inner) = new_device(1024);
+        let opts = MkfsOptions {
+            bitmaps: true,
+            ..Default::default()
+        };
+        drop(JFS::mkfs_with(Arc::clone(&dev), 1024, 4, &opts).unwrap());
+        let fs = JFS::from_dev(dev).unwrap();
+        assert!(fs.uses_bitmaps());
+        let info = fs.info().unwrap();
+        assert_eq!((1, 1), (info.inode_bitmap_blocks, info.block_bitmap_blocks));
+        // the bitmaps sit between the journal and the inode blocks
+        assert_eq!(JOURNAL_BLOCKS + 3, fs.inode_start_block);
+        let st = fs.statfs().unwrap();
+        assert_eq!(1024 - 1 - JOURNAL_BLOCKS - 2 - 4, st.blocks);
+        assert_eq!(st.blocks, st.free_blocks);
+        assert_eq!(16, st.inodes);
+        // the root is in use
+        assert_eq!(15, st.free_inodes);
+    }
+
+    #[test]
+    fn test_next_fit() {
+        let (_inner, fs) = bitmap_fs(1024, 1);
+        let start = fs.data_start_block;
+        let a = fs.alloc_blocks(5).unwrap();
+        assert_eq!((start..start + 5).collect::<Vec<_>>(), a);
+        let b = fs.alloc_blocks(3).unwrap();
+        assert_eq!((start + 5..start + 8).collect::<Vec<_>>(), b);
+        for &x in a.iter() {
+            fs.dealloc_block(x).unwrap();
+        }
+        // the search goes on from the last allocation, not from the start
+        assert_eq!(vec![start + 8], fs.alloc_blocks(1).unwrap());
+        let free = fs.statfs().unwrap().free_blocks;
+        let rest = fs.alloc_blocks(free as usize - 5).unwrap();
+        assert_eq!(start + 9, rest[0]);
+        // only the freed run at the start is left, the search wraps around to it
+        assert_eq!(a[..4], fs.alloc_blocks(4).unwrap()[..]);
+        assert!(matches!(fs.alloc_blocks(2), Err(IOError::DiskFull)));
+        assert_eq!(1, fs.statfs().unwrap().free_blocks);
+        assert_eq!(vec![a[4]], fs.alloc_blocks(1).unwrap());
+        assert!(matches!(fs.alloc_block(), Err(IOError::DiskFull)));
+
+        // no run is long enough, so the blocks are picked one by one
+        for &x in rest.iter().step_by(2).take(3) {
+            fs.dealloc_block(x).unwrap();
+        }
+        let picked = fs.alloc_blocks(3).unwrap();
+        assert_eq!(
+            rest.iter().step_by(2).take(3).copied().collect::<Vec<_>>(),
+            picked
+        );
+        fs.dealloc_block(start + 8).unwrap();
+        assert!(matches!(
+            fs.dealloc_block(start + 8),
+            Err(IOError::CorruptedFS)
+        ));
+    }
+
+    #[test]
+    fn test_files() {
+        let (_inner, fs) = bitmap_fs(1024, 4);
+        let before = fs.statfs().unwrap();
+        let root = fs.root_dir();
+        let data: Vec<u8> = (0..SECTOR_SIZE * 30).map(|i| i as u8).collect();
+        for name in ["a", "b", "c"] {
+            root.create(name, FileType::File)
+                .unwrap()
+                .write_at(0, &data)
+                .unwrap();
+        }
+        let st = fs.statfs().unwrap();
+        assert_eq!(before.free_inodes - 3, st.free_inodes);
+        // the root directory took one block too
+        assert_eq!(before.free_blocks - 3 * 31 - 1, st.free_blocks);
+        assert!(fsck(&fs, false).unwrap().is_clean());
+        root.remove("b").unwrap();
+        let d = root.create("d", FileType::File).unwrap();
+        d.write_at(0, &data).unwrap();
+        assert_eq!(data, d.read_all().unwrap());
+        for name in ["a", "c", "d"] {
+            root.remove(name).unwrap();
+        }
+        drop(d);
+        let st = fs.statfs().unwrap();
+        assert_eq!(before.free_inodes, st.free_inodes);
+        // the emptied root gave its block back too
+        assert_eq!(before.free_blocks, st.free_blocks);
+        assert!(fsck(&fs, false).unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_fsck() {
+        let (_inner, fs) = bitmap_fs(1024, 4);
+        let root = fs.root_dir();
+        let f = root.create("f", FileType::File).unwrap();
+        f.write_at(0, &vec![1; SECTOR_SIZE * 4]).unwrap();
+        let bm = fs.bitmaps.as_ref().unwrap();
+        let owned = fs
+            .read_disk_inode(f.id(), |d| d.data_block(0, &fs))
+            .unwrap()
+            .unwrap();
+        // an owned block and a live inode marked free, a free block marked used
+        bm.blocks
+            .set(&fs, owned - fs.data_start_block, false)
+            .unwrap();
+        bm.inodes.set(&fs, f.id(), false).unwrap();
+        let leaked = fs.data_end_block - 1;
+        bm.blocks
+            .set(&fs, leaked - fs.data_start_block, true)
+            .unwrap();
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert!(problems.contains(&Problem::DoubleOwnedBlock(owned)));
+        assert!(problems.contains(&Problem::BadFreeInode(f.id())));
+        assert!(problems.contains(&Problem::LeakedBlock(leaked)));
+        let report = fsck(&fs, true).unwrap();
+        assert!(report.repaired);
+        assert!(fsck(&fs, false).unwrap().is_clean());
+        assert_eq!(vec![1; SECTOR_SIZE * 4], f.read_all().unwrap());
+
+        // a count that drifted is put right as well
+        let st = fs.statfs().unwrap();
+        fs.get_block(0)
+            .unwrap()
+            .lock()
+            .write(0, |sb: &mut SuperBlock| sb.free_blocks += 2);
+        assert_eq!(
+            vec![Problem::BadFreeCount {
+                recorded: st.free_blocks + 2,
+                actual: st.free_blocks,
+            }],
+            fsck(&fs, false).unwrap().problems
+        );
+        fsck(&fs, true).unwrap();
+        assert_eq!(st.free_blocks, fs.statfs().unwrap().free_blocks);
+    }
+}
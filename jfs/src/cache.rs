@@ -1,5 +1,5 @@
-use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
-use spin::Mutex;
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use spin::{Mutex, MutexGuard};
 
 use lazy_static::lazy_static;
 
@@ -10,45 +10,99 @@ use super::types::*;
 
 pub struct BlockCache {
     blk_id: usize,
-    buf: [u8; BlockSize],
+    // u64 backed, so any block content can be viewed as u32 or DiskInode
+    buf: Vec<u64>,
     dirty: bool,
+    // write every change straight to the device, unless the block is pinned
+    write_through: bool,
+    pinned: bool,
     dev: Arc<dyn BlkDev>,
 }
 
 impl BlockCache {
-    pub fn new(blk_id: usize, dev: Arc<dyn BlkDev>) -> Result<Self, IOError> {
-        let mut buf = [0u8; BlockSize];
-        dev.read(blk_id, &mut buf)?;
-        Ok(Self {
+    pub fn new(blk_id: usize, block_size: usize, dev: Arc<dyn BlkDev>) -> Result<Self, IOError> {
+        let mut blk = Self::unread(blk_id, block_size, Arc::clone(&dev));
+        dev.read_blocks(blk.sector(), blk.bytes_mut())?;
+        Ok(blk)
+    }
+
+    // the caller fills buf from the device
+    fn unread(blk_id: usize, block_size: usize, dev: Arc<dyn BlkDev>) -> Self {
+        Self {
             blk_id,
             dev,
-            buf: buf,
+            buf: vec![0; block_size / size_of::<u64>()],
             dirty: false,
-        })
+            write_through: false,
+            pinned: false,
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        self.buf.len() * size_of::<u64>()
+    }
+
+    fn sector(&self) -> usize {
+        self.blk_id * self.block_size() / SECTOR_SIZE
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.buf.as_ptr() as *const u8, self.block_size()) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        let len = self.block_size();
+        unsafe { core::slice::from_raw_parts_mut(self.buf.as_mut_ptr() as *mut u8, len) }
     }
 
     pub fn write_back(&mut self) -> Result<(), IOError> {
         if self.dirty {
-            self.dev.write(self.blk_id, &self.buf)?;
+            self.dev.write_blocks(self.sector(), self.bytes())?;
             self.dirty = false;
         }
         Ok(())
     }
 
-    pub fn read<T>(&self, offset: usize, Fn: impl FnOnce(&T)) {
-        assert!(offset + size_of::<T>() <= BlockSize);
-        let ptr = &self.buf[offset] as *const u8 as *const T;
-        unsafe { Fn(&*ptr) }
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn read<T, V>(&self, offset: usize, func: impl FnOnce(&T) -> V) -> V {
+        assert!(offset + size_of::<T>() <= self.block_size());
+        let ptr = &self.bytes()[offset] as *const u8 as *const T;
+        unsafe { func(&*ptr) }
+    }
+
+    pub fn write<T, V>(&mut self, offset: usize, func: impl FnOnce(&mut T) -> V) -> V {
+        assert!(offset + size_of::<T>() <= self.block_size());
+        let ptr = &mut self.bytes_mut()[offset] as *mut u8 as *mut T;
+        self.dirty = true;
+        let rt = unsafe { func(&mut *ptr) };
+        self.written();
+        rt
     }
 
-    pub fn write<T>(&mut self, offset: usize, func: impl FnOnce(&mut T)) {
-        func(self.ref_mut(offset));
+    // the whole block as a slice of T, u8 for data and u32 for block tables
+    pub fn read_slice<T, V>(&self, func: impl FnOnce(&[T]) -> V) -> V {
+        let len = self.block_size() / size_of::<T>();
+        func(unsafe { core::slice::from_raw_parts(self.buf.as_ptr() as *const T, len) })
     }
-    pub fn ref_mut<T>(&mut self, offset: usize) -> &mut T {
-        assert!(offset + size_of::<T>() <= BlockSize);
-        let ptr = &mut self.buf[offset] as *mut u8 as *mut T;
+
+    pub fn write_slice<T, V>(&mut self, func: impl FnOnce(&mut [T]) -> V) -> V {
+        let len = self.block_size() / size_of::<T>();
         self.dirty = true;
-        unsafe { &mut *ptr }
+        let rt =
+            func(unsafe { core::slice::from_raw_parts_mut(self.buf.as_mut_ptr() as *mut T, len) });
+        self.written();
+        rt
+    }
+
+    fn written(&mut self) {
+        if self.write_through && !self.pinned {
+            if let Err(_e) = self.write_back() {
+                error!("write through block failed: blk_id={}", self.blk_id);
+            }
+        }
     }
 }
 
@@ -60,71 +114,439 @@ impl Drop for BlockCache {
     }
 }
 
-struct CacheManager {
-    index: BTreeMap<usize, Arc<Mutex<BlockCache>>>,
-    lru: Vec<usize>,
-    limit: usize,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    // every write reaches the device before returning
+    WriteThrough,
+    // dirty blocks are written on eviction or sync
+    WriteBack,
+    // like WriteBack, and flush_expired syncs the device every interval
+    Periodic { interval_ms: u64 },
 }
 
-impl CacheManager {
-    fn new(limit: usize) -> Self {
-        let mut lru = Vec::new();
-        lru.reserve(limit);
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    // blocks kept per device, pinned or busy blocks may exceed it
+    pub capacity: usize,
+    pub policy: FlushPolicy,
+    // blocks read in one request when misses are sequential, at most half the capacity
+    pub readahead: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 32,
+            policy: FlushPolicy::WriteBack,
+            readahead: 8,
+        }
+    }
+}
+
+const NIL: usize = usize::MAX;
+// dirty blocks written back in one request
+const MAX_MERGE: usize = 32;
+
+// a slot of the per-device slab, prev and next link it into the lru list
+struct Entry {
+    blk_id: usize,
+    blk: Arc<Mutex<BlockCache>>,
+    prev: usize,
+    next: usize,
+    pinned: bool,
+}
+
+// blocks of one device: the most recently used is at head,
+// pinned entries are kept out of the list so they are never evicted
+struct DeviceCache {
+    dev: Arc<dyn BlkDev>,
+    block_size: usize,
+    config: CacheConfig,
+    last_flush: u64,
+    last_miss: usize,
+    index: BTreeMap<usize, usize>,
+    slots: Vec<Option<Entry>>,
+    free_slots: Vec<usize>,
+    head: usize,
+    tail: usize,
+}
+
+impl DeviceCache {
+    fn new(dev: Arc<dyn BlkDev>, block_size: usize, config: CacheConfig) -> Self {
         Self {
+            dev,
+            block_size,
+            config,
+            last_flush: 0,
+            last_miss: NIL,
             index: BTreeMap::new(),
-            lru: lru,
-            limit,
-        }
-    }
-    fn get_block(
-        &mut self,
-        blk_id: usize,
-        dev: Arc<dyn BlkDev>,
-    ) -> Result<Arc<Mutex<BlockCache>>, IOError> {
-        match self.index.get(&blk_id).map(|blk| Arc::clone(blk)) {
-            Some(blk) => {
-                self.update_access(blk_id);
-                Ok(blk)
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    fn entry(&mut self, slot: usize) -> &mut Entry {
+        self.slots[slot].as_mut().unwrap()
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = {
+            let e = self.entry(slot);
+            (e.prev, e.next)
+        };
+        match prev {
+            NIL => self.head = next,
+            p => self.entry(p).next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            n => self.entry(n).prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        let head = self.head;
+        let e = self.entry(slot);
+        e.prev = NIL;
+        e.next = head;
+        match head {
+            NIL => self.tail = slot,
+            h => self.entry(h).prev = slot,
+        }
+        self.head = slot;
+    }
+
+    fn touch(&mut self, slot: usize) {
+        if !self.entry(slot).pinned {
+            self.unlink(slot);
+            self.push_front(slot);
+        }
+    }
+
+    fn remove(&mut self, slot: usize) -> Arc<Mutex<BlockCache>> {
+        if !self.entry(slot).pinned {
+            self.unlink(slot);
+        }
+        let e = self.slots[slot].take().unwrap();
+        self.index.remove(&e.blk_id);
+        self.free_slots.push(slot);
+        e.blk
+    }
+
+    // evict the least recently used block nobody else holds,
+    // the cache grows past its capacity when there is none
+    fn evict(&mut self) {
+        let mut slot = self.tail;
+        while slot != NIL {
+            let e = self.entry(slot);
+            if Arc::strong_count(&e.blk) == 1 {
+                drop(self.remove(slot));
+                return;
             }
-            None => {
-                if self.index.len() >= self.limit {
-                    self.expire_oldest();
-                }
-                let blk = Arc::new(Mutex::new(BlockCache::new(blk_id, dev)?));
-                self.index.insert(blk_id, Arc::clone(&blk));
-                self.lru.push(blk_id);
-                Ok(blk)
+            slot = e.prev;
+        }
+    }
+
+    fn get_block(&mut self, blk_id: usize) -> IOResult<Arc<Mutex<BlockCache>>> {
+        if let Some(&slot) = self.index.get(&blk_id) {
+            self.touch(slot);
+            return Ok(Arc::clone(&self.entry(slot).blk));
+        }
+        let sequential = self.last_miss.wrapping_add(1) == blk_id;
+        self.last_miss = blk_id;
+        let window = self.config.readahead.min(self.config.capacity / 2);
+        if sequential && window > 1 {
+            if let Some(blk) = self.read_ahead(blk_id, window) {
+                return Ok(blk);
             }
         }
+        let cache = BlockCache::new(blk_id, self.block_size, Arc::clone(&self.dev))?;
+        Ok(self.insert(cache))
     }
-    fn update_access(&mut self, blk_id: usize) {
-        if let Some(pos) = self.lru.iter().position(|&x| x == blk_id) {
-            self.lru.remove(pos);
+
+    // read blk_id and the uncached blocks after it in one request,
+    // None when the device refuses, e.g. past its end
+    fn read_ahead(&mut self, blk_id: usize, window: usize) -> Option<Arc<Mutex<BlockCache>>> {
+        let cnt = (blk_id..blk_id + window)
+            .take_while(|b| !self.index.contains_key(b))
+            .count();
+        let mut caches: Vec<BlockCache> = (blk_id..blk_id + cnt)
+            .map(|b| BlockCache::unread(b, self.block_size, Arc::clone(&self.dev)))
+            .collect();
+        let sector = caches[0].sector();
+        let mut bufs: Vec<&mut [u8]> = caches.iter_mut().map(|c| c.bytes_mut()).collect();
+        self.dev.read_blocks_vectored(sector, &mut bufs).ok()?;
+        // a miss right after the window keeps reading ahead
+        self.last_miss = blk_id + cnt - 1;
+        let mut caches = caches.into_iter();
+        let first = caches.next()?;
+        // the requested block ends up most recently used
+        for cache in caches.rev() {
+            self.insert(cache);
         }
-        self.lru.push(blk_id);
+        Some(self.insert(first))
     }
-    fn expire_oldest(&mut self) {
-        let blk = self.lru.remove(0);
-        let blk = self.index.remove(&blk).unwrap();
-        drop(blk);
+
+    fn insert(&mut self, mut cache: BlockCache) -> Arc<Mutex<BlockCache>> {
+        if self.index.len() >= self.config.capacity {
+            self.evict();
+        }
+        let blk_id = cache.blk_id;
+        cache.write_through = self.config.policy == FlushPolicy::WriteThrough;
+        let blk = Arc::new(Mutex::new(cache));
+        let entry = Entry {
+            blk_id,
+            blk: Arc::clone(&blk),
+            prev: NIL,
+            next: NIL,
+            pinned: false,
+        };
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(entry);
+                slot
+            }
+            None => {
+                self.slots.push(Some(entry));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(blk_id, slot);
+        self.push_front(slot);
+        blk
     }
-    fn sync(&mut self) -> IOResult<()> {
-        for (_i, blk) in self.index.iter_mut() {
-            blk.lock().write_back()?;
+
+    fn set_pinned(&mut self, blk_id: usize, pinned: bool) -> Option<Arc<Mutex<BlockCache>>> {
+        let slot = *self.index.get(&blk_id)?;
+        if self.entry(slot).pinned != pinned {
+            if pinned {
+                self.unlink(slot);
+                self.entry(slot).pinned = true;
+            } else {
+                self.entry(slot).pinned = false;
+                self.push_front(slot);
+            }
         }
-        Ok(())
+        Some(Arc::clone(&self.entry(slot).blk))
+    }
+
+    // unpinned blocks by id, the caller writes them back without holding the manager lock
+    fn unpinned(&self) -> (Arc<dyn BlkDev>, Vec<Arc<Mutex<BlockCache>>>) {
+        let blocks = self
+            .index
+            .values()
+            .map(|&slot| self.slots[slot].as_ref().unwrap())
+            .filter(|e| !e.pinned)
+            .map(|e| Arc::clone(&e.blk))
+            .collect();
+        (Arc::clone(&self.dev), blocks)
+    }
+}
+
+struct CacheManager {
+    devices: BTreeMap<usize, DeviceCache>,
+}
+
+// devices are told apart by address, the cache keeps them alive until removed
+fn dev_key(dev: &Arc<dyn BlkDev>) -> usize {
+    Arc::as_ptr(dev) as *const () as usize
+}
+
+impl CacheManager {
+    // a device nobody opened is cached in sectors
+    fn device(&mut self, dev: &Arc<dyn BlkDev>) -> &mut DeviceCache {
+        self.devices.entry(dev_key(dev)).or_insert_with(|| {
+            DeviceCache::new(Arc::clone(dev), SECTOR_SIZE, CacheConfig::default())
+        })
     }
 }
 
 lazy_static! {
-    static ref CACHE_MGR: Mutex<CacheManager> = { Mutex::new(CacheManager::new(32)) };
+    static ref CACHE_MGR: Mutex<CacheManager> = Mutex::new(CacheManager {
+        devices: BTreeMap::new(),
+    });
+}
+
+fn write_run(dev: &Arc<dyn BlkDev>, run: &mut Vec<MutexGuard<BlockCache>>) -> IOResult<()> {
+    if let Some(first) = run.first() {
+        let bufs: Vec<&[u8]> = run.iter().map(|blk| blk.bytes()).collect();
+        dev.write_blocks_vectored(first.sector(), &bufs)?;
+        for blk in run.iter_mut() {
+            blk.dirty = false;
+        }
+    }
+    run.clear();
+    Ok(())
+}
+
+// write back the dirty blocks of dev sorted by id, adjacent ones in a single request;
+// busy blocks are waited for alone so that no lock is held meanwhile
+fn write_back_all((dev, blocks): (Arc<dyn BlkDev>, Vec<Arc<Mutex<BlockCache>>>)) -> IOResult<()> {
+    let mut run: Vec<MutexGuard<BlockCache>> = Vec::new();
+    for blk in blocks.iter() {
+        let Some(guard) = blk.try_lock() else {
+            write_run(&dev, &mut run)?;
+            blk.lock().write_back()?;
+            continue;
+        };
+        let adjacent = run
+            .last()
+            .is_none_or(|last| last.blk_id + 1 == guard.blk_id);
+        if !guard.dirty || !adjacent || run.len() == MAX_MERGE {
+            write_run(&dev, &mut run)?;
+        }
+        if guard.dirty {
+            run.push(guard);
+        }
+    }
+    write_run(&dev, &mut run)
+}
+
+// cache dev in blocks of block_size with config from now on,
+// blocks cached with another size are written back and dropped first
+pub fn open_device(dev: &Arc<dyn BlkDev>, block_size: usize, config: CacheConfig) -> IOResult<()> {
+    let resized = CACHE_MGR.lock().device(dev).block_size != block_size;
+    if resized {
+        remove_device(dev)?;
+    }
+    let mut mgr = CACHE_MGR.lock();
+    let cache = mgr.device(dev);
+    cache.block_size = block_size;
+    cache.config = config;
+    let write_through = config.policy == FlushPolicy::WriteThrough;
+    for e in cache.slots.iter().flatten() {
+        e.blk.lock().write_through = write_through;
+    }
+    Ok(())
 }
 
 pub fn get_block(blk_id: usize, dev: Arc<dyn BlkDev>) -> IOResult<Arc<Mutex<BlockCache>>> {
-    CACHE_MGR.lock().get_block(blk_id, dev)
+    CACHE_MGR.lock().device(&dev).get_block(blk_id)
+}
+
+// fill buf with the blocks from blk_id on: cached ones are copied, each stretch
+// of uncached ones is read in a single request and left out of the cache,
+// so a large read does not push everything else out
+pub fn read_run(dev: &Arc<dyn BlkDev>, blk_id: usize, buf: &mut [u8]) -> IOResult<()> {
+    let (bs, cached) = {
+        let mut mgr = CACHE_MGR.lock();
+        let cache = mgr.device(dev);
+        let bs = cache.block_size;
+        let cached: Vec<_> = (blk_id..blk_id + buf.len() / bs)
+            .map(|b| {
+                let slot = *cache.index.get(&b)?;
+                Some(Arc::clone(&cache.entry(slot).blk))
+            })
+            .collect();
+        (bs, cached)
+    };
+    let mut i = 0;
+    while i < cached.len() {
+        if let Some(blk) = &cached[i] {
+            let dst = &mut buf[i * bs..(i + 1) * bs];
+            blk.lock()
+                .read_slice(|data: &[u8]| dst.copy_from_slice(data));
+            i += 1;
+            continue;
+        }
+        let end = (i..cached.len())
+            .find(|&j| cached[j].is_some())
+            .unwrap_or(cached.len());
+        dev.read_blocks((blk_id + i) * bs / SECTOR_SIZE, &mut buf[i * bs..end * bs])?;
+        i = end;
+    }
+    Ok(())
+}
+
+// pinned blocks are neither evicted nor written back until unpinned
+pub fn pin_block(blk_id: usize, dev: Arc<dyn BlkDev>) -> IOResult<Arc<Mutex<BlockCache>>> {
+    let blk = {
+        let mut mgr = CACHE_MGR.lock();
+        let cache = mgr.device(&dev);
+        cache.get_block(blk_id)?;
+        cache.set_pinned(blk_id, true).unwrap()
+    };
+    blk.lock().pinned = true;
+    Ok(blk)
+}
+
+pub fn unpin_block(blk_id: usize, dev: &Arc<dyn BlkDev>) {
+    let blk = CACHE_MGR.lock().device(dev).set_pinned(blk_id, false);
+    if let Some(blk) = blk {
+        blk.lock().pinned = false;
+    }
 }
+
+pub fn sync_device(dev: &Arc<dyn BlkDev>) -> IOResult<()> {
+    let blocks = match CACHE_MGR.lock().devices.get(&dev_key(dev)) {
+        Some(cache) => cache.unpinned(),
+        None => return Ok(()),
+    };
+    write_back_all(blocks)
+}
+
 pub fn sync_blocks() -> IOResult<()> {
-    CACHE_MGR.lock().sync()
+    let devices: Vec<_> = CACHE_MGR
+        .lock()
+        .devices
+        .values()
+        .map(|cache| cache.unpinned())
+        .collect();
+    devices.into_iter().try_for_each(write_back_all)
+}
+
+// sync the devices using FlushPolicy::Periodic whose interval has passed, call it from a timer
+pub fn flush_expired(now_ms: u64) -> IOResult<()> {
+    let mut devices = Vec::new();
+    for cache in CACHE_MGR.lock().devices.values_mut() {
+        if let FlushPolicy::Periodic { interval_ms } = cache.config.policy {
+            if now_ms >= cache.last_flush + interval_ms {
+                cache.last_flush = now_ms;
+                devices.push(cache.unpinned());
+            }
+        }
+    }
+    devices.into_iter().try_for_each(write_back_all)
+}
+
+// write back and forget every block of dev
+pub fn remove_device(dev: &Arc<dyn BlkDev>) -> IOResult<()> {
+    let cache = CACHE_MGR.lock().devices.remove(&dev_key(dev));
+    match cache {
+        Some(mut cache) => {
+            cache
+                .slots
+                .iter_mut()
+                .flatten()
+                .for_each(|e| e.pinned = false);
+            write_back_all(cache.unpinned())
+        }
+        None => Ok(()),
+    }
+}
+
+// forget blk_id of dev without writing it back, the next read sees the disk
+pub fn discard_block(blk_id: usize, dev: &Arc<dyn BlkDev>) {
+    let blk = {
+        let mut mgr = CACHE_MGR.lock();
+        let cache = mgr.device(dev);
+        let slot = cache.index.get(&blk_id).copied();
+        slot.map(|slot| cache.remove(slot))
+    };
+    if let Some(blk) = blk {
+        blk.lock().dirty = false;
+    }
+}
+
+// forget every block of dev without writing it back, like a power loss
+#[cfg(test)]
+pub fn discard_device(dev: &Arc<dyn BlkDev>) {
+    let cache = CACHE_MGR.lock().devices.remove(&dev_key(dev));
+    for e in cache.into_iter().flat_map(|c| c.slots).flatten() {
+        e.blk.lock().dirty = false;
+    }
 }
 
 #[cfg(test)]
@@ -132,23 +554,36 @@ mod test {
     use alloc::sync::Arc;
     use alloc::vec;
 
-    use super::CacheManager;
+    use super::{CacheConfig, DeviceCache, FlushPolicy};
     use crate::device::test::{MemoryBlock, MemoryBlockInner};
     use crate::device::BlkDev;
     use crate::types::*;
 
-    #[test]
-    fn test_cache() {
-        let mut blk_inner = MemoryBlockInner {
-            blocks: vec![[1u8; BlockSize]; 32],
+    fn device(cnt: usize) -> (Arc<dyn BlkDev>, alloc::boxed::Box<MemoryBlockInner>) {
+        let mut blk_inner = alloc::boxed::Box::new(MemoryBlockInner {
+            blocks: vec![[1u8; SECTOR_SIZE]; cnt],
             read_cnt: 0,
             write_cnt: 0,
-        };
+        });
         let dev: Arc<dyn BlkDev> = Arc::new(MemoryBlock {
-            inner: &raw mut blk_inner,
+            inner: &raw mut *blk_inner,
         });
-        let mut mgr = CacheManager::new(2);
-        let c = mgr.get_block(1, dev.clone()).unwrap();
+        (dev, blk_inner)
+    }
+
+    fn config(capacity: usize, policy: FlushPolicy) -> CacheConfig {
+        CacheConfig {
+            capacity,
+            policy,
+            readahead: 0,
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let (dev, blk_inner) = device(32);
+        let mut cache = DeviceCache::new(dev, SECTOR_SIZE, config(2, FlushPolicy::WriteBack));
+        let c = cache.get_block(1).unwrap();
         let mut l = c.lock();
         assert_eq!(1, l.blk_id);
         l.read(1, |c: &u8| {
@@ -158,12 +593,121 @@ mod test {
             *c += 1;
         });
         drop(l);
-        let _c = mgr.get_block(2, dev.clone()).unwrap();
-        let _c = mgr.get_block(3, dev.clone()).unwrap();
-        let _c = mgr.get_block(4, dev.clone()).unwrap();
-        assert_eq!(4, blk_inner.read_cnt);
-        assert_eq!(0, blk_inner.write_cnt);
         drop(c);
+        let _c = cache.get_block(2).unwrap();
+        let _c = cache.get_block(3).unwrap();
+        let _c = cache.get_block(4).unwrap();
+        assert_eq!(4, blk_inner.read_cnt);
         assert_eq!(1, blk_inner.write_cnt);
+        assert_eq!(2, blk_inner.blocks[1][1]);
+    }
+
+    #[test]
+    fn test_lru() {
+        let (dev, blk_inner) = device(32);
+        let mut cache = DeviceCache::new(dev, SECTOR_SIZE, config(3, FlushPolicy::WriteBack));
+        for b in [1, 2, 3, 1, 4] {
+            cache.get_block(b).unwrap();
+        }
+        // 2 was the least recently used
+        assert!(!cache.index.contains_key(&2));
+        assert_eq!(4, blk_inner.read_cnt);
+
+        // held blocks are skipped, then the cache grows
+        let held: alloc::vec::Vec<_> = [1, 3, 4]
+            .iter()
+            .map(|&b| cache.get_block(b).unwrap())
+            .collect();
+        cache.get_block(5).unwrap();
+        assert_eq!(4, cache.index.len());
+        drop(held);
+
+        // pinned blocks survive eviction and are not synced
+        let p = cache.get_block(6).unwrap();
+        cache.set_pinned(6, true);
+        p.lock().pinned = true;
+        p.lock().write(0, |c: &mut u8| *c = 7);
+        drop(p);
+        for b in 7..16 {
+            cache.get_block(b).unwrap();
+        }
+        assert!(cache.index.contains_key(&6));
+        assert_eq!(4, cache.index.len());
+        super::write_back_all(cache.unpinned()).unwrap();
+        assert_eq!(1, blk_inner.blocks[6][0]);
+        cache.set_pinned(6, false);
+        super::write_back_all(cache.unpinned()).unwrap();
+        assert_eq!(7, blk_inner.blocks[6][0]);
+    }
+
+    #[test]
+    fn test_readahead_and_merge() {
+        let (dev, blk_inner) = device(32);
+        let mut cfg = config(16, FlushPolicy::WriteBack);
+        cfg.readahead = 4;
+        let mut cache = DeviceCache::new(dev, SECTOR_SIZE, cfg);
+        // the second sequential miss reads 4 blocks at once
+        for b in 1..8 {
+            cache.get_block(b).unwrap();
+        }
+        assert_eq!(3, blk_inner.read_cnt);
+        assert!(cache.index.contains_key(&9));
+        // stops at the end of the device
+        cache.get_block(30).unwrap();
+        cache.get_block(31).unwrap();
+        assert!(!cache.index.contains_key(&32));
+
+        for b in [1, 2, 3, 5, 6, 31] {
+            cache
+                .get_block(b)
+                .unwrap()
+                .lock()
+                .write(0, |c: &mut u8| *c = 9);
+        }
+        let writes = blk_inner.write_cnt;
+        super::write_back_all(cache.unpinned()).unwrap();
+        // 1..=3, 5..=6 and 31
+        assert_eq!(writes + 3, blk_inner.write_cnt);
+        for b in [1, 2, 3, 5, 6, 31] {
+            assert_eq!(9, blk_inner.blocks[b][0]);
+        }
+        assert_eq!(1, blk_inner.blocks[4][0]);
+    }
+
+    #[test]
+    fn test_devices() {
+        let (dev1, inner1) = device(4);
+        let (dev2, inner2) = device(4);
+        super::open_device(&dev2, SECTOR_SIZE, config(2, FlushPolicy::WriteThrough)).unwrap();
+        super::get_block(1, Arc::clone(&dev1))
+            .unwrap()
+            .lock()
+            .write(0, |c: &mut u8| *c = 5);
+        super::get_block(1, Arc::clone(&dev2))
+            .unwrap()
+            .lock()
+            .write(0, |c: &mut u8| *c = 6);
+        // the same block id on two devices is cached apart
+        super::get_block(1, Arc::clone(&dev1))
+            .unwrap()
+            .lock()
+            .read(0, |c: &u8| assert_eq!(5, *c));
+        assert_eq!(1, inner1.blocks[1][0]);
+        assert_eq!(6, inner2.blocks[1][0]);
+        super::sync_device(&dev1).unwrap();
+        assert_eq!(5, inner1.blocks[1][0]);
+
+        let periodic = FlushPolicy::Periodic { interval_ms: 100 };
+        super::open_device(&dev1, SECTOR_SIZE, config(2, periodic)).unwrap();
+        super::get_block(2, Arc::clone(&dev1))
+            .unwrap()
+            .lock()
+            .write(0, |c: &mut u8| *c = 8);
+        super::flush_expired(50).unwrap();
+        assert_eq!(1, inner1.blocks[2][0]);
+        super::flush_expired(100).unwrap();
+        assert_eq!(8, inner1.blocks[2][0]);
+        super::remove_device(&dev1).unwrap();
+        super::remove_device(&dev2).unwrap();
     }
 }
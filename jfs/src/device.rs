@@ -2,16 +2,70 @@ use super::types::*;
 pub trait BlkDev: Sync + Send {
     fn read(&self, blk: usize, buf: &mut [u8]) -> IOResult<()>;
     fn write(&self, blk: usize, buf: &[u8]) -> IOResult<()>;
+
+    // read buf.len() / SECTOR_SIZE contiguous blocks starting at blk
+    fn read_blocks(&self, blk: usize, buf: &mut [u8]) -> IOResult<()> {
+        if !buf.len().is_multiple_of(SECTOR_SIZE) {
+            return Err(IOError::BadBufSize);
+        }
+        for (i, b) in buf.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            self.read(blk + i, b)?;
+        }
+        Ok(())
+    }
+
+    // write buf.len() / SECTOR_SIZE contiguous blocks starting at blk
+    fn write_blocks(&self, blk: usize, buf: &[u8]) -> IOResult<()> {
+        if !buf.len().is_multiple_of(SECTOR_SIZE) {
+            return Err(IOError::BadBufSize);
+        }
+        for (i, b) in buf.chunks_exact(SECTOR_SIZE).enumerate() {
+            self.write(blk + i, b)?;
+        }
+        Ok(())
+    }
+
+    // scatter contiguous blocks starting at blk into bufs, each a multiple of SECTOR_SIZE
+    fn read_blocks_vectored(&self, blk: usize, bufs: &mut [&mut [u8]]) -> IOResult<()> {
+        let mut blk = blk;
+        for buf in bufs.iter_mut() {
+            self.read_blocks(blk, buf)?;
+            blk += buf.len() / SECTOR_SIZE;
+        }
+        Ok(())
+    }
+
+    // gather bufs, each a multiple of SECTOR_SIZE, into contiguous blocks starting at blk
+    fn write_blocks_vectored(&self, blk: usize, bufs: &[&[u8]]) -> IOResult<()> {
+        let mut blk = blk;
+        for buf in bufs.iter() {
+            self.write_blocks(blk, buf)?;
+            blk += buf.len() / SECTOR_SIZE;
+        }
+        Ok(())
+    }
+
+    // barrier: writes issued before it are on the medium before any issued after it
+    fn flush(&self) -> IOResult<()> {
+        Ok(())
+    }
+
+    // the cnt blocks starting at blk hold nothing worth keeping
+    fn discard(&self, blk: usize, cnt: usize) -> IOResult<()> {
+        let _ = (blk, cnt);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 pub mod test {
     use super::BlkDev;
-    use alloc::vec::Vec;
+    use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+    use spin::Mutex;
 
     use super::super::types::*;
     pub struct MemoryBlockInner {
-        pub blocks: Vec<[u8; BlockSize]>,
+        pub blocks: Vec<[u8; SECTOR_SIZE]>,
         pub write_cnt: usize,
         pub read_cnt: usize,
     }
@@ -28,7 +82,7 @@ pub mod test {
             if blk >= inner.blocks.len() {
                 return Err(IOError::NoSuchBlock);
             }
-            if buf.len() != BlockSize {
+            if buf.len() != SECTOR_SIZE {
                 return Err(IOError::BadBufSize);
             }
             buf.copy_from_slice(&inner.blocks[blk]);
@@ -40,12 +94,99 @@ pub mod test {
             if blk >= inner.blocks.len() {
                 return Err(IOError::NoSuchBlock);
             }
-            if buf.len() != BlockSize {
+            if buf.len() != SECTOR_SIZE {
                 return Err(IOError::BadBufSize);
             }
             inner.blocks[blk].copy_from_slice(buf);
             inner.write_cnt += 1;
             Ok(())
         }
+        // a single request for the whole range
+        fn read_blocks(&self, blk: usize, buf: &mut [u8]) -> IOResult<()> {
+            let inner = unsafe { &mut *self.inner };
+            let cnt = buf.len() / SECTOR_SIZE;
+            if !buf.len().is_multiple_of(SECTOR_SIZE) {
+                return Err(IOError::BadBufSize);
+            }
+            if blk + cnt > inner.blocks.len() {
+                return Err(IOError::NoSuchBlock);
+            }
+            for (b, data) in buf.chunks_exact_mut(SECTOR_SIZE).zip(&inner.blocks[blk..]) {
+                b.copy_from_slice(data);
+            }
+            inner.read_cnt += 1;
+            Ok(())
+        }
+        fn write_blocks(&self, blk: usize, buf: &[u8]) -> IOResult<()> {
+            let inner = unsafe { &mut *self.inner };
+            let cnt = buf.len() / SECTOR_SIZE;
+            if !buf.len().is_multiple_of(SECTOR_SIZE) {
+                return Err(IOError::BadBufSize);
+            }
+            if blk + cnt > inner.blocks.len() {
+                return Err(IOError::NoSuchBlock);
+            }
+            for (b, data) in buf.chunks_exact(SECTOR_SIZE).zip(&mut inner.blocks[blk..]) {
+                data.copy_from_slice(b);
+            }
+            inner.write_cnt += 1;
+            Ok(())
+        }
+        fn read_blocks_vectored(&self, blk: usize, bufs: &mut [&mut [u8]]) -> IOResult<()> {
+            let mut data = alloc::vec![0u8; bufs.iter().map(|b| b.len()).sum()];
+            self.read_blocks(blk, &mut data)?;
+            let mut rest = &data[..];
+            for buf in bufs.iter_mut() {
+                buf.copy_from_slice(&rest[..buf.len()]);
+                rest = &rest[buf.len()..];
+            }
+            Ok(())
+        }
+        fn write_blocks_vectored(&self, blk: usize, bufs: &[&[u8]]) -> IOResult<()> {
+            self.write_blocks(blk, &bufs.concat())
+        }
+    }
+
+    // a disk that loses power after `limit` writes: later writes never reach `disk`,
+    // but reads still see them so the running fs behaves as if nothing happened
+    pub struct CrashDevice {
+        pub disk: Arc<dyn BlkDev>,
+        pub limit: usize,
+        pub writes: Mutex<usize>,
+        volatile: Mutex<BTreeMap<usize, [u8; SECTOR_SIZE]>>,
+    }
+
+    impl CrashDevice {
+        pub fn new(disk: Arc<dyn BlkDev>, limit: usize) -> Self {
+            Self {
+                disk,
+                limit,
+                writes: Mutex::new(0),
+                volatile: Mutex::new(BTreeMap::new()),
+            }
+        }
+    }
+
+    impl BlkDev for CrashDevice {
+        fn read(&self, blk: usize, buf: &mut [u8]) -> IOResult<()> {
+            match self.volatile.lock().get(&blk) {
+                Some(data) => {
+                    buf.copy_from_slice(data);
+                    Ok(())
+                }
+                None => self.disk.read(blk, buf),
+            }
+        }
+        fn write(&self, blk: usize, buf: &[u8]) -> IOResult<()> {
+            let mut writes = self.writes.lock();
+            *writes += 1;
+            if *writes <= self.limit {
+                return self.disk.write(blk, buf);
+            }
+            let mut data = [0u8; SECTOR_SIZE];
+            data.copy_from_slice(buf);
+            self.volatile.lock().insert(blk, data);
+            Ok(())
+        }
     }
 }
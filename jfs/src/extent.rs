@@ -0,0 +1,470 @@
+use alloc::vec::Vec;
+use core::mem::offset_of;
+
+use super::types::*;
+use crate::jfs::{DiskInode, DIRECT_BLOCKS, JFS};
+
+// extents held by the inode itself, in place of its block pointers
+pub(crate) const ROOT_EXTENTS: usize = 10;
+
+// len blocks from start on; in the root of a depth 1 tree,
+// start is a leaf block and len the data blocks its extents map
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Extent {
+    pub start: u32,
+    pub len: u32,
+}
+
+impl Extent {
+    fn end(&self) -> u32 {
+        self.start + self.len
+    }
+}
+
+// the block pointers of an extent inode: at depth 0 the root holds the extents,
+// at depth 1 it points to leaf blocks of extents ended by one with len 0;
+// files have no holes, so the extents map the data blocks in file order
+#[repr(C)]
+pub(crate) struct ExtentRoot {
+    pub depth: u16,
+    pub count: u16,
+    pub entries: [Extent; ROOT_EXTENTS],
+}
+
+const _: () = assert!(size_of::<ExtentRoot>() == (DIRECT_BLOCKS + 2) * size_of::<u32>());
+
+impl JFS {
+    // extents a leaf block holds
+    pub(crate) fn leaf_extents(&self) -> usize {
+        self.block_size / size_of::<Extent>()
+    }
+
+    pub(crate) fn read_leaf(&self, blk_id: u32) -> IOResult<Vec<Extent>> {
+        let blk = self.get_block(blk_id)?;
+        let rt = blk
+            .lock()
+            .read_slice(|es: &[Extent]| es.iter().take_while(|e| e.len != 0).copied().collect());
+        Ok(rt)
+    }
+
+    fn write_leaf(&self, blk_id: u32, extents: &[Extent]) -> IOResult<()> {
+        self.get_block(blk_id)?
+            .lock()
+            .write_slice(|es: &mut [Extent]| {
+                es.fill(Extent::default());
+                es[..extents.len()].copy_from_slice(extents);
+            });
+        Ok(())
+    }
+}
+
+impl DiskInode {
+    pub fn root(&self) -> &ExtentRoot {
+        let p = self as *const Self as *const u8;
+        unsafe { &*(p.add(offset_of!(DiskInode, block0s)) as *const ExtentRoot) }
+    }
+
+    pub(crate) fn root_mut(&mut self) -> &mut ExtentRoot {
+        let p = self as *mut Self as *mut u8;
+        unsafe { &mut *(p.add(offset_of!(DiskInode, block0s)) as *mut ExtentRoot) }
+    }
+
+    // the block of the i-th data block and how many blocks follow it on disk
+    pub(crate) fn extent_run(&self, i: usize, fs: &JFS) -> IOResult<(u32, usize)> {
+        let root = self.root();
+        let mut i = i as u32;
+        for e in root.entries[..root.count as usize].iter() {
+            if i >= e.len {
+                i -= e.len;
+                continue;
+            }
+            if root.depth == 0 {
+                return Ok((e.start + i, (e.len - i) as usize));
+            }
+            let blk = fs.get_block(e.start)?;
+            let rt = blk.lock().read_slice(|es: &[Extent]| {
+                for x in es.iter().take_while(|x| x.len != 0) {
+                    if i < x.len {
+                        return Some((x.start + i, (x.len - i) as usize));
+                    }
+                    i -= x.len;
+                }
+                None
+            });
+            return rt.ok_or(IOError::CorruptedFS);
+        }
+        Err(IOError::CorruptedFS)
+    }
+
+    // blocks held besides the data blocks
+    pub(crate) fn leaf_blocks(&self) -> usize {
+        let root = self.root();
+        match root.depth {
+            0 => 0,
+            _ => root.count as usize,
+        }
+    }
+
+    // whether adding cnt more data blocks takes a new leaf block, which the
+    // caller allocates beforehand; FileTooLarge when the tree can not hold them
+    pub(crate) fn needs_leaf(&self, cnt: usize, fs: &JFS) -> IOResult<bool> {
+        let root = self.root();
+        let count = root.count as usize;
+        // every block might start an extent of its own
+        let need_leaf = match root.depth {
+            0 => count + cnt > ROOT_EXTENTS,
+            _ => {
+                let last = root.entries[count - 1].start;
+                fs.read_leaf(last)?.len() + cnt > fs.leaf_extents()
+            }
+        };
+        // one leaf is enough, as long as a resize step fits in one
+        let full = root.depth == 1 && count == ROOT_EXTENTS;
+        if cnt > fs.leaf_extents() - ROOT_EXTENTS || need_leaf && full {
+            return Err(IOError::FileTooLarge);
+        }
+        Ok(need_leaf)
+    }
+
+    // map one more data block after the last one
+    pub(crate) fn push_extent(
+        &mut self,
+        block: u32,
+        spare: &mut Option<u32>,
+        fs: &JFS,
+    ) -> IOResult<()> {
+        let root = self.root_mut();
+        let count = root.count as usize;
+        if root.depth == 0 {
+            if count > 0 && root.entries[count - 1].end() == block {
+                root.entries[count - 1].len += 1;
+                return Ok(());
+            }
+            if count < ROOT_EXTENTS {
+                root.entries[count] = Extent {
+                    start: block,
+                    len: 1,
+                };
+                root.count += 1;
+                return Ok(());
+            }
+            // the root is full, its extents move to a leaf
+            let leaf = spare.take().ok_or(IOError::FileTooLarge)?;
+            fs.write_leaf(leaf, &root.entries)?;
+            let len = root.entries.iter().map(|e| e.len).sum();
+            root.entries.fill(Extent::default());
+            root.entries[0] = Extent { start: leaf, len };
+            root.depth = 1;
+            root.count = 1;
+        }
+        let count = root.count as usize;
+        let stored = fs
+            .get_block(root.entries[count - 1].start)?
+            .lock()
+            .write_slice(|es: &mut [Extent]| {
+                let n = es.iter().take_while(|x| x.len != 0).count();
+                if n > 0 && es[n - 1].end() == block {
+                    es[n - 1].len += 1;
+                } else if n < es.len() {
+                    es[n] = Extent {
+                        start: block,
+                        len: 1,
+                    };
+                } else {
+                    return false;
+                }
+                true
+            });
+        if !stored {
+            if count == ROOT_EXTENTS {
+                return Err(IOError::FileTooLarge);
+            }
+            let leaf = spare.take().ok_or(IOError::FileTooLarge)?;
+            fs.write_leaf(
+                leaf,
+                &[Extent {
+                    start: block,
+                    len: 1,
+                }],
+            )?;
+            root.entries[count] = Extent {
+                start: leaf,
+                len: 0,
+            };
+            root.count += 1;
+        }
+        root.entries[root.count as usize - 1].len += 1;
+        Ok(())
+    }
+
+    // unmap the last data block, returns it along with a leaf block left empty
+    pub(crate) fn pop_extent(&mut self, freed: &mut Vec<u32>, fs: &JFS) -> IOResult<()> {
+        let root = self.root_mut();
+        let count = root.count as usize;
+        if count == 0 {
+            return Err(IOError::CorruptedFS);
+        }
+        let last = &mut root.entries[count - 1];
+        if last.len == 0 {
+            return Err(IOError::CorruptedFS);
+        }
+        last.len -= 1;
+        if root.depth == 0 {
+            freed.push(last.start + last.len);
+        } else {
+            let block = fs
+                .get_block(last.start)?
+                .lock()
+                .write_slice(|es: &mut [Extent]| {
+                    let n = es.iter().take_while(|x| x.len != 0).count();
+                    let x = es[..n].last_mut()?;
+                    x.len -= 1;
+                    let block = x.start + x.len;
+                    if x.len == 0 {
+                        *x = Extent::default();
+                    }
+                    Some(block)
+                });
+            freed.push(block.ok_or(IOError::CorruptedFS)?);
+            if last.len == 0 {
+                freed.push(last.start);
+            }
+        }
+        if last.len == 0 {
+            *last = Extent::default();
+            root.count -= 1;
+        }
+        if root.count == 0 {
+            root.depth = 0;
+        }
+        Ok(())
+    }
+
+    // fold a depth 1 tree with one small leaf back into the root
+    pub(crate) fn shrink_tree(&mut self, freed: &mut Vec<u32>, fs: &JFS) -> IOResult<()> {
+        let root = self.root_mut();
+        if root.depth != 1 || root.count != 1 {
+            return Ok(());
+        }
+        let leaf = root.entries[0].start;
+        let extents = fs.read_leaf(leaf)?;
+        if extents.len() <= ROOT_EXTENTS {
+            root.entries.fill(Extent::default());
+            root.entries[..extents.len()].copy_from_slice(&extents);
+            root.depth = 0;
+            root.count = extents.len() as u16;
+            freed.push(leaf);
+        }
+        Ok(())
+    }
+
+    // map exactly the data blocks given, reusing leaf blocks from leaves,
+    // returns how many of them are in use
+    pub(crate) fn store_extents(
+        &mut self,
+        blocks: &[u32],
+        leaves: &[u32],
+        fs: &JFS,
+    ) -> IOResult<usize> {
+        let mut extents: Vec<Extent> = Vec::new();
+        for &b in blocks {
+            match extents.last_mut() {
+                Some(e) if e.end() == b => e.len += 1,
+                _ => extents.push(Extent { start: b, len: 1 }),
+            }
+        }
+        let root = self.root_mut();
+        root.entries.fill(Extent::default());
+        if extents.len() <= ROOT_EXTENTS {
+            root.depth = 0;
+            root.count = extents.len() as u16;
+            root.entries[..extents.len()].copy_from_slice(&extents);
+            return Ok(0);
+        }
+        let chunks = extents.chunks(fs.leaf_extents());
+        if chunks.len() > leaves.len().min(ROOT_EXTENTS) {
+            return Err(IOError::FileTooLarge);
+        }
+        root.depth = 1;
+        root.count = chunks.len() as u16;
+        for (i, (chunk, &leaf)) in chunks.zip(leaves).enumerate() {
+            fs.write_leaf(leaf, chunk)?;
+            let len = chunk.iter().map(|e| e.len).sum();
+            root.entries[i] = Extent { start: leaf, len };
+        }
+        Ok(root.count as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::ROOT_EXTENTS;
+    use crate::fsck::{fsck, Problem};
+    use crate::inode::Inode;
+    use crate::jfs::test::new_device;
+    use crate::jfs::{FileType, MkfsOptions, JFS};
+    use crate::types::*;
+
+    fn extent_fs(
+        total: u32,
+    ) -> (
+        Arc<dyn crate::BlkDev>,
+        Box<crate::device::test::MemoryBlockInner>,
+        Arc<JFS>,
+    ) {
+        let (dev, inner) = new_device(total as usize);
+        let opts = MkfsOptions {
+            extents: true,
+            ..Default::default()
+        };
+        let fs = Arc::new(JFS::mkfs_with(Arc::clone(&dev), total, 2, &opts).unwrap());
+        (dev, inner, fs)
+    }
+
+    fn pattern(blocks: usize, seed: usize) -> Vec<u8> {
+        (0..blocks * SECTOR_SIZE)
+            .map(|i| (i / SECTOR_SIZE * 31 + i + seed) as u8)
+            .collect()
+    }
+
+    // (depth, count) of the extent root
+    fn shape(fs: &JFS, f: &Inode) -> (u16, u16) {
+        fs.read_disk_inode(f.id(), |d| (d.root().depth, d.root().count))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_contiguous() {
+        let (dev, inner, fs) = extent_fs(1024);
+        let f = fs.root_dir().create("big", FileType::File).unwrap();
+        assert!(f.uses_extents().unwrap());
+        let data = pattern(300, 0);
+        f.write_at(0, &data).unwrap();
+        // a fresh fs hands out one run
+        assert_eq!((0, 1), shape(&fs, &f));
+        assert_eq!(300, f.stat().unwrap().blocks);
+        drop(f);
+        drop(Arc::into_inner(fs).unwrap());
+
+        // the whole file comes in a handful of requests
+        let fs = Arc::new(JFS::from_dev(dev).unwrap());
+        assert!(fs.uses_extents());
+        let f = fs.root_dir().lookup("big").unwrap();
+        let reads = inner.read_cnt;
+        assert_eq!(data, f.read_all().unwrap());
+        assert!(inner.read_cnt - reads <= 4);
+        let mut buf = vec![0u8; SECTOR_SIZE * 3];
+        f.read_at(SECTOR_SIZE * 100 + 7, &mut buf).unwrap();
+        assert_eq!(data[SECTOR_SIZE * 100 + 7..][..SECTOR_SIZE * 3], buf[..]);
+    }
+
+    #[test]
+    fn test_fragmented() {
+        let (_dev, _inner, fs) = extent_fs(1024);
+        let root = fs.root_dir();
+        let a = root.create("a", FileType::File).unwrap();
+        let b = root.create("b", FileType::File).unwrap();
+        let data = pattern(100, 1);
+        // growing both files in turn interleaves their blocks
+        for i in 0..100 {
+            let block = &data[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE];
+            a.write_at(i * SECTOR_SIZE, block).unwrap();
+            b.write_at(i * SECTOR_SIZE, block).unwrap();
+        }
+        // 64 extents fit a leaf of 512 bytes
+        assert_eq!((1, 2), shape(&fs, &a));
+        assert_eq!(102, a.stat().unwrap().blocks);
+        assert_eq!(data, a.read_all().unwrap());
+        assert_eq!(data, b.read_all().unwrap());
+        assert!(fsck(&fs, false).unwrap().is_clean());
+
+        // shrinking frees the leaves and folds the tree back into the inode
+        a.resize(SECTOR_SIZE * 70).unwrap();
+        assert_eq!((1, 2), shape(&fs, &a));
+        a.resize(SECTOR_SIZE * ROOT_EXTENTS).unwrap();
+        assert_eq!((0, ROOT_EXTENTS as u16), shape(&fs, &a));
+        assert_eq!(ROOT_EXTENTS as u64, a.stat().unwrap().blocks);
+        assert_eq!(
+            data[..SECTOR_SIZE * ROOT_EXTENTS],
+            a.read_all().unwrap()[..]
+        );
+        root.remove("b").unwrap();
+        assert!(fsck(&fs, false).unwrap().is_clean());
+        // freed blocks are reused in order, so a regrown file is contiguous again
+        let c = root.create("c", FileType::File).unwrap();
+        c.write_at(0, &pattern(90, 2)).unwrap();
+        assert!(shape(&fs, &c).1 <= 2);
+        assert_eq!(pattern(90, 2), c.read_all().unwrap());
+        assert!(fsck(&fs, false).unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_per_inode_flag() {
+        let (dev, _inner) = new_device(1024);
+        let fs = Arc::new(JFS::mkfs(Arc::clone(&dev), 1024, 2).unwrap());
+        let root = fs.root_dir();
+        let plain = root.create("plain", FileType::File).unwrap();
+        let ext = root.create("ext", FileType::File).unwrap();
+        assert!(!ext.uses_extents().unwrap());
+        assert_eq!(0, fs.info().unwrap().feature_incompat);
+        ext.set_extents(true).unwrap();
+        assert_eq!(1, fs.info().unwrap().feature_incompat);
+        let data = pattern(40, 3);
+        plain.write_at(0, &data).unwrap();
+        ext.write_at(0, &data).unwrap();
+        assert!(matches!(
+            ext.set_extents(false),
+            Err(IOError::InvalidArgument)
+        ));
+        // the table inode also holds its block1 table
+        assert_eq!(41, plain.stat().unwrap().blocks);
+        assert_eq!(40, ext.stat().unwrap().blocks);
+        assert_eq!(data, plain.read_all().unwrap());
+        assert_eq!(data, ext.read_all().unwrap());
+        assert!(fsck(&fs, false).unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_fsck_extents() {
+        let (_dev, _inner, fs) = extent_fs(1024);
+        let root = fs.root_dir();
+        let a = root.create("a", FileType::File).unwrap();
+        a.write_at(0, &pattern(20, 4)).unwrap();
+        let b = root.create("b", FileType::File).unwrap();
+        b.write_at(0, &pattern(20, 5)).unwrap();
+        // the second half of b now claims blocks of a
+        let shared = fs
+            .read_disk_inode(a.id(), |d| d.root().entries[0].start)
+            .unwrap();
+        fs.modify_disk_inode(b.id(), |d| {
+            let root = d.root_mut();
+            root.entries[0].len = 10;
+            root.entries[1] = super::Extent {
+                start: shared,
+                len: 10,
+            };
+            root.count = 2;
+        })
+        .unwrap();
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert!(problems.contains(&Problem::DoubleOwnedBlock(shared)));
+        assert!(problems.contains(&Problem::SizeMismatch {
+            inode: b.id(),
+            size: (SECTOR_SIZE * 20) as u32,
+            valid_size: (SECTOR_SIZE * 10) as u32,
+        }));
+        let report = fsck(&fs, true).unwrap();
+        assert!(report.repaired);
+        assert!(fsck(&fs, false).unwrap().is_clean());
+        assert_eq!(pattern(20, 4), a.read_all().unwrap());
+        assert_eq!(
+            pattern(20, 5)[..SECTOR_SIZE * 10],
+            b.read_all().unwrap()[..]
+        );
+    }
+}
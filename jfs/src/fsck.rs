@@ -0,0 +1,906 @@
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt;
+
+use super::types::*;
+use crate::{
+    extent::ROOT_EXTENTS,
+    inode::{DirEntry, DIR_ENTRY_SIZE},
+    jfs::{DiskInode, FileType, SuperBlock, DIRECT_BLOCKS, JFS, ROOT_INODE, VERSION},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    BadSuperBlock,
+    BadInode(u32),
+    InodeListCycle(u32),
+    BadFreeInode(u32),
+    LeakedInode(u32),
+    // unlinked while open, and never freed as the fs went down first
+    Orphan(u32),
+    BadFreeBlock(u32),
+    BadFreeCount {
+        recorded: u32,
+        actual: u32,
+    },
+    BadFreeInodeCount {
+        recorded: u32,
+        actual: u32,
+    },
+    DoubleOwnedBlock(u32),
+    LeakedBlock(u32),
+    SizeMismatch {
+        inode: u32,
+        size: u32,
+        valid_size: u32,
+    },
+    DanglingEntry {
+        dir: u32,
+        name: String,
+        inode: u32,
+    },
+    LinkCount {
+        inode: u32,
+        nlink: u32,
+        links: u32,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadSuperBlock => write!(f, "bad super block"),
+            Problem::BadInode(id) => write!(f, "inode {} has a bad file type", id),
+            Problem::InodeListCycle(id) => write!(f, "inode free list loops at inode {}", id),
+            Problem::BadFreeInode(id) => write!(f, "bad inode {} is marked free", id),
+            Problem::LeakedInode(id) => write!(f, "inode {} is neither free nor reachable", id),
+            Problem::Orphan(id) => write!(f, "inode {} was unlinked but never freed", id),
+            Problem::BadFreeBlock(b) => write!(f, "free block list holds bad block {}", b),
+            Problem::BadFreeCount { recorded, actual } => write!(
+                f,
+                "free block count is {}, but {} blocks are free",
+                recorded, actual
+            ),
+            Problem::BadFreeInodeCount { recorded, actual } => write!(
+                f,
+                "free inode count is {}, but {} inodes are free",
+                recorded, actual
+            ),
+            Problem::DoubleOwnedBlock(b) => write!(f, "block {} is owned twice", b),
+            Problem::LeakedBlock(b) => write!(f, "block {} is neither free nor owned", b),
+            Problem::SizeMismatch {
+                inode,
+                size,
+                valid_size,
+            } => write!(
+                f,
+                "inode {} has size {}, but its blocks only hold {} bytes",
+                inode, size, valid_size
+            ),
+            Problem::DanglingEntry { dir, name, inode } => write!(
+                f,
+                "entry {:?} in directory {} points to bad inode {}",
+                name, dir, inode
+            ),
+            Problem::LinkCount {
+                inode,
+                nlink,
+                links,
+            } => write!(
+                f,
+                "inode {} has link count {}, but {} entries link it",
+                inode, nlink, links
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    pub problems: Vec<Problem>,
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+// where a block pointer of an inode is stored
+enum Slot {
+    Direct(usize),
+    Block1,
+    Block2,
+    Table(u32, usize),
+}
+
+struct Pointer {
+    pos: usize,
+    slot: Slot,
+    block: u32,
+}
+
+struct Checker<'a> {
+    fs: &'a JFS,
+    repair: bool,
+    problems: Vec<Problem>,
+    owner: BTreeMap<u32, u32>,
+    // entries linking each reachable inode, the root counts one
+    links: BTreeMap<u32, u32>,
+    free_inodes: BTreeSet<u32>,
+    free_blocks: BTreeSet<u32>,
+}
+
+// check the whole file system, and rebuild what can be rebuilt when repair is set
+pub fn fsck(fs: &Arc<JFS>, repair: bool) -> IOResult<FsckReport> {
+    let mut c = Checker {
+        fs,
+        repair,
+        problems: Vec::new(),
+        owner: BTreeMap::new(),
+        links: BTreeMap::new(),
+        free_inodes: BTreeSet::new(),
+        free_blocks: BTreeSet::new(),
+    };
+    c.check_super_block()?;
+    c.scan_free_inodes()?;
+    c.scan_free_blocks()?;
+    c.walk_tree()?;
+    c.check_link_counts()?;
+    c.keep_open_orphans()?;
+    for id in ROOT_INODE + 1..fs.inode_cnt() {
+        if !c.links.contains_key(&id) && !c.free_inodes.contains(&id) {
+            let problem = match c.is_orphan(id)? {
+                true => Problem::Orphan(id),
+                false => Problem::LeakedInode(id),
+            };
+            c.problems.push(problem);
+        }
+    }
+    for b in fs.data_start_block..fs.data_end_block {
+        match (c.owner.contains_key(&b), c.free_blocks.contains(&b)) {
+            (true, true) => c.problems.push(Problem::DoubleOwnedBlock(b)),
+            (false, false) => c.problems.push(Problem::LeakedBlock(b)),
+            _ => {}
+        }
+    }
+    c.check_free_counts()?;
+    let repaired = repair && !c.problems.is_empty();
+    if repaired {
+        c.rebuild_free_inodes()?;
+        c.rebuild_free_blocks()?;
+    }
+    Ok(FsckReport {
+        problems: c.problems,
+        repaired,
+    })
+}
+
+impl Checker<'_> {
+    fn in_data(&self, b: u32) -> bool {
+        b >= self.fs.data_start_block && b < self.fs.data_end_block
+    }
+
+    fn raw_type(&self, id: u32) -> IOResult<Option<FileType>> {
+        let pos = self.fs.get_inode_pos(id);
+        let raw = self
+            .fs
+            .get_block(pos.block_id)?
+            .lock()
+            .read(pos.offset, |t: &u8| *t);
+        Ok(FileType::from_raw(raw))
+    }
+
+    fn read_table(&self, blk_id: u32) -> IOResult<Vec<u32>> {
+        let blk = self.fs.get_block(blk_id)?;
+        let rt = blk.lock().read_slice(|bs: &[u32]| bs.to_vec());
+        Ok(rt)
+    }
+
+    fn check_super_block(&mut self) -> IOResult<()> {
+        let fs = self.fs;
+        let data_blocks = fs.data_end_block - fs.data_start_block;
+        let repair = self.repair;
+        let sb_ok = fs.get_block(0)?.lock().write(0, |sb: &mut SuperBlock| {
+            let ok = sb.version == VERSION && sb.data_blocks == data_blocks;
+            if !ok && repair {
+                sb.version = VERSION;
+                sb.data_blocks = data_blocks;
+            }
+            ok
+        });
+        let head = fs.idle_head_pos();
+        let gc = fs.block_gc_pos();
+        let blk = fs.get_block(0)?;
+        let head_type = blk.lock().read(head.offset, |t: &u8| *t);
+        let gc_type = blk.lock().read(gc.offset, |t: &u8| *t);
+        if !sb_ok || head_type != FileType::IdleHead as u8 || gc_type != FileType::BlockGC as u8 {
+            self.problems.push(Problem::BadSuperBlock);
+        }
+        Ok(())
+    }
+
+    fn scan_free_inodes(&mut self) -> IOResult<()> {
+        let fs = self.fs;
+        if let Some(bm) = &fs.bitmaps {
+            if !bm.inodes.is_used(fs, ROOT_INODE)? {
+                self.problems.push(Problem::BadFreeInode(ROOT_INODE));
+            }
+            for id in ROOT_INODE + 1..fs.inode_cnt() {
+                if bm.inodes.is_used(fs, id)? {
+                    continue;
+                }
+                match self.raw_type(id)? {
+                    Some(FileType::IdleHead) => _ = self.free_inodes.insert(id),
+                    _ => self.problems.push(Problem::BadFreeInode(id)),
+                }
+            }
+            return Ok(());
+        }
+        let head = self.fs.idle_head_pos();
+        let mut next = self
+            .fs
+            .get_block(head.block_id)?
+            .lock()
+            .read(head.offset, |d: &DiskInode| d.block1);
+        let mut visited = BTreeSet::new();
+        while next != 0 {
+            if next >= self.fs.inode_cnt() || next == ROOT_INODE {
+                self.problems.push(Problem::BadFreeInode(next));
+                break;
+            }
+            if !visited.insert(next) {
+                self.problems.push(Problem::InodeListCycle(next));
+                break;
+            }
+            if self.raw_type(next)? != Some(FileType::IdleHead) {
+                self.problems.push(Problem::BadFreeInode(next));
+                break;
+            }
+            self.free_inodes.insert(next);
+            next = self.fs.read_disk_inode(next, |d| d.block1)?;
+        }
+        Ok(())
+    }
+
+    fn add_free_block(&mut self, b: u32) -> bool {
+        if !self.in_data(b) {
+            self.problems.push(Problem::BadFreeBlock(b));
+            return false;
+        }
+        if !self.free_blocks.insert(b) {
+            self.problems.push(Problem::DoubleOwnedBlock(b));
+            return false;
+        }
+        true
+    }
+
+    fn add_free_table(&mut self, blk_id: u32) -> IOResult<()> {
+        if self.add_free_block(blk_id) {
+            for &b in self.read_table(blk_id)?.iter().filter(|b| **b != 0) {
+                self.add_free_block(b);
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_free_blocks(&mut self) -> IOResult<()> {
+        let fs = self.fs;
+        if let Some(bm) = &fs.bitmaps {
+            for b in fs.data_start_block..fs.data_end_block {
+                if !bm.blocks.is_used(fs, b - fs.data_start_block)? {
+                    self.free_blocks.insert(b);
+                }
+            }
+            return Ok(());
+        }
+        let pos = fs.block_gc_pos();
+        let (block0s, block1, mut block2) = fs
+            .get_block(pos.block_id)?
+            .lock()
+            .read(pos.offset, |d: &DiskInode| (d.block0s, d.block1, d.block2));
+        for &b in block0s.iter().filter(|b| **b != 0) {
+            self.add_free_block(b);
+        }
+        if block1 != 0 {
+            self.add_free_table(block1)?;
+        }
+        while block2 != 0 {
+            if !self.add_free_block(block2) {
+                break;
+            }
+            let l2 = self.read_table(block2)?;
+            for &l1 in l2[1..].iter().filter(|b| **b != 0) {
+                self.add_free_table(l1)?;
+            }
+            block2 = l2[0];
+        }
+        Ok(())
+    }
+
+    // the counts statfs reports
+    fn check_free_counts(&mut self) -> IOResult<()> {
+        let st = self.fs.statfs()?;
+        let actual = self.free_blocks.len() as u32;
+        if st.free_blocks != actual {
+            self.problems.push(Problem::BadFreeCount {
+                recorded: st.free_blocks,
+                actual,
+            });
+        }
+        let actual = self.free_inodes.len() as u32;
+        if st.free_inodes != actual {
+            self.problems.push(Problem::BadFreeInodeCount {
+                recorded: st.free_inodes,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    fn pointers(&self, d: &DiskInode) -> IOResult<Vec<Pointer>> {
+        let mut rt = Vec::new();
+        for (i, &b) in d.block0s.iter().enumerate().filter(|(_, b)| **b != 0) {
+            rt.push(Pointer {
+                pos: i,
+                slot: Slot::Direct(i),
+                block: b,
+            });
+        }
+        if d.block1 != 0 {
+            rt.push(Pointer {
+                pos: DIRECT_BLOCKS,
+                slot: Slot::Block1,
+                block: d.block1,
+            });
+            if self.in_data(d.block1) {
+                self.table_pointers(d.block1, DIRECT_BLOCKS + 1, &mut rt)?;
+            }
+        }
+        if d.block2 != 0 {
+            rt.push(Pointer {
+                pos: self.fs.l1_limit(),
+                slot: Slot::Block2,
+                block: d.block2,
+            });
+            if self.in_data(d.block2) {
+                let l2 = self.read_table(d.block2)?;
+                for (t, &l1) in l2.iter().enumerate().filter(|(_, b)| **b != 0) {
+                    let pos = self.fs.l1_limit() + 1 + t * (self.fs.ids_per_block() + 1);
+                    rt.push(Pointer {
+                        pos,
+                        slot: Slot::Table(d.block2, t),
+                        block: l1,
+                    });
+                    if self.in_data(l1) {
+                        self.table_pointers(l1, pos + 1, &mut rt)?;
+                    }
+                }
+            }
+        }
+        Ok(rt)
+    }
+
+    fn table_pointers(&self, blk_id: u32, start: usize, rt: &mut Vec<Pointer>) -> IOResult<()> {
+        for (k, &b) in self.read_table(blk_id)?.iter().enumerate() {
+            if b != 0 {
+                rt.push(Pointer {
+                    pos: start + k,
+                    slot: Slot::Table(blk_id, k),
+                    block: b,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // check the block pointers of an inode against its size and claim its blocks,
+    // returns the data blocks in file order and the usable size
+    fn check_inode(&mut self, id: u32, is_dir: bool) -> IOResult<(Vec<u32>, u32)> {
+        let fs = self.fs;
+        if fs.read_disk_inode(id, |d| d.is_extents())? {
+            return self.check_extents(id, is_dir);
+        }
+        let (size, pointers) = fs.read_disk_inode(id, |d| (d.size, self.pointers(d)))?;
+        let pointers = pointers?;
+        let total = DiskInode::total_blocks(size, fs);
+        let mut valid_until = total;
+        let mut expect = 0;
+        let mut seen = BTreeSet::new();
+        for p in pointers.iter().take_while(|p| p.pos < total) {
+            if p.pos != expect {
+                break;
+            }
+            if !self.in_data(p.block) || !seen.insert(p.block) {
+                break;
+            }
+            if self.owner.contains_key(&p.block) {
+                self.problems.push(Problem::DoubleOwnedBlock(p.block));
+                break;
+            }
+            expect += 1;
+        }
+        valid_until = valid_until.min(expect);
+        let mut data_cnt = 0;
+        while data_cnt < (size as usize).div_ceil(fs.block_size)
+            && DiskInode::data_block_pos(data_cnt, fs) < valid_until
+        {
+            data_cnt += 1;
+        }
+        let mut valid_size = size.min((data_cnt * fs.block_size) as u32);
+        if is_dir {
+            valid_size -= valid_size % DIR_ENTRY_SIZE as u32;
+        }
+        let valid_total = DiskInode::total_blocks(valid_size, fs);
+        if valid_size != size || pointers.iter().any(|p| p.pos >= valid_total) {
+            self.problems.push(Problem::SizeMismatch {
+                inode: id,
+                size,
+                valid_size,
+            });
+            if self.repair {
+                self.truncate(id, &pointers, valid_size, valid_total)?;
+            }
+        }
+        let by_pos: BTreeMap<usize, u32> = pointers
+            .iter()
+            .take_while(|p| p.pos < valid_total)
+            .map(|p| (p.pos, p.block))
+            .collect();
+        for &b in by_pos.values() {
+            self.owner.insert(b, id);
+        }
+        let data = (0..(valid_size as usize).div_ceil(fs.block_size))
+            .map(|i| by_pos[&DiskInode::data_block_pos(i, fs)])
+            .collect();
+        Ok((data, valid_size))
+    }
+
+    // check_inode for extent inodes, a broken tree is cut back to the blocks before the damage
+    fn check_extents(&mut self, id: u32, is_dir: bool) -> IOResult<(Vec<u32>, u32)> {
+        let fs = self.fs;
+        let (size, depth, entries) = fs.read_disk_inode(id, |d| {
+            let root = d.root();
+            let count = (root.count as usize).min(ROOT_EXTENTS);
+            (d.size, root.depth, root.entries[..count].to_vec())
+        })?;
+        let mut intact = depth <= 1;
+        let mut claimed = BTreeSet::new();
+        let mut leaves = Vec::new();
+        let mut extents = Vec::new();
+        if depth == 0 {
+            extents = entries;
+        } else if depth == 1 {
+            for idx in entries {
+                if !self.claimable(idx.start, &claimed) {
+                    intact = false;
+                    break;
+                }
+                let leaf = fs.read_leaf(idx.start)?;
+                claimed.insert(idx.start);
+                leaves.push(idx.start);
+                extents.extend(leaf);
+            }
+        }
+        let mut data = Vec::new();
+        'extents: for e in extents {
+            for b in e.start..e.start.saturating_add(e.len) {
+                if !self.claimable(b, &claimed) {
+                    intact = false;
+                    break 'extents;
+                }
+                claimed.insert(b);
+                data.push(b);
+            }
+        }
+        let mut valid_size = size.min((data.len() * fs.block_size) as u32);
+        if is_dir {
+            valid_size -= valid_size % DIR_ENTRY_SIZE as u32;
+        }
+        let keep = (valid_size as usize).div_ceil(fs.block_size);
+        if !intact || valid_size != size || data.len() > keep {
+            self.problems.push(Problem::SizeMismatch {
+                inode: id,
+                size,
+                valid_size,
+            });
+            data.truncate(keep);
+            if self.repair {
+                let used = fs.modify_disk_inode(id, |d| {
+                    d.size = valid_size;
+                    d.store_extents(&data, &leaves, fs)
+                })??;
+                leaves.truncate(used);
+            }
+        }
+        for &b in data.iter().chain(leaves.iter()) {
+            self.owner.insert(b, id);
+        }
+        Ok((data, valid_size))
+    }
+
+    // a data block no inode owns yet
+    fn claimable(&mut self, b: u32, claimed: &BTreeSet<u32>) -> bool {
+        if !self.in_data(b) || claimed.contains(&b) {
+            return false;
+        }
+        if self.owner.contains_key(&b) {
+            self.problems.push(Problem::DoubleOwnedBlock(b));
+            return false;
+        }
+        true
+    }
+
+    fn truncate(&self, id: u32, pointers: &[Pointer], size: u32, total: usize) -> IOResult<()> {
+        for p in pointers.iter().filter(|p| p.pos >= total) {
+            match p.slot {
+                Slot::Table(blk_id, idx) if self.in_data(blk_id) => {
+                    self.fs
+                        .get_block(blk_id)?
+                        .lock()
+                        .write_slice(|bs: &mut [u32]| bs[idx] = 0);
+                }
+                _ => {}
+            }
+        }
+        self.fs.modify_disk_inode(id, |d| {
+            for p in pointers.iter().filter(|p| p.pos >= total) {
+                match p.slot {
+                    Slot::Direct(i) => d.block0s[i] = 0,
+                    Slot::Block1 => d.block1 = 0,
+                    Slot::Block2 => d.block2 = 0,
+                    Slot::Table(..) => {}
+                }
+            }
+            d.size = size;
+        })
+    }
+
+    fn read_entries(&self, data: &[u32], size: u32) -> IOResult<Vec<DirEntry>> {
+        let cnt = size as usize / DIR_ENTRY_SIZE;
+        let per_block = self.fs.block_size / DIR_ENTRY_SIZE;
+        let mut rt = Vec::with_capacity(cnt);
+        for i in 0..cnt {
+            let mut e = DirEntry::empty();
+            self.fs
+                .get_block(data[i / per_block])?
+                .lock()
+                .read_slice(|bs: &[u8]| {
+                    let off = i % per_block * DIR_ENTRY_SIZE;
+                    e.as_bytes_mut()
+                        .copy_from_slice(&bs[off..off + DIR_ENTRY_SIZE]);
+                });
+            rt.push(e);
+        }
+        Ok(rt)
+    }
+
+    fn write_entries(&mut self, dir: u32, data: &[u32], entries: &[DirEntry]) -> IOResult<()> {
+        let per_block = self.fs.block_size / DIR_ENTRY_SIZE;
+        for (i, e) in entries.iter().enumerate() {
+            self.fs
+                .get_block(data[i / per_block])?
+                .lock()
+                .write_slice(|bs: &mut [u8]| {
+                    let off = i % per_block * DIR_ENTRY_SIZE;
+                    bs[off..off + DIR_ENTRY_SIZE].copy_from_slice(e.as_bytes());
+                });
+        }
+        let size = (entries.len() * DIR_ENTRY_SIZE) as u32;
+        let fs = self.fs;
+        let freed = fs.modify_disk_inode(dir, |d| {
+            if size < d.size {
+                d.dec_size(size, fs)
+            } else {
+                Ok(Vec::new())
+            }
+        })??;
+        // the blocks go back to the free list when it is rebuilt
+        for b in freed {
+            self.owner.remove(&b);
+        }
+        Ok(())
+    }
+
+    fn walk_tree(&mut self) -> IOResult<()> {
+        self.links.insert(ROOT_INODE, 1);
+        if self.raw_type(ROOT_INODE)? != Some(FileType::Directory) {
+            self.problems.push(Problem::BadInode(ROOT_INODE));
+            if !self.repair {
+                return Ok(());
+            }
+            self.fs
+                .modify_disk_inode(ROOT_INODE, |d| d.init(FileType::Directory))?;
+        }
+        let mut dirs = Vec::from([ROOT_INODE]);
+        while let Some(dir) = dirs.pop() {
+            let (data, size) = self.check_inode(dir, true)?;
+            let entries = self.read_entries(&data, size)?;
+            let mut kept = Vec::with_capacity(entries.len());
+            for e in entries {
+                let child = e.inode;
+                let tp =
+                    if child < self.fs.inode_cnt() && child != ROOT_INODE && !e.name().is_empty() {
+                        self.raw_type(child)?
+                    } else {
+                        None
+                    };
+                let seen = self.links.contains_key(&child);
+                match tp {
+                    // only files and symlinks may have several entries
+                    Some(FileType::Directory) if !seen => dirs.push(child),
+                    Some(FileType::File | FileType::Symlink) => {
+                        if !seen {
+                            self.check_inode(child, false)?;
+                        }
+                    }
+                    _ => {
+                        self.problems.push(Problem::DanglingEntry {
+                            dir,
+                            name: String::from(e.name()),
+                            inode: child,
+                        });
+                        continue;
+                    }
+                }
+                *self.links.entry(child).or_insert(0) += 1;
+                kept.push(e);
+            }
+            if self.repair && kept.len() * DIR_ENTRY_SIZE != size as usize {
+                self.write_entries(dir, &data, &kept)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_link_counts(&mut self) -> IOResult<()> {
+        let links: Vec<(u32, u32)> = self.links.iter().map(|(&id, &n)| (id, n)).collect();
+        for (id, links) in links {
+            let nlink = self.fs.read_disk_inode(id, |d| d.nlink)?;
+            if nlink == links {
+                continue;
+            }
+            self.problems.push(Problem::LinkCount {
+                inode: id,
+                nlink,
+                links,
+            });
+            if self.repair {
+                self.fs.modify_disk_inode(id, |d| d.nlink = links)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_orphan(&self, id: u32) -> IOResult<bool> {
+        match self.raw_type(id)? {
+            Some(FileType::File | FileType::Directory | FileType::Symlink) => {
+                self.fs.read_disk_inode(id, |d| d.is_orphan())
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // an inode unlinked while a handle holds it is still in use, its blocks
+    // stay owned and a repair leaves it alone
+    fn keep_open_orphans(&mut self) -> IOResult<()> {
+        let open: Vec<u32> = self.fs.open.lock().keys().copied().collect();
+        for id in open {
+            if self.links.contains_key(&id) || !self.is_orphan(id)? {
+                continue;
+            }
+            let is_dir = self.fs.read_disk_inode(id, |d| d.is_dir())?;
+            self.check_inode(id, is_dir)?;
+            self.links.insert(id, 0);
+        }
+        Ok(())
+    }
+
+    fn rebuild_free_inodes(&self) -> IOResult<()> {
+        self.fs.clear_free_inodes()?;
+        for id in (ROOT_INODE + 1..self.fs.inode_cnt()).rev() {
+            if !self.links.contains_key(&id) {
+                self.fs.dealloc_inode(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rebuild_free_blocks(&self) -> IOResult<()> {
+        self.fs.clear_free_blocks()?;
+        let free: Vec<u32> = (self.fs.data_start_block..self.fs.data_end_block)
+            .filter(|b| !self.owner.contains_key(b))
+            .collect();
+        self.fs.dealloc_ascending(&free)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    use super::{fsck, Problem};
+    use crate::inode::Inode;
+    use crate::jfs::test::new_device;
+    use crate::jfs::{DiskInode, FileType, JFS};
+    use crate::journal::JOURNAL_BLOCKS;
+    use crate::types::*;
+
+    // the device memory is returned first, so it is dropped after the fs
+    fn sample_fs() -> (
+        alloc::boxed::Box<crate::device::test::MemoryBlockInner>,
+        Arc<JFS>,
+    ) {
+        let (dev, inner) = new_device(2048);
+        let fs = Arc::new(JFS::mkfs(dev, 2048, 8).unwrap());
+        let root = fs.root_dir();
+        let dir = root.create("dir", FileType::Directory).unwrap();
+        for name in ["a", "b", "c"] {
+            let f = dir.create(name, FileType::File).unwrap();
+            f.write_at(0, &vec![name.as_bytes()[0]; SECTOR_SIZE * 40])
+                .unwrap();
+        }
+        root.create("empty", FileType::File).unwrap();
+        (inner, fs)
+    }
+
+    fn first_block(f: &Inode, fs: &JFS) -> u32 {
+        fs.read_disk_inode(f.id(), |d| d.block0s[0]).unwrap()
+    }
+
+    // check, repair, then check again that the repair left a clean fs
+    fn repair(fs: &Arc<JFS>) -> alloc::vec::Vec<Problem> {
+        let report = fsck(fs, true).unwrap();
+        assert!(report.repaired);
+        assert!(fsck(fs, false).unwrap().is_clean());
+        report.problems
+    }
+
+    #[test]
+    fn test_clean() {
+        let (_inner, fs) = sample_fs();
+        let report = fsck(&fs, true).unwrap();
+        assert!(report.is_clean());
+        assert!(!report.repaired);
+    }
+
+    #[test]
+    fn test_block_problems() {
+        let (_inner, fs) = sample_fs();
+        let leaked = fs.alloc_block().unwrap();
+        let a = fs.root_dir().lookup("dir/a").unwrap();
+        let shared = first_block(&a, &fs);
+        fs.dealloc_block(shared).unwrap();
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert!(problems.contains(&Problem::LeakedBlock(leaked)));
+        assert!(problems.contains(&Problem::DoubleOwnedBlock(shared)));
+
+        assert_eq!(2, repair(&fs).len());
+        // the file survived and the free list is whole again
+        let mut buf = vec![0u8; SECTOR_SIZE * 40];
+        a.read_at(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&c| c == b'a'));
+        let mut cnt = 0;
+        while fs.alloc_block().is_ok() {
+            cnt += 1;
+        }
+        // 3 files of 40 blocks plus an indirect block each, 2 directory blocks
+        assert_eq!(2048 - 9 - JOURNAL_BLOCKS as usize - 3 * 41 - 2, cnt);
+    }
+
+    #[test]
+    fn test_inode_problems() {
+        let (_inner, fs) = sample_fs();
+        // make the free list loop back to its first inode
+        let head = fs.idle_head_pos();
+        let first = fs
+            .get_block(head.block_id)
+            .unwrap()
+            .lock()
+            .read(head.offset, |d: &DiskInode| d.block1);
+        let second = fs.read_disk_inode(first, |d| d.block1).unwrap();
+        fs.modify_disk_inode(second, |d| d.block1 = first).unwrap();
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert!(problems.contains(&Problem::InodeListCycle(first)));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, Problem::LeakedInode(_))));
+        repair(&fs);
+        let free_cnt = fs.inode_cnt() - 6;
+        for _ in 0..free_cnt {
+            fs.alloc_inode().unwrap();
+        }
+        assert!(matches!(fs.alloc_inode(), Err(IOError::DiskFull)));
+    }
+
+    #[test]
+    fn test_size_and_entry_problems() {
+        let (_inner, fs) = sample_fs();
+        let root = fs.root_dir();
+        let b = root.lookup("dir/b").unwrap();
+        fs.modify_disk_inode(b.id(), |d| d.size = (SECTOR_SIZE * 45) as u32)
+            .unwrap();
+        let c = root.lookup("dir/c").unwrap();
+        fs.dealloc_inode(c.id()).unwrap();
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert!(problems.contains(&Problem::SizeMismatch {
+            inode: b.id(),
+            size: (SECTOR_SIZE * 45) as u32,
+            valid_size: (SECTOR_SIZE * 40) as u32,
+        }));
+        assert!(problems.iter().any(|p| matches!(
+            p,
+            Problem::DanglingEntry { name, .. } if name == "c"
+        )));
+        repair(&fs);
+        assert_eq!(SECTOR_SIZE * 40, b.size().unwrap());
+        let dir = root.lookup("dir").unwrap();
+        assert_eq!(vec!["a", "b"], dir.ls().unwrap());
+    }
+
+    #[test]
+    fn test_link_count_problems() {
+        let (_inner, fs) = sample_fs();
+        let root = fs.root_dir();
+        let a = root.lookup("dir/a").unwrap();
+        root.link("a2", &a).unwrap();
+        root.symlink("s", "dir/b").unwrap();
+        assert!(fsck(&fs, false).unwrap().is_clean());
+
+        fs.modify_disk_inode(a.id(), |d| d.nlink = 1).unwrap();
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert_eq!(
+            vec![Problem::LinkCount {
+                inode: a.id(),
+                nlink: 1,
+                links: 2,
+            }],
+            problems
+        );
+        repair(&fs);
+        assert_eq!(2, a.stat().unwrap().nlink);
+        // a directory linked twice keeps only the first entry
+        let dir = root.lookup("dir").unwrap();
+        let entry = crate::inode::DirEntry::new("dir2", dir.id());
+        root.write_at(root.size().unwrap(), entry.as_bytes())
+            .unwrap();
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert!(problems.iter().any(|p| matches!(
+            p,
+            Problem::DanglingEntry { name, .. } if name == "dir2"
+        )));
+        repair(&fs);
+        assert!(root.find("dir2").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_orphans() {
+        let (_inner, fs) = sample_fs();
+        let root = fs.root_dir();
+        // an unlinked open file is in use, not lost
+        let a = root.lookup("dir/a").unwrap();
+        let blk = first_block(&a, &fs);
+        root.lookup("dir").unwrap().remove("a").unwrap();
+        assert!(fsck(&fs, true).unwrap().is_clean());
+        assert_eq!(blk, first_block(&a, &fs));
+        drop(a);
+        assert!(fsck(&fs, false).unwrap().is_clean());
+        // a crash before the last handle went leaves the inode unnamed
+        let b = root.lookup("dir/b").unwrap();
+        let id = b.id();
+        root.lookup("dir").unwrap().remove("b").unwrap();
+        core::mem::forget(b);
+        fs.open.lock().remove(&id);
+        let problems = fsck(&fs, false).unwrap().problems;
+        assert!(problems.contains(&Problem::Orphan(id)));
+        repair(&fs);
+        let file_type = fs.read_disk_inode(id, |d| d.file_type).unwrap();
+        assert_eq!(FileType::IdleHead, file_type);
+    }
+}
@@ -0,0 +1,1050 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use log::error;
+
+use super::types::*;
+use crate::jfs::{
+    FileType, StatFs, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, FEATURE_INCOMPAT_EXTENTS, INODE_EXTENTS,
+    JFS,
+};
+use crate::time::now;
+
+pub const NAME_LIMIT: usize = 28;
+pub(crate) const DIR_ENTRY_SIZE: usize = size_of::<DirEntry>();
+// atime is only refreshed when older than mtime or than a day, like relatime
+const RELATIME_SECS: u64 = 24 * 60 * 60;
+// access bits checked by `permits`, the same for the owner, group and other classes
+pub const MAY_READ: u16 = 0o4;
+pub const MAY_WRITE: u16 = 0o2;
+pub const MAY_EXEC: u16 = 0o1;
+// resize in steps, so one transaction never logs more blocks than the journal holds
+const RESIZE_STEP_BLOCKS: usize = 8;
+// symlinks one lookup follows before it gives up
+pub const SYMLINK_LIMIT: usize = 8;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct DirEntry {
+    name: [u8; NAME_LIMIT],
+    pub inode: u32,
+}
+
+impl DirEntry {
+    pub fn empty() -> Self {
+        Self {
+            name: [0; NAME_LIMIT],
+            inode: 0,
+        }
+    }
+    pub fn new(name: &str, inode: u32) -> Self {
+        let mut e = Self::empty();
+        e.name[..name.len()].copy_from_slice(name.as_bytes());
+        e.inode = inode;
+        e
+    }
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(NAME_LIMIT);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, DIR_ENTRY_SIZE) }
+    }
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut Self as *mut u8, DIR_ENTRY_SIZE) }
+    }
+}
+
+// a handle on an inode, the inode outlives its last link until the last
+// handle is dropped, the way an open file does on unix
+pub struct Inode {
+    id: u32,
+    fs: Arc<JFS>,
+}
+
+impl Clone for Inode {
+    fn clone(&self) -> Self {
+        Self::new(self.id, Arc::clone(&self.fs))
+    }
+}
+
+impl Drop for Inode {
+    fn drop(&mut self) {
+        // freed while this handle still counts, so the handles the free
+        // makes on the way never see the count drop to zero
+        if self.only_handle() {
+            if let Err(e) = self.free_if_orphan() {
+                error!("free inode {} failed: {:?}", self.id, e);
+            }
+        }
+        let mut open = self.fs.open.lock();
+        let cnt = open.get_mut(&self.id).unwrap();
+        *cnt -= 1;
+        if *cnt == 0 {
+            open.remove(&self.id);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub ino: u32,
+    pub file_type: FileType,
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub block_size: u32,
+    // blocks held by the inode, table blocks included
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl Inode {
+    pub(crate) fn new(id: u32, fs: Arc<JFS>) -> Self {
+        *fs.open.lock().entry(id).or_insert(0) += 1;
+        Self { id, fs }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn file_type(&self) -> IOResult<FileType> {
+        self.fs.read_disk_inode(self.id, |d| d.file_type)
+    }
+
+    pub fn is_dir(&self) -> IOResult<bool> {
+        self.fs.read_disk_inode(self.id, |d| d.is_dir())
+    }
+
+    pub fn size(&self) -> IOResult<usize> {
+        self.fs.read_disk_inode(self.id, |d| d.size as usize)
+    }
+
+    pub fn uses_extents(&self) -> IOResult<bool> {
+        self.fs.read_disk_inode(self.id, |d| d.is_extents())
+    }
+
+    // choose how an empty inode maps its blocks, with extents or with block tables
+    pub fn set_extents(&self, on: bool) -> IOResult<()> {
+        if self.size()? != 0 {
+            return Err(IOError::InvalidArgument);
+        }
+        self.fs.transaction(|| {
+            if on {
+                self.fs.set_incompat(FEATURE_INCOMPAT_EXTENTS)?;
+            }
+            self.fs.modify_disk_inode(self.id, |d| {
+                d.block0s.fill(0);
+                d.block1 = 0;
+                d.block2 = 0;
+                match on {
+                    true => d.flags |= INODE_EXTENTS,
+                    false => d.flags &= !INODE_EXTENTS,
+                }
+            })
+        })
+    }
+
+    pub fn stat(&self) -> IOResult<Stat> {
+        let fs = &self.fs;
+        fs.read_disk_inode(self.id, |d| Stat {
+            ino: self.id,
+            file_type: d.file_type,
+            mode: d.mode,
+            nlink: d.nlink,
+            uid: d.uid,
+            gid: d.gid,
+            size: d.size as u64,
+            block_size: fs.block_size as u32,
+            blocks: d.held_blocks(fs) as u64,
+            atime: d.atime,
+            mtime: d.mtime,
+            ctime: d.ctime,
+        })
+    }
+
+    // usage of the fs the inode lives on
+    pub fn statfs(&self) -> IOResult<StatFs> {
+        self.fs.statfs()
+    }
+
+    pub fn chmod(&self, mode: u16) -> IOResult<()> {
+        self.fs.modify_disk_inode(self.id, |d| {
+            d.mode = mode & 0o7777;
+            d.ctime = now();
+        })
+    }
+
+    pub fn chown(&self, uid: u32, gid: u32) -> IOResult<()> {
+        self.fs.modify_disk_inode(self.id, |d| {
+            d.uid = uid;
+            d.gid = gid;
+            d.ctime = now();
+        })
+    }
+
+    // whether a user may access the inode as `want`, a mask of MAY_* bits,
+    // root passes except for exec on an inode nobody may exec
+    pub fn permits(&self, uid: u32, gid: u32, want: u16) -> IOResult<bool> {
+        self.fs.read_disk_inode(self.id, |d| {
+            if uid == 0 {
+                return want & MAY_EXEC == 0 || d.is_dir() || d.mode & 0o111 != 0;
+            }
+            let class = if uid == d.uid {
+                d.mode >> 6
+            } else if gid == d.gid {
+                d.mode >> 3
+            } else {
+                d.mode
+            };
+            class & want == want
+        })
+    }
+
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> IOResult<usize> {
+        let fs = &self.fs;
+        let bs = fs.block_size;
+        let read = fs.read_disk_inode(self.id, |d| {
+            let end = (d.size as usize).min(offset + buf.len());
+            let mut pos = offset;
+            while pos < end {
+                let (blk_id, run) = d.data_run(pos / bs, fs)?;
+                // whole blocks contiguous on disk are read in one go
+                let cnt = run.min((end - pos) / bs);
+                if pos.is_multiple_of(bs) && cnt > 1 {
+                    let dst = &mut buf[pos - offset..pos - offset + cnt * bs];
+                    fs.read_blocks(blk_id, dst)?;
+                    pos += cnt * bs;
+                    continue;
+                }
+                let blk_end = (pos / bs + 1) * bs;
+                let to_read = blk_end.min(end) - pos;
+                let dst = &mut buf[pos - offset..pos - offset + to_read];
+                fs.get_block(blk_id)?.lock().read_slice(|data: &[u8]| {
+                    dst.copy_from_slice(&data[pos % bs..pos % bs + to_read]);
+                });
+                pos += to_read;
+            }
+            Ok(end.saturating_sub(offset))
+        })??;
+        let now = now();
+        let stale = fs.read_disk_inode(self.id, |d| {
+            now > d.atime && (d.atime <= d.mtime || now - d.atime >= RELATIME_SECS)
+        })?;
+        if stale {
+            fs.modify_disk_inode(self.id, |d| d.atime = now)?;
+        }
+        Ok(read)
+    }
+
+    // the whole content, e.g. of a program about to be loaded
+    pub fn read_all(&self) -> IOResult<Vec<u8>> {
+        let mut buf = vec![0u8; self.size()?];
+        let n = self.read_at(0, &mut buf)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> IOResult<usize> {
+        let end = offset + buf.len();
+        if end > self.size()? {
+            self.resize(end)?;
+        }
+        let fs = &self.fs;
+        let bs = fs.block_size;
+        fs.read_disk_inode(self.id, |d| {
+            let mut pos = offset;
+            while pos < end {
+                let blk_id = d.data_block(pos / bs, fs)?;
+                let blk_end = (pos / bs + 1) * bs;
+                let to_write = blk_end.min(end) - pos;
+                let src = &buf[pos - offset..pos - offset + to_write];
+                fs.get_block(blk_id)?.lock().write_slice(|data: &mut [u8]| {
+                    data[pos % bs..pos % bs + to_write].copy_from_slice(src);
+                });
+                pos += to_write;
+            }
+            Ok(())
+        })??;
+        fs.modify_disk_inode(self.id, |d| d.touch())?;
+        Ok(buf.len())
+    }
+
+    // grow with zeros or shrink to `size` bytes
+    pub fn resize(&self, size: usize) -> IOResult<()> {
+        let step_size = RESIZE_STEP_BLOCKS * self.fs.block_size;
+        let max_blocks = self
+            .fs
+            .read_disk_inode(self.id, |d| d.max_blocks(&self.fs))?;
+        if size > u32::MAX as usize || size.div_ceil(self.fs.block_size) > max_blocks {
+            return Err(IOError::FileTooLarge);
+        }
+        loop {
+            let old_size = self.size()?;
+            let step = if size < old_size {
+                size.max(old_size.saturating_sub(step_size))
+            } else {
+                size.min(old_size + step_size)
+            };
+            if step == old_size {
+                return Ok(());
+            }
+            self.fs.transaction(|| self.resize_step(step as u32))?;
+        }
+    }
+
+    fn resize_step(&self, size: u32) -> IOResult<()> {
+        let fs = &self.fs;
+        let old_size = fs.read_disk_inode(self.id, |d| d.size)?;
+        if size < old_size {
+            let freed = fs.modify_disk_inode(self.id, |d| {
+                d.touch();
+                d.dec_size(size, fs)
+            })??;
+            for b in freed {
+                fs.dealloc_block(b)?;
+            }
+            return Ok(());
+        }
+        // the tail of the last block may hold stale bytes from an earlier shrink
+        let tail = old_size as usize % fs.block_size;
+        if tail != 0 {
+            let blk_id = fs.read_disk_inode(self.id, |d| {
+                d.data_block(old_size as usize / fs.block_size, fs)
+            })??;
+            fs.get_block(blk_id)?
+                .lock()
+                .write_slice(|data: &mut [u8]| data[tail..].fill(0));
+        }
+        let (needed, needs_leaf) = fs.read_disk_inode(self.id, |d| {
+            let needed = d.blocks_for(size, fs) - d.blocks_for(old_size, fs);
+            let needs_leaf = d.is_extents() && d.needs_leaf(needed, fs)?;
+            Ok((needed, needs_leaf))
+        })??;
+        let mut blocks = fs.alloc_blocks(needed + needs_leaf as usize)?;
+        // the leaf comes last, so it does not split a run of data blocks
+        let mut spare = match needs_leaf {
+            true => blocks.pop(),
+            false => None,
+        };
+        for &b in blocks.iter() {
+            fs.get_block(b)?
+                .lock()
+                .write_slice(|data: &mut [u8]| data.fill(0));
+        }
+        fs.modify_disk_inode(self.id, |d| {
+            d.touch();
+            d.inc_size(size, blocks, &mut spare, fs)
+        })??;
+        match spare {
+            Some(b) => fs.dealloc_block(b),
+            None => Ok(()),
+        }
+    }
+
+    fn dir_entries(&self) -> IOResult<Vec<DirEntry>> {
+        if !self.is_dir()? {
+            return Err(IOError::NotDirectory);
+        }
+        let size = self.size()?;
+        let mut rt = vec![DirEntry::empty(); size / DIR_ENTRY_SIZE];
+        for (i, e) in rt.iter_mut().enumerate() {
+            self.read_at(i * DIR_ENTRY_SIZE, e.as_bytes_mut())?;
+        }
+        Ok(rt)
+    }
+
+    pub fn ls(&self) -> IOResult<Vec<String>> {
+        Ok(self
+            .dir_entries()?
+            .iter()
+            .map(|e| String::from(e.name()))
+            .collect())
+    }
+
+    pub fn find(&self, name: &str) -> IOResult<Option<Inode>> {
+        Ok(self
+            .dir_entries()?
+            .iter()
+            .find(|e| e.name() == name)
+            .map(|e| Inode::new(e.inode, Arc::clone(&self.fs))))
+    }
+
+    // resolve a '/' separated path relative to this inode, following symlinks
+    pub fn lookup(&self, path: &str) -> IOResult<Inode> {
+        self.walk(path, true, |_| Ok(()))
+    }
+
+    // resolve a path relative to this inode, a symlink as the last component is
+    // only followed when follow is set, search is called on every directory
+    // before a name is looked up in it and may refuse with an error
+    pub fn walk(
+        &self,
+        path: &str,
+        follow: bool,
+        mut search: impl FnMut(&Inode) -> IOResult<()>,
+    ) -> IOResult<Inode> {
+        // the names left to resolve, the next one last
+        let mut names: Vec<String> = split_path(path).rev().map(String::from).collect();
+        // the directories passed, so ".." can go back up; directories do not
+        // record their parent, so ".." never climbs above this inode
+        let mut parents = Vec::new();
+        let mut cur = self.clone();
+        let mut links = 0;
+        while let Some(name) = names.pop() {
+            match name.as_str() {
+                "." => continue,
+                ".." => {
+                    cur = parents.pop().unwrap_or(cur);
+                    continue;
+                }
+                _ => {}
+            }
+            if !cur.is_dir()? {
+                return Err(IOError::NotDirectory);
+            }
+            search(&cur)?;
+            let next = cur.find(&name)?.ok_or(IOError::NotFound)?;
+            if (follow || !names.is_empty()) && next.file_type()? == FileType::Symlink {
+                links += 1;
+                if links > SYMLINK_LIMIT {
+                    return Err(IOError::SymlinkLoop);
+                }
+                let target = next.readlink()?;
+                if target.starts_with('/') {
+                    parents.clear();
+                    cur = self.fs.root_dir();
+                }
+                names.extend(split_path(&target).rev().map(String::from));
+                continue;
+            }
+            parents.push(core::mem::replace(&mut cur, next));
+        }
+        Ok(cur)
+    }
+
+    pub fn create(&self, name: &str, file_type: FileType) -> IOResult<Inode> {
+        let mode = match file_type {
+            FileType::Directory => DEFAULT_DIR_MODE,
+            _ => DEFAULT_FILE_MODE,
+        };
+        self.create_as(name, file_type, mode, 0, 0)
+    }
+
+    // create an inode owned by uid and gid
+    pub fn create_as(
+        &self,
+        name: &str,
+        file_type: FileType,
+        mode: u16,
+        uid: u32,
+        gid: u32,
+    ) -> IOResult<Inode> {
+        if !matches!(file_type, FileType::File | FileType::Directory) {
+            return Err(IOError::Unknown);
+        }
+        self.new_child(name, file_type, mode, uid, gid)
+    }
+
+    fn new_child(
+        &self,
+        name: &str,
+        file_type: FileType,
+        mode: u16,
+        uid: u32,
+        gid: u32,
+    ) -> IOResult<Inode> {
+        check_name(name)?;
+        self.check_linked()?;
+        self.fs.transaction(|| {
+            if self.find(name)?.is_some() {
+                return Err(IOError::AlreadyExists);
+            }
+            let id = self.fs.alloc_inode()?;
+            let extents = self.fs.extents;
+            self.fs.modify_disk_inode(id, |d| {
+                d.init(file_type);
+                if extents {
+                    d.flags |= INODE_EXTENTS;
+                }
+                d.mode = mode & 0o7777;
+                d.uid = uid;
+                d.gid = gid;
+            })?;
+            let entry = DirEntry::new(name, id);
+            if let Err(e) = self.write_at(self.size()?, entry.as_bytes()) {
+                self.fs.dealloc_inode(id)?;
+                return Err(e);
+            }
+            Ok(Inode::new(id, Arc::clone(&self.fs)))
+        })
+    }
+
+    pub fn symlink(&self, name: &str, target: &str) -> IOResult<Inode> {
+        self.symlink_as(name, target, 0, 0)
+    }
+
+    // create a symlink to target owned by uid and gid, the target is not checked
+    pub fn symlink_as(&self, name: &str, target: &str, uid: u32, gid: u32) -> IOResult<Inode> {
+        // one block keeps the whole symlink in a single transaction
+        if target.is_empty() || target.len() > self.fs.block_size || target.contains('\0') {
+            return Err(IOError::BadFileName);
+        }
+        self.fs.transaction(|| {
+            let link = self.new_child(name, FileType::Symlink, 0o777, uid, gid)?;
+            if let Err(e) = link.write_at(0, target.as_bytes()) {
+                self.remove(name)?;
+                return Err(e);
+            }
+            Ok(link)
+        })
+    }
+
+    pub fn readlink(&self) -> IOResult<String> {
+        if self.file_type()? != FileType::Symlink {
+            return Err(IOError::InvalidArgument);
+        }
+        let mut buf = vec![0u8; self.size()?];
+        let n = self.read_at(0, &mut buf)?;
+        buf.truncate(n);
+        String::from_utf8(buf).map_err(|_| IOError::CorruptedFS)
+    }
+
+    // add name as one more link to inode, directories can not be linked
+    pub fn link(&self, name: &str, inode: &Inode) -> IOResult<()> {
+        check_name(name)?;
+        if !Arc::ptr_eq(&self.fs, &inode.fs) {
+            return Err(IOError::InvalidArgument);
+        }
+        if inode.is_dir()? {
+            return Err(IOError::IsDirectory);
+        }
+        self.check_linked()?;
+        self.fs.transaction(|| {
+            if self.find(name)?.is_some() {
+                return Err(IOError::AlreadyExists);
+            }
+            self.write_at(self.size()?, DirEntry::new(name, inode.id).as_bytes())?;
+            self.fs.modify_disk_inode(inode.id, |d| {
+                d.nlink += 1;
+                d.ctime = now();
+            })
+        })
+    }
+
+    pub fn remove(&self, name: &str) -> IOResult<()> {
+        let child = self.find(name)?.ok_or(IOError::NotFound)?;
+        if child.is_dir()? && child.size()? > 0 {
+            return Err(IOError::DirectoryNotEmpty);
+        }
+        child.empty_if_last()?;
+        self.fs.transaction(|| {
+            self.remove_entry(name)?;
+            child.unlink()
+        })
+    }
+
+    // move old_name to new_name in new_dir as one transaction,
+    // an existing new_name is replaced the way rename(2) does
+    pub fn rename(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> IOResult<()> {
+        check_name(new_name)?;
+        if !Arc::ptr_eq(&self.fs, &new_dir.fs) {
+            return Err(IOError::InvalidArgument);
+        }
+        new_dir.check_linked()?;
+        let src = self.find(old_name)?.ok_or(IOError::NotFound)?;
+        let src_dir = src.is_dir()?;
+        // a directory can not move below itself
+        if src_dir && src.contains(new_dir.id)? {
+            return Err(IOError::InvalidArgument);
+        }
+        let target = new_dir.find(new_name)?;
+        if let Some(t) = &target {
+            // both names already link the same inode
+            if t.id == src.id {
+                return Ok(());
+            }
+            match (src_dir, t.is_dir()?) {
+                (true, false) => return Err(IOError::NotDirectory),
+                (false, true) => return Err(IOError::IsDirectory),
+                (true, true) if t.size()? > 0 => return Err(IOError::DirectoryNotEmpty),
+                _ => {}
+            }
+        }
+        if let Some(t) = &target {
+            t.empty_if_last()?;
+        }
+        self.fs.transaction(|| {
+            self.fs.modify_disk_inode(src.id, |d| d.ctime = now())?;
+            if self.id == new_dir.id && target.is_none() {
+                return self.set_entry(old_name, new_name, src.id);
+            }
+            // the new name is in place before the old one goes,
+            // so a failure leaves the old name behind
+            match &target {
+                Some(_) => new_dir.set_entry(new_name, new_name, src.id)?,
+                None => {
+                    let entry = DirEntry::new(new_name, src.id);
+                    new_dir.write_at(new_dir.size()?, entry.as_bytes())?;
+                }
+            }
+            self.remove_entry(old_name)?;
+            match &target {
+                Some(t) => t.unlink(),
+                None => Ok(()),
+            }
+        })
+    }
+
+    // point the entry called name at inode id under a new name
+    fn set_entry(&self, name: &str, new_name: &str, id: u32) -> IOResult<()> {
+        let idx = self
+            .dir_entries()?
+            .iter()
+            .position(|e| e.name() == name)
+            .ok_or(IOError::NotFound)?;
+        let entry = DirEntry::new(new_name, id);
+        self.write_at(idx * DIR_ENTRY_SIZE, entry.as_bytes())?;
+        Ok(())
+    }
+
+    // drop the entry called name, the last entry moves into its slot
+    fn remove_entry(&self, name: &str) -> IOResult<()> {
+        let entries = self.dir_entries()?;
+        let idx = entries
+            .iter()
+            .position(|e| e.name() == name)
+            .ok_or(IOError::NotFound)?;
+        let last = entries.len() - 1;
+        if idx != last {
+            self.write_at(idx * DIR_ENTRY_SIZE, entries[last].as_bytes())?;
+        }
+        self.resize(last * DIR_ENTRY_SIZE)
+    }
+
+    // truncating may take several transactions, so an inode about to lose its
+    // last link and held nowhere else is emptied beforehand and a crash
+    // leaves a shorter file behind
+    fn empty_if_last(&self) -> IOResult<()> {
+        if self.only_handle() && self.fs.read_disk_inode(self.id, |d| d.nlink)? <= 1 {
+            self.resize(0)?;
+        }
+        Ok(())
+    }
+
+    // drop one link, the inode is freed along with the last one unless
+    // another handle still holds it, then the last handle frees it
+    fn unlink(&self) -> IOResult<()> {
+        let nlink = self.fs.modify_disk_inode(self.id, |d| {
+            d.nlink = d.nlink.saturating_sub(1);
+            d.ctime = now();
+            d.nlink
+        })?;
+        if nlink == 0 && self.only_handle() {
+            self.fs.dealloc_inode(self.id)?;
+        }
+        Ok(())
+    }
+
+    // the inode was unlinked while open, a crash before the last handle
+    // goes leaves an orphan that fsck frees
+    fn free_if_orphan(&self) -> IOResult<()> {
+        if !self.fs.read_disk_inode(self.id, |d| d.is_orphan())? {
+            return Ok(());
+        }
+        self.resize(0)?;
+        self.fs.transaction(|| self.fs.dealloc_inode(self.id))
+    }
+
+    fn only_handle(&self) -> bool {
+        self.fs.open.lock()[&self.id] == 1
+    }
+
+    // an unlinked directory takes no new entries
+    fn check_linked(&self) -> IOResult<()> {
+        match self.fs.read_disk_inode(self.id, |d| d.nlink)? {
+            0 => Err(IOError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    // whether the inode id is this directory or lies below it
+    fn contains(&self, id: u32) -> IOResult<bool> {
+        let mut dirs = vec![self.clone()];
+        while let Some(dir) = dirs.pop() {
+            if dir.id == id {
+                return Ok(true);
+            }
+            for e in dir.dir_entries()? {
+                let child = Inode::new(e.inode, Arc::clone(&self.fs));
+                if child.is_dir()? {
+                    dirs.push(child);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn split_path(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|n| !n.is_empty())
+}
+
+fn check_name(name: &str) -> IOResult<()> {
+    if name.is_empty()
+        || name.len() > NAME_LIMIT
+        || name.contains('/')
+        || name.contains('\0')
+        || name == "."
+        || name == ".."
+    {
+        return Err(IOError::BadFileName);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    use super::{MAY_EXEC, MAY_READ, MAY_WRITE, SYMLINK_LIMIT};
+    use crate::jfs::test::new_device;
+    use crate::jfs::{FileType, JFS};
+    use crate::types::*;
+
+    #[test]
+    fn test_file_rw() {
+        let (dev, _blk_inner) = new_device(4096);
+        let fs = Arc::new(JFS::mkfs(dev, 4096, 16).unwrap());
+        let root = fs.root_dir();
+        let f = root.create("hello", FileType::File).unwrap();
+        assert_eq!(11, f.write_at(0, b"hello world").unwrap());
+        let mut buf = [0u8; 32];
+        assert_eq!(11, f.read_at(0, &mut buf).unwrap());
+        assert_eq!(b"hello world", &buf[..11]);
+        assert_eq!(5, f.read_at(6, &mut buf).unwrap());
+        assert_eq!(0, f.read_at(100, &mut buf).unwrap());
+
+        // cross the direct, single and double indirect ranges
+        let big: vec::Vec<u8> = (0..SECTOR_SIZE * 200)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        let g = root.create("big", FileType::File).unwrap();
+        assert_eq!(big.len(), g.write_at(3, &big).unwrap());
+        let mut back = vec![0u8; big.len() + 3];
+        assert_eq!(back.len(), g.read_at(0, &mut back).unwrap());
+        assert_eq!([0, 0, 0], back[..3]);
+        assert_eq!(big, back[3..]);
+
+        // shrinking then growing again reads back zeros
+        g.resize(10).unwrap();
+        g.resize(SECTOR_SIZE * 40).unwrap();
+        let mut back = vec![0xffu8; SECTOR_SIZE * 40];
+        g.read_at(0, &mut back).unwrap();
+        assert_eq!(big[..7], back[3..10]);
+        assert!(back[10..].iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn test_dir() {
+        let (dev, _blk_inner) = new_device(1024);
+        let fs = Arc::new(JFS::mkfs(dev, 1024, 16).unwrap());
+        let root = fs.root_dir();
+        let free_inodes = fs.inode_cnt() - 1;
+        let bin = root.create("bin", FileType::Directory).unwrap();
+        for i in 0..40 {
+            let name = alloc::format!("app{}", i);
+            bin.create(&name, FileType::File)
+                .unwrap()
+                .write_at(0, name.as_bytes())
+                .unwrap();
+        }
+        assert!(matches!(
+            bin.create("app3", FileType::File),
+            Err(IOError::AlreadyExists)
+        ));
+        assert!(matches!(
+            bin.create("a/b", FileType::File),
+            Err(IOError::BadFileName)
+        ));
+        assert_eq!(vec![String::from("bin")], root.ls().unwrap());
+        assert_eq!(40, bin.ls().unwrap().len());
+        let app7 = root.lookup("/bin/app7").unwrap();
+        let mut buf = [0u8; 8];
+        let n = app7.read_at(0, &mut buf).unwrap();
+        assert_eq!(b"app7", &buf[..n]);
+        assert!(matches!(root.lookup("bin/nope"), Err(IOError::NotFound)));
+        assert!(matches!(
+            root.remove("bin"),
+            Err(IOError::DirectoryNotEmpty)
+        ));
+        for i in 0..40 {
+            bin.remove(&alloc::format!("app{}", i)).unwrap();
+        }
+        assert!(bin.ls().unwrap().is_empty());
+        root.remove("bin").unwrap();
+        assert!(root.ls().unwrap().is_empty());
+        drop((bin, app7));
+        // every inode went back to the free list
+        for _ in 0..free_inodes {
+            fs.alloc_inode().unwrap();
+        }
+        assert!(matches!(fs.alloc_inode(), Err(IOError::DiskFull)));
+    }
+
+    static NOW: AtomicU64 = AtomicU64::new(1000);
+
+    fn clock() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn test_stat() {
+        crate::time::set_clock(clock);
+        let (dev, _blk_inner) = new_device(1024);
+        let fs = Arc::new(JFS::mkfs(dev, 1024, 4).unwrap());
+        let root = fs.root_dir();
+        let f = root.create_as("f", FileType::File, 0o640, 7, 8).unwrap();
+        let st = f.stat().unwrap();
+        assert_eq!((0o640, 7, 8, 1), (st.mode, st.uid, st.gid, st.nlink));
+        assert_eq!((1000, 1000, 1000), (st.atime, st.mtime, st.ctime));
+        assert_eq!(FileType::File, st.file_type);
+        assert_eq!(1000, root.stat().unwrap().mtime);
+
+        NOW.store(2000, Ordering::Relaxed);
+        f.write_at(0, &[1; 600]).unwrap();
+        let st = f.stat().unwrap();
+        assert_eq!(
+            (600, 2, SECTOR_SIZE as u32),
+            (st.size, st.blocks, st.block_size)
+        );
+        assert_eq!((1000, 2000, 2000), (st.atime, st.mtime, st.ctime));
+        // a read after the last write refreshes atime, the next one does not
+        NOW.store(3000, Ordering::Relaxed);
+        f.read_at(0, &mut [0; 4]).unwrap();
+        NOW.store(4000, Ordering::Relaxed);
+        f.read_at(0, &mut [0; 4]).unwrap();
+        assert_eq!(3000, f.stat().unwrap().atime);
+
+        f.chmod(0o604).unwrap();
+        let st = f.stat().unwrap();
+        assert_eq!((0o604, 2000, 4000), (st.mode, st.mtime, st.ctime));
+        // owner, group and others are checked by their own bits
+        assert!(f.permits(7, 1, MAY_READ | MAY_WRITE).unwrap());
+        assert!(!f.permits(7, 1, MAY_EXEC).unwrap());
+        assert!(!f.permits(9, 8, MAY_READ).unwrap());
+        assert!(f.permits(9, 9, MAY_READ).unwrap());
+        assert!(!f.permits(9, 9, MAY_WRITE).unwrap());
+        // root reads and writes anything but only execs what someone may exec
+        assert!(f.permits(0, 0, MAY_READ | MAY_WRITE).unwrap());
+        assert!(!f.permits(0, 0, MAY_EXEC).unwrap());
+        f.chmod(0o700).unwrap();
+        assert!(f.permits(0, 0, MAY_EXEC).unwrap());
+        f.chown(9, 9).unwrap();
+        assert!(f.permits(9, 1, MAY_EXEC).unwrap());
+        assert!(!f.permits(7, 8, MAY_READ).unwrap());
+    }
+
+    fn read_all(f: &super::Inode) -> vec::Vec<u8> {
+        let mut buf = vec![0u8; f.size().unwrap()];
+        f.read_at(0, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_links() {
+        let (dev, _blk_inner) = new_device(1024);
+        let fs = Arc::new(JFS::mkfs(dev, 1024, 4).unwrap());
+        let root = fs.root_dir();
+        let free_inodes = fs.inode_cnt() - 1;
+        let dir = root.create("dir", FileType::Directory).unwrap();
+        let f = dir.create("f", FileType::File).unwrap();
+        f.write_at(0, b"shared").unwrap();
+        root.link("g", &f).unwrap();
+        assert_eq!(2, f.stat().unwrap().nlink);
+        assert!(matches!(root.link("g", &f), Err(IOError::AlreadyExists)));
+        assert!(matches!(root.link("d", &dir), Err(IOError::IsDirectory)));
+        // the data lives on until the last name is gone
+        dir.remove("f").unwrap();
+        let g = root.lookup("g").unwrap();
+        assert_eq!(f.id(), g.id());
+        assert_eq!(1, g.stat().unwrap().nlink);
+        assert_eq!(b"shared", &read_all(&g)[..]);
+        root.remove("g").unwrap();
+        root.remove("dir").unwrap();
+        drop((dir, f, g));
+        for _ in 0..free_inodes {
+            fs.alloc_inode().unwrap();
+        }
+        assert!(matches!(fs.alloc_inode(), Err(IOError::DiskFull)));
+    }
+
+    #[test]
+    fn test_unlink_open() {
+        let (dev, _blk_inner) = new_device(1024);
+        let fs = Arc::new(JFS::mkfs(dev, 1024, 4).unwrap());
+        let root = fs.root_dir();
+        let free_inodes = fs.inode_cnt() - 1;
+        let f = root.create("f", FileType::File).unwrap();
+        f.write_at(0, &[3; SECTOR_SIZE * 20]).unwrap();
+        root.remove("f").unwrap();
+        assert!(matches!(root.lookup("f"), Err(IOError::NotFound)));
+        // the handle keeps reading and writing the unnamed inode
+        assert_eq!(0, f.stat().unwrap().nlink);
+        f.write_at(SECTOR_SIZE * 20, b"tail").unwrap();
+        let data = read_all(&f);
+        assert_eq!(SECTOR_SIZE * 20 + 4, data.len());
+        assert!(data[..SECTOR_SIZE * 20].iter().all(|&c| c == 3));
+        assert_eq!(b"tail", &data[SECTOR_SIZE * 20..]);
+        assert!(crate::fsck::fsck(&fs, false).unwrap().is_clean());
+        let g = root.create("g", FileType::File).unwrap();
+        assert_ne!(f.id(), g.id());
+        // a clone is one more handle, the inode waits for both
+        let f2 = f.clone();
+        drop(f);
+        assert_eq!(b"tail", &read_all(&f2)[SECTOR_SIZE * 20..]);
+        drop(f2);
+        assert!(crate::fsck::fsck(&fs, false).unwrap().is_clean());
+        root.remove("g").unwrap();
+        drop(g);
+        for _ in 0..free_inodes {
+            fs.alloc_inode().unwrap();
+        }
+        assert!(matches!(fs.alloc_inode(), Err(IOError::DiskFull)));
+    }
+
+    #[test]
+    fn test_symlink() {
+        let (dev, _blk_inner) = new_device(1024);
+        let fs = Arc::new(JFS::mkfs(dev, 1024, 8).unwrap());
+        let root = fs.root_dir();
+        let bin = root.create("bin", FileType::Directory).unwrap();
+        let sh = bin.create("sh", FileType::File).unwrap();
+        sh.write_at(0, b"shell").unwrap();
+        let usr = root.create("usr", FileType::Directory).unwrap();
+        usr.symlink("bin", "../bin").unwrap();
+        root.symlink("sh", "/usr/bin/sh").unwrap();
+        let link = root.symlink("self", "./bin/.././self").unwrap();
+        assert_eq!(FileType::Symlink, link.file_type().unwrap());
+        assert_eq!(1, link.stat().unwrap().nlink);
+        assert_eq!("./bin/.././self", link.readlink().unwrap());
+        assert!(matches!(sh.readlink(), Err(IOError::InvalidArgument)));
+
+        assert_eq!(sh.id(), root.lookup("usr/bin/sh").unwrap().id());
+        assert_eq!(sh.id(), root.lookup("sh").unwrap().id());
+        assert_eq!(b"shell", &read_all(&root.lookup("sh").unwrap())[..]);
+        // the last component is only followed on request
+        let sh_link = root.walk("sh", false, |_| Ok(())).unwrap();
+        assert_eq!(FileType::Symlink, sh_link.file_type().unwrap());
+        assert!(matches!(root.lookup("self"), Err(IOError::SymlinkLoop)));
+        assert!(matches!(root.lookup("sh/x"), Err(IOError::NotDirectory)));
+        usr.symlink("gone", "nowhere").unwrap();
+        assert!(matches!(root.lookup("usr/gone"), Err(IOError::NotFound)));
+        // a chain exactly as long as the limit still resolves
+        for i in 0..SYMLINK_LIMIT {
+            let target = alloc::format!("l{}", i + 1);
+            root.symlink(&alloc::format!("l{}", i), &target).unwrap();
+        }
+        root.link(&alloc::format!("l{}", SYMLINK_LIMIT), &sh)
+            .unwrap();
+        assert_eq!(sh.id(), root.lookup("l0").unwrap().id());
+        root.symlink("l", "l0").unwrap();
+        assert!(matches!(root.lookup("l"), Err(IOError::SymlinkLoop)));
+        // the search hook sees every directory passed through
+        let mut searched = vec::Vec::new();
+        root.walk("sh", true, |d| {
+            searched.push(d.id());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            vec![root.id(), root.id(), usr.id(), root.id(), bin.id()],
+            searched
+        );
+        assert!(matches!(
+            root.walk("bin/sh", true, |_| Err(IOError::PermissionDenied)),
+            Err(IOError::PermissionDenied)
+        ));
+    }
+
+    #[test]
+    fn test_rename() {
+        let (dev, _blk_inner) = new_device(1024);
+        let fs = Arc::new(JFS::mkfs(dev, 1024, 4).unwrap());
+        let root = fs.root_dir();
+        let a = root.create("a", FileType::Directory).unwrap();
+        let b = root.create("b", FileType::Directory).unwrap();
+        let f = a.create("f", FileType::File).unwrap();
+        f.write_at(0, b"f").unwrap();
+        a.create("x", FileType::File).unwrap();
+
+        a.rename("f", &a, "g").unwrap();
+        assert_eq!(vec!["g", "x"], a.ls().unwrap());
+        a.rename("g", &b, "h").unwrap();
+        assert_eq!(vec!["x"], a.ls().unwrap());
+        assert_eq!(f.id(), root.lookup("b/h").unwrap().id());
+        assert!(matches!(a.rename("g", &b, "h"), Err(IOError::NotFound)));
+
+        // replacing a file frees it once its last link and handle are gone
+        let old = b.create("old", FileType::File).unwrap();
+        old.write_at(0, &[1; SECTOR_SIZE * 4]).unwrap();
+        let old_id = old.id();
+        b.rename("h", &b, "old").unwrap();
+        assert_eq!(vec!["old"], b.ls().unwrap());
+        assert_eq!(b"f", &read_all(&b.lookup("old").unwrap())[..]);
+        assert_eq!(0, old.stat().unwrap().nlink);
+        drop(old);
+        let file_type = fs.read_disk_inode(old_id, |d| d.file_type).unwrap();
+        assert_eq!(FileType::IdleHead, file_type);
+        // two names for one inode stay as they are
+        b.link("again", &f).unwrap();
+        b.rename("old", &b, "again").unwrap();
+        assert_eq!(vec!["old", "again"], b.ls().unwrap());
+
+        assert!(matches!(
+            root.rename("a", &a, "a"),
+            Err(IOError::InvalidArgument)
+        ));
+        let sub = a.create("sub", FileType::Directory).unwrap();
+        assert!(matches!(
+            root.rename("a", &sub, "a"),
+            Err(IOError::InvalidArgument)
+        ));
+        assert!(matches!(
+            root.rename("a", &root, "b"),
+            Err(IOError::DirectoryNotEmpty)
+        ));
+        assert!(matches!(
+            b.rename("old", &a, "sub"),
+            Err(IOError::IsDirectory)
+        ));
+        assert!(matches!(
+            a.rename("sub", &b, "old"),
+            Err(IOError::NotDirectory)
+        ));
+        // an empty directory may be replaced by another one
+        let empty = root.create("empty", FileType::Directory).unwrap();
+        a.rename("sub", &root, "empty").unwrap();
+        assert_eq!(sub.id(), root.lookup("empty").unwrap().id());
+        assert_eq!(0, empty.stat().unwrap().nlink);
+        assert!(matches!(
+            empty.create("x", FileType::File),
+            Err(IOError::NotFound)
+        ));
+        let empty_id = empty.id();
+        drop(empty);
+        let file_type = fs.read_disk_inode(empty_id, |d| d.file_type).unwrap();
+        assert_eq!(FileType::IdleHead, file_type);
+        root.rename("b", &a, "b").unwrap();
+        assert_eq!(vec!["a", "empty"], root.ls().unwrap());
+        assert_eq!(f.id(), root.lookup("a/b/again").unwrap().id());
+        assert!(crate::fsck::fsck(&fs, false).unwrap().is_clean());
+    }
+}
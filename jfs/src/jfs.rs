@@ -1,108 +1,367 @@
-use alloc::sync::Arc;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use log::error;
 use spin::Mutex;
 
 use super::types::*;
 use crate::{
-    cache::{get_block, BlockCache},
+    bitmap::{bitmap_blocks, Bitmap, Bitmaps},
+    cache::{get_block, open_device, pin_block, read_run, remove_device, BlockCache, CacheConfig},
     device::BlkDev,
+    inode::{Inode, NAME_LIMIT},
+    journal::{Journal, JOURNAL_BLOCKS},
+    time::now,
 };
 
 const MAGIC: [u8; 4] = [b'\x18', b'j', b'f', b's'];
-const InodeSize: usize = 128;
-const InodePerBlock: usize = BlockSize / InodeSize;
-const BlockInBlock: usize = BlockSize / size_of::<u32>();
+pub(crate) const VERSION: u32 = 5;
+const INODE_SIZE: usize = 128;
+pub(crate) const ROOT_INODE: u32 = 0;
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+pub const LABEL_LEN: usize = 32;
+// new inodes map their blocks with extents
+pub(crate) const FEATURE_COMPAT_EXTENTS_DEFAULT: u32 = 1 << 0;
+// some inodes map their blocks with extents
+pub(crate) const FEATURE_INCOMPAT_EXTENTS: u32 = 1 << 0;
+// free inodes and blocks are tracked in bitmaps instead of free lists
+pub(crate) const FEATURE_INCOMPAT_BITMAPS: u32 = 1 << 1;
+// incompatible features this jfs understands
+pub(crate) const FEATURE_INCOMPAT_SUPPORTED: u32 =
+    FEATURE_INCOMPAT_EXTENTS | FEATURE_INCOMPAT_BITMAPS;
+// DiskInode flags
+pub(crate) const INODE_EXTENTS: u8 = 1 << 0;
+
 #[repr(C)]
-struct SuperBlock {
+#[derive(Clone, Copy)]
+pub(crate) struct SuperBlock {
     magic: [u8; 4],
-    version: u32,
+    pub version: u32,
+    pub block_size: u32,
     pub total_blocks: u32,
     pub inode_blocks: u32,
     pub data_blocks: u32,
+    pub journal_blocks: u32,
+    // both zero without the bitmap layout
+    pub inode_bitmap_blocks: u32,
+    pub block_bitmap_blocks: u32,
+    // kept up to date by every alloc and dealloc, for statfs
+    pub free_blocks: u32,
+    pub free_inodes: u32,
+    // an unknown compat feature can be ignored, an unknown incompat one can not
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub uuid: [u8; 16],
+    pub label: [u8; LABEL_LEN],
+    pub mount_count: u32,
+    // cleared while mounted, set again on unmount
+    pub clean: u32,
 }
 
+// the super block shares block 0 with the IdleHead and BlockGC inodes
+const _: () = assert!(size_of::<SuperBlock>() <= 2 * INODE_SIZE);
+
 impl SuperBlock {
-    fn is_valid(&self) -> bool {
-        self.magic == MAGIC
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.version == VERSION
     }
-    fn init(&mut self, total_blocks: u32, inode_blocks: u32, data_blocks: u32) {
+    fn init(
+        &mut self,
+        total_blocks: u32,
+        inode_blocks: u32,
+        journal_blocks: u32,
+        (inode_bitmap_blocks, block_bitmap_blocks): (u32, u32),
+        opts: &MkfsOptions,
+    ) {
+        let mut label = [0; LABEL_LEN];
+        label[..opts.label.len()].copy_from_slice(opts.label.as_bytes());
         *self = Self {
             magic: MAGIC,
-            version: 1,
+            version: VERSION,
+            block_size: opts.block_size as u32,
             total_blocks,
             inode_blocks,
-            data_blocks,
+            data_blocks: total_blocks
+                - inode_blocks
+                - journal_blocks
+                - inode_bitmap_blocks
+                - block_bitmap_blocks
+                - 1,
+            journal_blocks,
+            inode_bitmap_blocks,
+            block_bitmap_blocks,
+            // counted up as mkfs frees the inodes and blocks
+            free_blocks: 0,
+            free_inodes: 0,
+            feature_compat: if opts.extents {
+                FEATURE_COMPAT_EXTENTS_DEFAULT
+            } else {
+                0
+            },
+            feature_incompat: if opts.extents {
+                FEATURE_INCOMPAT_EXTENTS
+            } else {
+                0
+            } | if opts.bitmaps {
+                FEATURE_INCOMPAT_BITMAPS
+            } else {
+                0
+            },
+            uuid: opts.uuid,
+            label,
+            mount_count: 0,
+            clean: 1,
         }
     }
+    // the super block sits at the start of sector 0 whatever the block size is
+    fn read_from(dev: &Arc<dyn BlkDev>) -> IOResult<Self> {
+        let mut buf = [0u8; SECTOR_SIZE];
+        dev.read(0, &mut buf)?;
+        Ok(unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const SuperBlock) })
+    }
+}
+
+// what mkfs writes besides the layout
+pub struct MkfsOptions<'a> {
+    pub block_size: usize,
+    pub uuid: [u8; 16],
+    pub label: &'a str,
+    // new inodes map their blocks with extents
+    pub extents: bool,
+    // track free inodes and blocks in bitmaps
+    pub bitmaps: bool,
 }
 
-struct DiskPos {
-    block_id: u32,
-    offset: usize,
+impl Default for MkfsOptions<'_> {
+    fn default() -> Self {
+        Self {
+            block_size: MIN_BLOCK_SIZE,
+            uuid: [0; 16],
+            label: "",
+            extents: false,
+            bitmaps: false,
+        }
+    }
 }
 
-const DIRECT_BLOCKS: usize = 28;
+// the super block fields of a mounted fs
+#[derive(Debug, Clone)]
+pub struct FsInfo {
+    pub block_size: usize,
+    pub total_blocks: u32,
+    pub inode_blocks: u32,
+    pub data_blocks: u32,
+    pub journal_blocks: u32,
+    pub inode_bitmap_blocks: u32,
+    pub block_bitmap_blocks: u32,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub uuid: [u8; 16],
+    pub label: String,
+    pub mount_count: u32,
+    pub clean: bool,
+}
+
+// space and inode usage, as statfs reports it
+#[derive(Debug, Clone)]
+pub struct StatFs {
+    pub block_size: usize,
+    // data blocks
+    pub blocks: u32,
+    pub free_blocks: u32,
+    pub inodes: u32,
+    pub free_inodes: u32,
+    pub name_len: usize,
+}
+
+pub(crate) struct DiskPos {
+    pub block_id: u32,
+    pub offset: usize,
+}
+
+pub(crate) const DIRECT_BLOCKS: usize = 19;
+
 #[derive(Debug)]
 #[repr(C)]
-struct DiskInode {
-    file_type: FileType,
-    size: u32,
-    block0s: [u32; DIRECT_BLOCKS],
-    block1: u32,
-    block2: u32,
+pub(crate) struct DiskInode {
+    pub file_type: FileType,
+    // INODE_* bits
+    pub flags: u8,
+    // permission bits, rwx for the owner, the group and others
+    pub mode: u16,
+    pub size: u32,
+    // seconds since the unix epoch
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub block0s: [u32; DIRECT_BLOCKS],
+    pub block1: u32,
+    pub block2: u32,
 }
 
-const _: () = assert!(InodeSize == size_of::<DiskInode>());
+const _: () = assert!(INODE_SIZE == size_of::<DiskInode>());
 
 #[test]
 fn test_size() {
-    assert_eq!(InodeSize, size_of::<DiskInode>());
+    assert_eq!(INODE_SIZE, size_of::<DiskInode>());
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum FileType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FileType {
     IdleHead = 0,
     BlockGC = 1,
     File = 2,
     Directory = 3,
+    // the content is the target path
+    Symlink = 4,
 }
 
-struct JFS {
-    inode_start_block: u32,
-    data_start_block: u32,
-    data_end_block: u32,
-    dev: Arc<dyn BlkDev>,
+impl FileType {
+    pub fn from_raw(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::IdleHead),
+            1 => Some(Self::BlockGC),
+            2 => Some(Self::File),
+            3 => Some(Self::Directory),
+            4 => Some(Self::Symlink),
+            _ => None,
+        }
+    }
 }
 
-struct Inode {
-    pos: DiskPos,
-    fs: Arc<JFS>,
+// block 0 holds the super block and the IdleHead and BlockGC inodes,
+// it is followed by the journal, the inode and data bitmaps if there are any,
+// the inode blocks and the data blocks
+#[allow(clippy::upper_case_acronyms)]
+pub struct JFS {
+    pub(crate) block_size: usize,
+    pub(crate) journal_start_block: u32,
+    pub(crate) inode_start_block: u32,
+    pub(crate) data_start_block: u32,
+    pub(crate) data_end_block: u32,
+    pub(crate) dev: Arc<dyn BlkDev>,
+    pub(crate) journal: Mutex<Journal>,
+    // new inodes map their blocks with extents
+    pub(crate) extents: bool,
+    // None with the free list layout
+    pub(crate) bitmaps: Option<Bitmaps>,
+    // live Inode handles by inode id, an inode that lost its last link
+    // is freed along with its last handle
+    pub(crate) open: Mutex<BTreeMap<u32, usize>>,
 }
 
-enum BlockPosition<'a> {
-    InINode(&'a mut u32),
-    Block(DiskPos),
-}
+impl DiskInode {
+    pub fn init(&mut self, file_type: FileType) {
+        let now = now();
+        self.file_type = file_type;
+        self.flags = 0;
+        self.mode = match file_type {
+            FileType::Directory => DEFAULT_DIR_MODE,
+            // symlink permissions are never checked
+            FileType::Symlink => 0o777,
+            _ => DEFAULT_FILE_MODE,
+        };
+        self.size = 0;
+        self.atime = now;
+        self.mtime = now;
+        self.ctime = now;
+        self.uid = 0;
+        self.gid = 0;
+        self.nlink = match file_type {
+            FileType::File | FileType::Directory | FileType::Symlink => 1,
+            _ => 0,
+        };
+        self.block0s.fill(0);
+        self.block1 = 0;
+        self.block2 = 0;
+    }
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Directory
+    }
+    // in use but without a name, it was unlinked while open
+    pub fn is_orphan(&self) -> bool {
+        self.nlink == 0
+            && matches!(
+                self.file_type,
+                FileType::File | FileType::Directory | FileType::Symlink
+            )
+    }
+    // the content changed
+    pub fn touch(&mut self) {
+        let now = now();
+        self.mtime = now;
+        self.ctime = now;
+    }
 
-struct BlockIndicator<'a> {
-    pos: BlockPosition<'a>,
-    is_block_table: bool,
-}
+    pub fn is_extents(&self) -> bool {
+        self.flags & INODE_EXTENTS != 0
+    }
 
-impl DiskInode {
-    fn inc_size(&mut self, sz: u32, new_blocks: Vec<u32>, jfs: &JFS) -> IOResult<()> {
+    // blocks needed to hold sz bytes, table blocks included but not extent leaves
+    pub fn blocks_for(&self, sz: u32, fs: &JFS) -> usize {
+        match self.is_extents() {
+            true => (sz as usize).div_ceil(fs.block_size),
+            false => Self::total_blocks(sz, fs),
+        }
+    }
+
+    // every block the inode holds
+    pub fn held_blocks(&self, fs: &JFS) -> usize {
+        self.blocks_for(self.size, fs)
+            + if self.is_extents() {
+                self.leaf_blocks()
+            } else {
+                0
+            }
+    }
+
+    // data blocks the inode can address
+    pub fn max_blocks(&self, fs: &JFS) -> usize {
+        match self.is_extents() {
+            true => u32::MAX as usize / fs.block_size,
+            false => fs.max_file_blocks(),
+        }
+    }
+
+    // an extent inode takes a leaf block from spare when its tree grows
+    pub fn inc_size(
+        &mut self,
+        sz: u32,
+        new_blocks: Vec<u32>,
+        spare: &mut Option<u32>,
+        jfs: &JFS,
+    ) -> IOResult<()> {
         assert!(sz > self.size);
-        let cur_block = Self::total_blocks(self.size);
+        if self.is_extents() {
+            for block in new_blocks {
+                self.push_extent(block, spare, jfs)?;
+            }
+            self.size = sz;
+            return Ok(());
+        }
+        let cur_block = Self::total_blocks(self.size, jfs);
         for (i, block) in new_blocks.into_iter().enumerate() {
             self.emplace_block(cur_block + i, block, jfs)?
         }
         self.size = sz;
         Ok(())
     }
-    fn dec_size(&mut self, sz: u32, jfs: &JFS) -> IOResult<Vec<u32>> {
+    pub fn dec_size(&mut self, sz: u32, jfs: &JFS) -> IOResult<Vec<u32>> {
         assert!(sz < self.size);
         let mut rt = Vec::new();
-        let cur_block = Self::total_blocks(self.size);
-        let new_block = Self::total_blocks(sz);
+        if self.is_extents() {
+            for _ in self.blocks_for(sz, jfs)..self.blocks_for(self.size, jfs) {
+                self.pop_extent(&mut rt, jfs)?;
+            }
+            self.shrink_tree(&mut rt, jfs)?;
+            self.size = sz;
+            return Ok(rt);
+        }
+        let cur_block = Self::total_blocks(self.size, jfs);
+        let new_block = Self::total_blocks(sz, jfs);
         rt.reserve(cur_block - new_block);
         for b in (new_block..cur_block).rev() {
             let poped = self.pop_block(b, jfs)?;
@@ -111,6 +370,7 @@ impl DiskInode {
             }
             rt.push(poped);
         }
+        self.size = sz;
         Ok(rt)
     }
     // block: when block=0, it will remove block, otherwise, it will add block
@@ -126,13 +386,11 @@ impl DiskInode {
             self.block1 = block;
             if block != 0 {
                 let blk_lk = fs.get_block(block)?;
-                blk_lk.lock().write(0, |bs: &mut [u32; BlockInBlock]| {
-                    bs.fill(0);
-                });
+                blk_lk.lock().write_slice(|bs: &mut [u32]| bs.fill(0));
             }
             return Ok(old);
         }
-        if at < DIRECT_BLOCKS + BlockInBlock + 1 {
+        if at < fs.l1_limit() {
             let blk_lk = fs.get_block(self.block1)?;
             blk_lk.lock().write(
                 (at - DIRECT_BLOCKS - 1) * size_of::<u32>(),
@@ -143,31 +401,26 @@ impl DiskInode {
             );
             return Ok(old);
         }
-        const L1_BLOCK_LIMIT: usize = DIRECT_BLOCKS + BlockInBlock + 1;
-        if at == L1_BLOCK_LIMIT {
+        if at == fs.l1_limit() {
             old = self.block2;
             self.block2 = block;
             if block != 0 {
                 let blk_lk = fs.get_block(block)?;
-                blk_lk.lock().write(0, |bs: &mut [u32; BlockInBlock]| {
-                    bs.fill(0);
-                });
+                blk_lk.lock().write_slice(|bs: &mut [u32]| bs.fill(0));
             }
             return Ok(old);
         }
-        const L2_BLOCK_LIMIT: usize = L1_BLOCK_LIMIT + 1 + BlockInBlock * (BlockInBlock + 1);
-        if at >= L2_BLOCK_LIMIT {
+        if at >= fs.l2_limit() {
             return Err(IOError::DiskFull);
         };
-        let pos_in_l2 = at - L1_BLOCK_LIMIT - 1;
-        let l2_bt = pos_in_l2 / (1 + BlockInBlock);
-        let off_in_l2_l1_p1 = pos_in_l2 % (1 + BlockInBlock); // offset in l2->l1, plus 1
+        let n = fs.ids_per_block();
+        let pos_in_l2 = at - fs.l1_limit() - 1;
+        let l2_bt = pos_in_l2 / (1 + n);
+        let off_in_l2_l1_p1 = pos_in_l2 % (1 + n); // offset in l2->l1, plus 1
         if off_in_l2_l1_p1 == 0 {
             if block != 0 {
                 let blk_lk = fs.get_block(block)?;
-                blk_lk.lock().write(0, |bs: &mut [u32; BlockInBlock]| {
-                    bs.fill(0);
-                });
+                blk_lk.lock().write_slice(|bs: &mut [u32]| bs.fill(0));
             }
             let l2_blk = fs.get_block(self.block2)?;
             l2_blk
@@ -205,127 +458,500 @@ impl DiskInode {
         self.replace_block_at(at, 0, fs)
     }
 
-    fn total_blocks(sz: u32) -> usize {
-        let eblocks = (sz as usize).div_ceil(BlockSize);
+    // the block id of the i-th data block and how many blocks from it on
+    // are contiguous on disk, at least 1
+    pub fn data_run(&self, i: usize, fs: &JFS) -> IOResult<(u32, usize)> {
+        match self.is_extents() {
+            true => self.extent_run(i, fs),
+            false => Ok((self.data_block(i, fs)?, 1)),
+        }
+    }
+
+    // the block id of the i-th data block, table blocks are skipped
+    pub fn data_block(&self, i: usize, fs: &JFS) -> IOResult<u32> {
+        if self.is_extents() {
+            return self.extent_run(i, fs).map(|(b, _)| b);
+        }
+        let at = Self::data_block_pos(i, fs);
+        if at < DIRECT_BLOCKS {
+            return Ok(self.block0s[at]);
+        }
+        if at < fs.l1_limit() {
+            return fs.read_table_entry(self.block1, at - DIRECT_BLOCKS - 1);
+        }
+        if at >= fs.l2_limit() {
+            return Err(IOError::FileTooLarge);
+        }
+        let n = fs.ids_per_block();
+        let pos_in_l2 = at - fs.l1_limit() - 1;
+        let l2_l1_block = fs.read_table_entry(self.block2, pos_in_l2 / (1 + n))?;
+        fs.read_table_entry(l2_l1_block, pos_in_l2 % (1 + n) - 1)
+    }
+
+    // position of the i-th data block among all blocks of the inode
+    pub(crate) fn data_block_pos(i: usize, fs: &JFS) -> usize {
+        let n = fs.ids_per_block();
+        if i < DIRECT_BLOCKS {
+            return i;
+        }
+        if i < DIRECT_BLOCKS + n {
+            return i + 1;
+        }
+        let j = i - DIRECT_BLOCKS - n;
+        fs.l1_limit() + 1 + j / n * (n + 1) + 1 + j % n
+    }
+
+    pub fn total_blocks(sz: u32, fs: &JFS) -> usize {
+        let n = fs.ids_per_block();
+        let eblocks = (sz as usize).div_ceil(fs.block_size);
         if eblocks <= DIRECT_BLOCKS {
             return eblocks;
         }
-        if eblocks <= DIRECT_BLOCKS + BlockInBlock {
+        if eblocks <= DIRECT_BLOCKS + n {
             return eblocks + 1;
         }
-        eblocks + 1 + 1 + (eblocks - DIRECT_BLOCKS - BlockInBlock).div_ceil(BlockInBlock)
+        eblocks + 1 + 1 + (eblocks - DIRECT_BLOCKS - n).div_ceil(n)
     }
 }
 
 impl JFS {
-    fn mkfs(dev: Arc<dyn BlkDev>, total_blocks: u32, inode_blocks: u32) -> IOResult<Self> {
-        let data_blocks = total_blocks - inode_blocks - 1;
-        let s = Self {
-            inode_start_block: 1,
-            data_start_block: inode_blocks + 1,
-            data_end_block: total_blocks,
-            dev: dev,
+    pub fn mkfs(dev: Arc<dyn BlkDev>, total_blocks: u32, inode_blocks: u32) -> IOResult<Self> {
+        Self::mkfs_with(dev, total_blocks, inode_blocks, &MkfsOptions::default())
+    }
+
+    pub fn mkfs_with(
+        dev: Arc<dyn BlkDev>,
+        total_blocks: u32,
+        inode_blocks: u32,
+        opts: &MkfsOptions,
+    ) -> IOResult<Self> {
+        let bs = opts.block_size;
+        if !bs.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&bs) {
+            return Err(IOError::InvalidArgument);
+        }
+        let bitmaps = match opts.bitmaps {
+            true => Self::bitmap_layout(total_blocks, inode_blocks, bs),
+            false => (0, 0),
         };
-        let super_block = s.get_block(0)?;
-        super_block.lock().write(0, |su: &mut SuperBlock| {
-            su.init(total_blocks, inode_blocks, data_blocks);
-        });
+        if opts.label.len() > LABEL_LEN
+            || inode_blocks == 0
+            || inode_blocks as u64 + JOURNAL_BLOCKS as u64 + 1 + bitmaps.0 as u64 + bitmaps.1 as u64
+                >= total_blocks as u64
         {
-            let idle_head = s.idle_head_pos();
-            s.get_block(idle_head.block_id)?.lock().write(
-                idle_head.offset,
-                |idle_root: &mut DiskInode| {
-                    idle_root.file_type = FileType::IdleHead;
-                    idle_root.block1 = 0;
-                },
-            );
+            return Err(IOError::InvalidArgument);
         }
-        for inode_id in (2..s.inode_cnt()).rev() {
+        open_device(&dev, bs, CacheConfig::default())?;
+        let mut s = Self::new(dev, bs, total_blocks, inode_blocks, JOURNAL_BLOCKS, bitmaps);
+        s.extents = opts.extents;
+        // a journal left by an earlier fs on the device must not be replayed
+        s.clear_journal()?;
+        s.get_block(0)?.lock().write(0, |su: &mut SuperBlock| {
+            su.init(total_blocks, inode_blocks, JOURNAL_BLOCKS, bitmaps, opts);
+        });
+        s.clear_free_inodes()?;
+        for inode_id in (ROOT_INODE + 1..s.inode_cnt()).rev() {
             s.dealloc_inode(inode_id)?;
         }
+        s.clear_free_blocks()?;
+        let blocks: Vec<u32> = (s.data_start_block..s.data_end_block).collect();
+        s.dealloc_ascending(&blocks)?;
         {
-            let free_pos = s.block_gc_pos();
-            s.get_block(free_pos.block_id)?.lock().write(
-                free_pos.offset,
-                |free: &mut DiskInode| {
-                    free.file_type = FileType::BlockGC;
-                    free.size = 0;
-                    free.block0s.fill(0);
-                    free.block1 = 0;
-                    free.block2 = 0;
-                },
-            );
-        }
-        for block_id in s.data_start_block..s.data_end_block {
-            s.dealloc_block(block_id)?;
-        }
-        {
-            let root_dir = s.root_dir_pos();
-            s.get_block(root_dir.block_id)?.lock().write(
-                root_dir.offset,
-                |root: &mut DiskInode| {
-                    root.file_type = FileType::Directory;
-                    root.block0s.fill(0);
-                    root.block1 = 0;
-                    root.block2 = 0;
-                },
-            );
+            s.modify_disk_inode(ROOT_INODE, |root| root.init(FileType::Directory))?;
         }
         Ok(s)
     }
 
-    fn inode_cnt(&self) -> u32 {
-        (self.data_start_block - self.inode_start_block) * InodePerBlock as u32
+    // blocks of the inode bitmap and of the data bitmap, which covers whatever the
+    // inodes leave, its own blocks included
+    fn bitmap_layout(total_blocks: u32, inode_blocks: u32, bs: usize) -> (u32, u32) {
+        let inodes = inode_blocks.saturating_mul((bs / INODE_SIZE) as u32);
+        let inode_bitmap = bitmap_blocks(inodes, bs);
+        let rest = total_blocks
+            .saturating_sub(JOURNAL_BLOCKS + 1)
+            .saturating_sub(inode_blocks)
+            .saturating_sub(inode_bitmap);
+        (inode_bitmap, bitmap_blocks(rest, bs))
+    }
+
+    pub fn inode_cnt(&self) -> u32 {
+        (self.data_start_block - self.inode_start_block) * self.inodes_per_block() as u32
+    }
+
+    // record that the fs now relies on an incompatible feature
+    pub(crate) fn set_incompat(&self, feature: u32) -> IOResult<()> {
+        self.get_block(0)?
+            .lock()
+            .write(0, |sb: &mut SuperBlock| sb.feature_incompat |= feature);
+        Ok(())
+    }
+
+    // whether new inodes map their blocks with extents
+    pub fn uses_extents(&self) -> bool {
+        self.extents
+    }
+
+    // whether free inodes and blocks are tracked in bitmaps
+    pub fn uses_bitmaps(&self) -> bool {
+        self.bitmaps.is_some()
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn statfs(&self) -> IOResult<StatFs> {
+        let blk = self.get_block(0)?;
+        let rt = blk.lock().read(0, |sb: &SuperBlock| StatFs {
+            block_size: self.block_size,
+            blocks: self.data_end_block - self.data_start_block,
+            free_blocks: sb.free_blocks,
+            inodes: self.inode_cnt(),
+            free_inodes: sb.free_inodes,
+            name_len: NAME_LIMIT,
+        });
+        Ok(rt)
+    }
+
+    pub fn info(&self) -> IOResult<FsInfo> {
+        let blk = self.get_block(0)?;
+        let rt = blk.lock().read(0, |sb: &SuperBlock| {
+            let len = sb.label.iter().position(|c| *c == 0).unwrap_or(LABEL_LEN);
+            FsInfo {
+                block_size: sb.block_size as usize,
+                total_blocks: sb.total_blocks,
+                inode_blocks: sb.inode_blocks,
+                data_blocks: sb.data_blocks,
+                journal_blocks: sb.journal_blocks,
+                inode_bitmap_blocks: sb.inode_bitmap_blocks,
+                block_bitmap_blocks: sb.block_bitmap_blocks,
+                feature_compat: sb.feature_compat,
+                feature_incompat: sb.feature_incompat,
+                uuid: sb.uuid,
+                label: String::from_utf8_lossy(&sb.label[..len]).into(),
+                mount_count: sb.mount_count,
+                clean: sb.clean != 0,
+            }
+        });
+        Ok(rt)
+    }
+
+    pub(crate) fn inodes_per_block(&self) -> usize {
+        self.block_size / INODE_SIZE
+    }
+
+    // block ids held by a table block
+    pub(crate) fn ids_per_block(&self) -> usize {
+        self.block_size / size_of::<u32>()
+    }
+
+    // positions past the block1 table and past the block2 tree, table blocks included
+    pub(crate) fn l1_limit(&self) -> usize {
+        DIRECT_BLOCKS + self.ids_per_block() + 1
+    }
+
+    fn l2_limit(&self) -> usize {
+        let n = self.ids_per_block();
+        self.l1_limit() + 1 + n * (n + 1)
+    }
+
+    // data blocks a single inode can address
+    pub fn max_file_blocks(&self) -> usize {
+        let n = self.ids_per_block();
+        DIRECT_BLOCKS + n + n * n
     }
 
-    fn root_dir(self: Arc<Self>) -> Inode {
-        let pos = self.get_inode_pos(1);
-        Inode { pos, fs: self }
+    // the first device sector of a block
+    pub(crate) fn sector(&self, blk_id: u32) -> usize {
+        blk_id as usize * self.block_size / SECTOR_SIZE
     }
 
-    fn from_dev(dev: Arc<dyn BlkDev>) -> IOResult<Self> {
-        let mut s = Self {
-            inode_start_block: 0,
-            data_start_block: 0,
-            data_end_block: 0,
-            dev: dev,
+    pub fn root_dir(self: &Arc<Self>) -> Inode {
+        Inode::new(ROOT_INODE, Arc::clone(self))
+    }
+
+    fn new(
+        dev: Arc<dyn BlkDev>,
+        block_size: usize,
+        total_blocks: u32,
+        inode_blocks: u32,
+        journal_blocks: u32,
+        (inode_bitmap_blocks, block_bitmap_blocks): (u32, u32),
+    ) -> Self {
+        let bitmap_start = journal_blocks + 1;
+        let inode_start_block = bitmap_start + inode_bitmap_blocks + block_bitmap_blocks;
+        let data_start_block = inode_start_block + inode_blocks;
+        let bitmaps = (inode_bitmap_blocks != 0).then(|| Bitmaps {
+            inodes: Bitmap::new(
+                bitmap_start,
+                inode_blocks * (block_size / INODE_SIZE) as u32,
+            ),
+            blocks: Bitmap::new(
+                bitmap_start + inode_bitmap_blocks,
+                total_blocks - data_start_block,
+            ),
+        });
+        Self {
+            block_size,
+            journal_start_block: 1,
+            inode_start_block,
+            data_start_block,
+            data_end_block: total_blocks,
+            dev,
+            journal: Mutex::new(Journal::default()),
+            extents: false,
+            bitmaps,
+            open: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn from_dev(dev: Arc<dyn BlkDev>) -> IOResult<Self> {
+        Self::mount(dev, CacheConfig::default())
+    }
+
+    // open the fs on dev, redoing the last committed transaction if it was cut short
+    pub fn mount(dev: Arc<dyn BlkDev>, config: CacheConfig) -> IOResult<Self> {
+        let sb = SuperBlock::read_from(&dev)?;
+        let bs = sb.block_size as usize;
+        if !sb.is_valid()
+            || !bs.is_power_of_two()
+            || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&bs)
+            || sb.journal_blocks != JOURNAL_BLOCKS
+            || sb.inode_blocks as u64 + sb.journal_blocks as u64 + 1 >= sb.total_blocks as u64
+        {
+            return Err(IOError::CorruptedFS);
+        }
+        if sb.feature_incompat & !FEATURE_INCOMPAT_SUPPORTED != 0 {
+            return Err(IOError::UnsupportedFeature);
+        }
+        let bitmaps = match sb.feature_incompat & FEATURE_INCOMPAT_BITMAPS != 0 {
+            true => Self::bitmap_layout(sb.total_blocks, sb.inode_blocks, bs),
+            false => (0, 0),
         };
-        let su = s.get_block(0)?;
-        su.lock().read(0, |sb: &SuperBlock| {
-            s.inode_start_block = 1;
-            s.data_start_block = sb.inode_blocks + 1;
-            s.data_end_block = sb.total_blocks;
+        if (sb.inode_bitmap_blocks, sb.block_bitmap_blocks) != bitmaps
+            || sb.inode_blocks as u64
+                + sb.journal_blocks as u64
+                + 1
+                + bitmaps.0 as u64
+                + bitmaps.1 as u64
+                >= sb.total_blocks as u64
+        {
+            return Err(IOError::CorruptedFS);
+        }
+        open_device(&dev, bs, config)?;
+        let mut s = Self::new(
+            dev,
+            bs,
+            sb.total_blocks,
+            sb.inode_blocks,
+            sb.journal_blocks,
+            bitmaps,
+        );
+        s.extents = sb.feature_compat & FEATURE_COMPAT_EXTENTS_DEFAULT != 0;
+        s.replay_journal()?;
+        let blk = s.get_block(0)?;
+        let mut blk = blk.lock();
+        blk.write(0, |sb: &mut SuperBlock| {
+            sb.mount_count = sb.mount_count.wrapping_add(1);
+            sb.clean = 0;
         });
+        blk.write_back()?;
+        drop(blk);
         Ok(s)
     }
 
-    fn get_block(&self, blk_id: u32) -> IOResult<Arc<Mutex<BlockCache>>> {
+    // blocks touched inside a transaction stay pinned in the cache until it commits;
+    // what was written to one before goes to disk first, as a transaction too large
+    // to commit drops the blocks it dirtied
+    pub(crate) fn get_block(&self, blk_id: u32) -> IOResult<Arc<Mutex<BlockCache>>> {
+        let mut journal = self.journal.lock();
+        if journal.active() {
+            let first = journal.add(blk_id);
+            let blk = pin_block(blk_id as usize, Arc::clone(&self.dev))?;
+            drop(journal);
+            if first {
+                blk.lock().write_back()?;
+            }
+            return Ok(blk);
+        }
         get_block(blk_id as usize, Arc::clone(&self.dev))
     }
-    fn get_inode_pos(&self, id: u32) -> DiskPos {
-        let block_id = self.inode_start_block + id / InodePerBlock as u32;
-        let offset = (id as usize % InodePerBlock) * InodeSize;
+
+    fn read_table_entry(&self, blk_id: u32, idx: usize) -> IOResult<u32> {
+        if blk_id == 0 {
+            return Err(IOError::CorruptedFS);
+        }
+        let blk = self.get_block(blk_id)?;
+        let rt = blk.lock().read_slice(|bs: &[u32]| bs[idx]);
+        Ok(rt)
+    }
+
+    pub(crate) fn read_disk_inode<V>(
+        &self,
+        inode_id: u32,
+        func: impl FnOnce(&DiskInode) -> V,
+    ) -> IOResult<V> {
+        let pos = self.get_inode_pos(inode_id);
+        let blk = self.get_block(pos.block_id)?;
+        let rt = blk.lock().read(pos.offset, func);
+        Ok(rt)
+    }
+
+    pub(crate) fn modify_disk_inode<V>(
+        &self,
+        inode_id: u32,
+        func: impl FnOnce(&mut DiskInode) -> V,
+    ) -> IOResult<V> {
+        let pos = self.get_inode_pos(inode_id);
+        let blk = self.get_block(pos.block_id)?;
+        let rt = blk.lock().write(pos.offset, func);
+        Ok(rt)
+    }
+    pub(crate) fn get_inode_pos(&self, id: u32) -> DiskPos {
+        let block_id = self.inode_start_block + (id as usize / self.inodes_per_block()) as u32;
+        let offset = (id as usize % self.inodes_per_block()) * INODE_SIZE;
         DiskPos { block_id, offset }
     }
 
-    fn idle_head_pos(&self) -> DiskPos {
+    pub(crate) fn idle_head_pos(&self) -> DiskPos {
         DiskPos {
             block_id: 0,
-            offset: 2 * InodeSize,
+            offset: 2 * INODE_SIZE,
         }
     }
 
-    fn block_gc_pos(&self) -> DiskPos {
+    pub(crate) fn block_gc_pos(&self) -> DiskPos {
         DiskPos {
             block_id: 0,
-            offset: 3 * InodeSize,
+            offset: 3 * INODE_SIZE,
         }
     }
-    fn root_dir_pos(&self) -> DiskPos {
-        self.get_inode_pos(0)
+    pub(crate) fn alloc_inode(&self) -> IOResult<u32> {
+        let id = match &self.bitmaps {
+            Some(bm) => bm.inodes.alloc(self, 1)?[0],
+            None => self.pop_idle_inode()?,
+        };
+        self.add_free(0, -1)?;
+        Ok(id)
     }
 
-    fn alloc_inode(&self) -> Result<u32, IOError> {
+    pub(crate) fn dealloc_inode(&self, inode_id: u32) -> IOResult<()> {
+        match &self.bitmaps {
+            Some(bm) => {
+                bm.inodes.free(self, inode_id)?;
+                self.modify_disk_inode(inode_id, |d| d.file_type = FileType::IdleHead)?;
+            }
+            None => self.push_idle_inode(inode_id)?,
+        }
+        self.add_free(0, 1)
+    }
+
+    // n data blocks, in one run from where the last allocation ended when the bitmap
+    // has such a run, none are taken when there are fewer than n free
+    pub(crate) fn alloc_blocks(&self, n: usize) -> IOResult<Vec<u32>> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let blocks = match &self.bitmaps {
+            Some(bm) => bm
+                .blocks
+                .alloc(self, n as u32)?
+                .into_iter()
+                .map(|i| self.data_start_block + i)
+                .collect(),
+            None => {
+                let mut blocks = Vec::with_capacity(n);
+                while blocks.len() < n {
+                    match self.pop_free_block() {
+                        Ok(b) => blocks.push(b),
+                        Err(e) => {
+                            for b in blocks {
+                                self.push_free_block(b)?;
+                            }
+                            return Err(e);
+                        }
+                    }
+                }
+                blocks
+            }
+        };
+        self.add_free(-(n as i32), 0)?;
+        Ok(blocks)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn alloc_block(&self) -> IOResult<u32> {
+        Ok(self.alloc_blocks(1)?[0])
+    }
+
+    pub(crate) fn dealloc_block(&self, block_id: u32) -> IOResult<()> {
+        if block_id < self.data_start_block || block_id >= self.data_end_block {
+            return Err(IOError::NoSuchBlock);
+        }
+        match &self.bitmaps {
+            Some(bm) => bm.blocks.free(self, block_id - self.data_start_block)?,
+            None => self.push_free_block(block_id)?,
+        }
+        self.add_free(1, 0)
+    }
+
+    // free blocks into an empty free list, so that they are handed out again
+    // in ascending order: the inode cache takes the first ones, the tables the rest
+    pub(crate) fn dealloc_ascending(&self, blocks: &[u32]) -> IOResult<()> {
+        if self.bitmaps.is_some() {
+            return blocks.iter().try_for_each(|&b| self.dealloc_block(b));
+        }
+        let (cached, rest) = blocks.split_at(blocks.len().min(DIRECT_BLOCKS));
+        for &b in cached.iter().rev().chain(rest.iter().rev()) {
+            self.dealloc_block(b)?;
+        }
+        Ok(())
+    }
+
+    // forget every free inode, dealloc_inode hands them back
+    pub(crate) fn clear_free_inodes(&self) -> IOResult<()> {
+        let head = self.idle_head_pos();
+        self.get_block(head.block_id)?
+            .lock()
+            .write(head.offset, |d: &mut DiskInode| d.init(FileType::IdleHead));
+        if let Some(bm) = &self.bitmaps {
+            bm.inodes.fill(self)?;
+        }
+        self.get_block(0)?
+            .lock()
+            .write(0, |sb: &mut SuperBlock| sb.free_inodes = 0);
+        Ok(())
+    }
+
+    // forget every free block, dealloc_block hands them back
+    pub(crate) fn clear_free_blocks(&self) -> IOResult<()> {
+        let gc = self.block_gc_pos();
+        self.get_block(gc.block_id)?
+            .lock()
+            .write(gc.offset, |d: &mut DiskInode| d.init(FileType::BlockGC));
+        if let Some(bm) = &self.bitmaps {
+            bm.blocks.fill(self)?;
+        }
+        self.get_block(0)?
+            .lock()
+            .write(0, |sb: &mut SuperBlock| sb.free_blocks = 0);
+        Ok(())
+    }
+
+    fn add_free(&self, blocks: i32, inodes: i32) -> IOResult<()> {
+        self.get_block(0)?.lock().write(0, |sb: &mut SuperBlock| {
+            sb.free_blocks = sb.free_blocks.wrapping_add_signed(blocks);
+            sb.free_inodes = sb.free_inodes.wrapping_add_signed(inodes);
+        });
+        Ok(())
+    }
+
+    // fill buf with the blocks from blk_id on, in as few device requests as the cache allows
+    pub(crate) fn read_blocks(&self, blk_id: u32, buf: &mut [u8]) -> IOResult<()> {
+        read_run(&self.dev, blk_id as usize, buf)
+    }
+
+    // without bitmaps free inodes are linked through block1, starting at the IdleHead inode
+    fn pop_idle_inode(&self) -> IOResult<u32> {
         let head_pos = self.idle_head_pos();
         let head_blk_lk = self.get_block(head_pos.block_id)?;
         let mut next = 0;
@@ -354,7 +980,7 @@ impl JFS {
         Ok(next)
     }
 
-    fn dealloc_inode(&self, inode_id: u32) -> Result<(), IOError> {
+    fn push_idle_inode(&self, inode_id: u32) -> IOResult<()> {
         let head_pos = self.idle_head_pos();
         let head_blk_lk = self.get_block(head_pos.block_id)?;
         let mut next_next = 0;
@@ -384,239 +1010,396 @@ impl JFS {
         Ok(())
     }
 
-    fn alloc_block(&self) -> IOResult<u32> {
-        for _ in 0..5 {
-            match self._alloc_block() {
-                Ok(0) => {
-                    continue;
-                }
-                other => {
-                    return other;
-                }
-            }
-        }
-        panic!("should return after 5 times alloc retry")
-    }
-    fn _alloc_block(&self) -> IOResult<u32> {
+    // without bitmaps free blocks are kept in the BlockGC inode:
+    // block0s: free block ids cached in the inode
+    // block1: a free block used as table of free block ids
+    // block2: a free block used as table of full block1 tables, slot 0 links to the next one
+    // table blocks are free blocks as well, they are handed out once emptied
+    fn pop_free_block(&self) -> IOResult<u32> {
         let pos = self.block_gc_pos();
-        let blk_lk = self.get_block(pos.block_id)?;
-        let mut blk = blk_lk.lock();
-        let mut rt = Err(IOError::Unknown);
-        blk.write(pos.offset, |free: &mut DiskInode| {
-            if free.size == 0 {
-                rt = Err(IOError::DiskFull);
-                return;
+        let blk = self.get_block(pos.block_id)?;
+        let rt = blk.lock().write(pos.offset, |free: &mut DiskInode| {
+            // the cache is used as a stack, so freed runs come back in order
+            if let Some(b) = free.block0s.iter_mut().rev().find(|b| **b != 0) {
+                return Ok(core::mem::take(b));
             }
-            for b in free.block0s.iter_mut() {
-                if *b != 0 {
-                    rt = Ok(*b);
-                    *b = 0;
-                    free.size -= BlockSize as u32;
-                    return;
+            if free.block1 == 0 {
+                if free.block2 == 0 {
+                    return Err(IOError::DiskFull);
                 }
-            }
-            if free.block1 != 0 {
-                let blk = self.get_block(free.block1);
-                if let Err(e) = blk {
-                    rt = Err(e);
-                    return;
-                }
-                let blk_lk = blk.unwrap();
-                let mut blk = blk_lk.lock();
-                let mut current_index = 0;
-                let fill_cnt = free.block0s.len() / 2;
-                for block0_ref in free.block0s[0..fill_cnt].iter_mut() {
-                    if current_index >= BlockInBlock {
-                        break;
+                let (block1, next) =
+                    self.get_block(free.block2)?
+                        .lock()
+                        .write_slice(|blk2_arr: &mut [u32]| {
+                            let block1 = blk2_arr[1..].iter_mut().rev().find(|b| **b != 0);
+                            (block1.map(core::mem::take), blk2_arr[0])
+                        });
+                match block1 {
+                    Some(b) => free.block1 = b,
+                    None => {
+                        return Ok(core::mem::replace(&mut free.block2, next));
                     }
-                    blk.write(0, |block_id_array: &mut [u32; BlockInBlock]| {
-                        while current_index <= BlockInBlock {
-                            if current_index == BlockInBlock {
-                                *block0_ref = free.block1;
-                                break;
-                            }
-                            if block_id_array[current_index] == 0 {
-                                current_index += 1;
-                                continue;
-                            }
-                            *block0_ref = block_id_array[current_index];
-                            block_id_array[current_index] = 0;
-                            current_index += 1;
-                        }
-                    });
                 }
-                rt = Ok(0);
-                return;
             }
-            if free.block2 != 0 {
-                let blk_lk = self.get_block(free.block2);
-                if let Err(e) = blk_lk {
-                    rt = Err(e);
-                    return;
-                }
-                let blk_lk = blk_lk.unwrap();
-                let mut blk = blk_lk.lock();
-                blk.write(0, |blk_id_array: &mut [u32; BlockInBlock]| {
-                    for blk in blk_id_array.iter_mut() {
-                        if *blk != 0 {
-                            free.block1 = *blk;
-                            *blk = 0;
-                            break;
-                        }
-                    }
+            let b = self
+                .get_block(free.block1)?
+                .lock()
+                .write_slice(|blk1_arr: &mut [u32]| {
+                    blk1_arr
+                        .iter_mut()
+                        .rev()
+                        .find(|b| **b != 0)
+                        .map(core::mem::take)
                 });
-                if free.block1 == 0 {
-                    free.block1 = free.block2;
-                    free.block2 = 0;
-                }
-                rt = Ok(0)
-            }
-            panic!("should not be empty")
+            Ok(b.unwrap_or_else(|| core::mem::take(&mut free.block1)))
         });
         rt
     }
 
-    fn dealloc_block(&self, block_id: u32) -> IOResult<()> {
+    fn push_free_block(&self, block_id: u32) -> IOResult<()> {
         let pos = self.block_gc_pos();
-        let blk_lk = self.get_block(pos.block_id)?;
-        let mut blk = blk_lk.lock();
-        let free: &mut DiskInode = blk.ref_mut(pos.offset);
-        for block in free.block0s.iter_mut() {
-            if *block == 0 {
-                *block = block_id;
-                free.size += BlockSize as u32;
+        let blk = self.get_block(pos.block_id)?;
+        let rt = blk.lock().write(pos.offset, |free: &mut DiskInode| {
+            if let Some(b) = free.block0s.iter_mut().find(|b| **b == 0) {
+                *b = block_id;
                 return Ok(());
             }
-        }
-        if free.block1 == 0 {
-            let blk = self.get_block(block_id)?;
-            blk.lock().write(0, |blk_arr: &mut [u32; BlockInBlock]| {
-                blk_arr[..free.block0s.len()].copy_from_slice(&free.block0s);
-                blk_arr[free.block0s.len()..].fill(0);
-            });
-            free.block1 = block_id;
-            free.block0s.fill(0);
-            free.size += BlockSize as u32;
-            return Ok(());
-        } else {
-            let blk1_lk = self.get_block(free.block1)?;
-            let mut blk1 = blk1_lk.lock();
-            let blk1_arr: &mut [u32; BlockInBlock] = blk1.ref_mut(0);
-            for blk in blk1_arr.iter_mut() {
-                if *blk == 0 {
-                    *blk = block_id;
-                    free.size += BlockSize as u32;
+            if free.block1 != 0 {
+                let stored =
+                    self.get_block(free.block1)?
+                        .lock()
+                        .write_slice(|blk1_arr: &mut [u32]| {
+                            blk1_arr
+                                .iter_mut()
+                                .find(|b| **b == 0)
+                                .map(|b| *b = block_id)
+                                .is_some()
+                        });
+                if stored {
                     return Ok(());
                 }
-            }
-        }
-        if free.block2 == 0 {
-            let blk = self.get_block(block_id)?;
-            blk.lock().write(0, |blk_arr: &mut [u32; BlockInBlock]| {
-                blk_arr[0] = free.block1;
-                blk_arr[1..].fill(0);
-            });
-            free.block2 = block_id;
-            free.block1 = 0;
-            free.size += BlockSize as u32;
-            return Ok(());
-        } else {
-            let blk2_lk = self.get_block(free.block2)?;
-            let mut blk2 = blk2_lk.lock();
-            let blk2_arr: &mut [u32; BlockInBlock] = blk2.ref_mut(0);
-            for blk in blk2_arr.iter_mut() {
-                if *blk == 0 {
-                    *blk = free.block1;
+                // block1 is full, move it to block2
+                if free.block2 != 0 {
+                    let block1 = free.block1;
+                    let moved = self.get_block(free.block2)?.lock().write_slice(
+                        |blk2_arr: &mut [u32]| {
+                            blk2_arr[1..]
+                                .iter_mut()
+                                .find(|b| **b == 0)
+                                .map(|b| *b = block1)
+                                .is_some()
+                        },
+                    );
+                    if moved {
+                        free.block1 = 0;
+                    }
+                }
+                if free.block1 != 0 {
+                    self.get_block(block_id)?
+                        .lock()
+                        .write_slice(|blk_arr: &mut [u32]| {
+                            blk_arr.fill(0);
+                            blk_arr[0] = free.block2;
+                            blk_arr[1] = free.block1;
+                        });
+                    free.block2 = block_id;
                     free.block1 = 0;
-                    free.size += BlockSize as u32;
                     return Ok(());
                 }
             }
+            self.get_block(block_id)?
+                .lock()
+                .write_slice(|blk_arr: &mut [u32]| {
+                    blk_arr.fill(0);
+                });
+            free.block1 = block_id;
+            Ok(())
+        });
+        rt
+    }
+}
+
+impl JFS {
+    fn unmount(&self) -> IOResult<()> {
+        self.get_block(0)?
+            .lock()
+            .write(0, |sb: &mut SuperBlock| sb.clean = 1);
+        remove_device(&self.dev)?;
+        self.dev.flush()
+    }
+}
+
+impl Drop for JFS {
+    fn drop(&mut self) {
+        if let Err(_e) = self.unmount() {
+            error!("write back blocks failed on unmount");
         }
-        Err(IOError::DiskFull)
     }
 }
 
 #[cfg(test)]
-mod test {
-    use crate::cache::sync_blocks;
+pub(crate) mod test {
+    use crate::cache::sync_device;
     use crate::device::test::{MemoryBlock, MemoryBlockInner};
     use crate::device::BlkDev;
-    use crate::jfs::{BlockInBlock, DiskInode, FileType, MAGIC};
+    use crate::jfs::{DiskInode, FileType, MkfsOptions, SuperBlock, MAGIC, ROOT_INODE};
+    use crate::journal::JOURNAL_BLOCKS;
     use crate::types::*;
+    use alloc::boxed::Box;
+    use alloc::collections::BTreeSet;
     use alloc::sync::Arc;
     use alloc::vec;
+    use alloc::vec::Vec;
 
-    use super::JFS;
+    use super::{DIRECT_BLOCKS, JFS};
 
-    #[test]
-    fn test_mkfs() {
-        let mut blk_inner = MemoryBlockInner {
-            blocks: vec![[0u8; BlockSize]; 2048],
+    pub(crate) fn new_device(blocks: usize) -> (Arc<dyn BlkDev>, Box<MemoryBlockInner>) {
+        let mut blk_inner = Box::new(MemoryBlockInner {
+            blocks: vec![[0u8; SECTOR_SIZE]; blocks],
             read_cnt: 0,
             write_cnt: 0,
-        };
+        });
         let dev: Arc<dyn BlkDev> = Arc::new(MemoryBlock {
-            inner: &raw mut blk_inner,
+            inner: &raw mut *blk_inner,
         });
+        (dev, blk_inner)
+    }
+
+    #[test]
+    fn test_mkfs() {
+        let (dev, blk_inner) = new_device(2048);
         let fs = JFS::mkfs(dev, 2048, 31).unwrap();
-        sync_blocks().unwrap();
+        sync_device(&fs.dev).unwrap();
         assert_eq!(MAGIC, blk_inner.blocks[0][..4]);
-        let inode = fs.root_dir_pos();
+        assert_eq!(2048 - 32 - JOURNAL_BLOCKS as usize, free_blocks(&fs).len());
+        let inode = fs.get_inode_pos(ROOT_INODE);
         let inode_blk = fs.get_block(inode.block_id).unwrap();
-        let sz = (BlockSize * 29) as u32;
-        let blocks_needed = DiskInode::total_blocks(sz);
+        let sz = (SECTOR_SIZE * 29) as u32;
+        let blocks_needed = DiskInode::total_blocks(sz, &fs);
         assert_eq!(30, blocks_needed);
         let mut blocks = vec![];
         for _ in 0..blocks_needed {
             blocks.push(fs.alloc_block().unwrap());
         }
-        println!("blocks {:?}", blocks);
-        panic!("block not valid"); //todo fix this
+        check_blocks(&fs, &blocks);
+        inode_blk
+            .lock()
+            .write(inode.offset, |inode: &mut DiskInode| {
+                inode.inc_size(sz, blocks.clone(), &mut None, &fs).unwrap();
+            });
+        let mut poped = vec![];
         inode_blk
             .lock()
             .write(inode.offset, |inode: &mut DiskInode| {
-                inode.inc_size(sz, blocks, &fs).unwrap();
+                poped = inode.dec_size(0, &fs).unwrap();
+            });
+        poped.sort();
+        blocks.sort();
+        assert_eq!(blocks, poped);
+        for b in poped {
+            fs.dealloc_block(b).unwrap();
+        }
+        check_blocks(&fs, &[]);
+    }
+
+    #[test]
+    fn test_block_gc() {
+        // enough data blocks to chain several block2 tables
+        let n = SECTOR_SIZE / size_of::<u32>();
+        let total = 2 + JOURNAL_BLOCKS as usize + 3 * n * n;
+        let (dev, _blk_inner) = new_device(total);
+        let fs = JFS::mkfs(dev, total as u32, 1).unwrap();
+        check_blocks(&fs, &[]);
+
+        let mut owned = Vec::new();
+        loop {
+            match fs.alloc_block() {
+                Ok(b) => owned.push(b),
+                Err(IOError::DiskFull) => break,
+                Err(e) => panic!("alloc block failed: {:?}", e),
+            }
+        }
+        let data_blocks = total - 2 - JOURNAL_BLOCKS as usize;
+        assert_eq!(data_blocks, owned.len());
+        check_blocks(&fs, &owned);
+
+        let mut rng = XorShift(0x2545f491);
+        for round in 0..3 {
+            // free in random order, checking along the way
+            while !owned.is_empty() {
+                let idx = rng.next() % owned.len();
+                let b = owned.swap_remove(idx);
+                fs.dealloc_block(b).unwrap();
+                if owned.len() % 1000 == 0 {
+                    check_blocks(&fs, &owned);
+                }
+            }
+            check_blocks(&fs, &owned);
+            // reallocate part of the disk, then free part of it again
+            let cnt = data_blocks / (round + 1);
+            for _ in 0..cnt {
+                owned.push(fs.alloc_block().unwrap());
+            }
+            check_blocks(&fs, &owned);
+            for _ in 0..cnt / 2 {
+                let idx = rng.next() % owned.len();
+                fs.dealloc_block(owned.swap_remove(idx)).unwrap();
+            }
+            check_blocks(&fs, &owned);
+        }
+        while let Ok(b) = fs.alloc_block() {
+            owned.push(b);
+        }
+        check_blocks(&fs, &owned);
+    }
+
+    #[test]
+    fn test_dealloc_bad_block() {
+        let (dev, _blk_inner) = new_device(64);
+        let fs = JFS::mkfs(dev, 64, 3).unwrap();
+        assert!(matches!(fs.dealloc_block(0), Err(IOError::NoSuchBlock)));
+        assert!(matches!(fs.dealloc_block(1), Err(IOError::NoSuchBlock)));
+        assert!(matches!(
+            fs.dealloc_block(fs.data_start_block - 1),
+            Err(IOError::NoSuchBlock)
+        ));
+        assert!(matches!(fs.dealloc_block(64), Err(IOError::NoSuchBlock)));
+    }
+
+    #[test]
+    fn test_large_blocks() {
+        // 512 blocks of 4 KiB
+        let (dev, blk_inner) = new_device(512 * 8);
+        let opts = MkfsOptions {
+            block_size: 4096,
+            uuid: [7; 16],
+            label: "data",
+            extents: false,
+            bitmaps: false,
+        };
+        let fs = JFS::mkfs_with(Arc::clone(&dev), 512, 2, &opts).unwrap();
+        assert_eq!(512 - 3 - JOURNAL_BLOCKS as usize, free_blocks(&fs).len());
+        // block1 holds 1024 ids, so this stays clear of block2
+        assert_eq!(DIRECT_BLOCKS + 1024 + 1, fs.l1_limit());
+        let f = fs_file(fs);
+        let data: Vec<u8> = (0..4096 * 40).map(|i| (i % 253) as u8).collect();
+        f.write_at(100, &data).unwrap();
+        drop(f);
+
+        let fs = Arc::new(JFS::from_dev(dev).unwrap());
+        let info = fs.info().unwrap();
+        assert_eq!(4096, info.block_size);
+        assert_eq!("data", info.label);
+        assert_eq!([7; 16], info.uuid);
+        assert_eq!(1, info.mount_count);
+        assert!(!info.clean);
+        let f = fs.root_dir().lookup("f").unwrap();
+        let mut back = vec![0; data.len()];
+        assert_eq!(data.len(), f.read_at(100, &mut back).unwrap());
+        assert_eq!(data, back);
+        assert!(crate::fsck::fsck(&fs, false).unwrap().is_clean());
+        drop(f);
+        drop(fs);
+        // unmounting marks the fs clean again
+        let sb =
+            unsafe { core::ptr::read_unaligned(blk_inner.blocks[0].as_ptr() as *const SuperBlock) };
+        assert_eq!(1, sb.clean);
+        assert_eq!(1, sb.mount_count);
+    }
+
+    fn fs_file(fs: JFS) -> crate::inode::Inode {
+        Arc::new(fs).root_dir().create("f", FileType::File).unwrap()
+    }
+
+    #[test]
+    fn test_bad_options() {
+        let (dev, _blk_inner) = new_device(64);
+        for block_size in [256, 1000, 8192] {
+            let opts = MkfsOptions {
+                block_size,
+                ..Default::default()
+            };
+            let rt = JFS::mkfs_with(Arc::clone(&dev), 64, 1, &opts);
+            assert!(matches!(rt, Err(IOError::InvalidArgument)));
+        }
+        let opts = MkfsOptions {
+            label: "a label longer than thirty two bytes",
+            ..Default::default()
+        };
+        let rt = JFS::mkfs_with(dev, 64, 1, &opts);
+        assert!(matches!(rt, Err(IOError::InvalidArgument)));
+    }
+
+    #[test]
+    fn test_unknown_incompat_feature() {
+        let (dev, mut blk_inner) = new_device(128);
+        drop(JFS::mkfs(Arc::clone(&dev), 128, 1).unwrap());
+        let sb = blk_inner.blocks[0].as_mut_ptr() as *mut SuperBlock;
+        unsafe { (*sb).feature_compat = 1 << 31 };
+        drop(JFS::from_dev(Arc::clone(&dev)).unwrap());
+        unsafe { (*sb).feature_incompat = 1 << 31 };
+        assert!(matches!(
+            JFS::from_dev(dev),
+            Err(IOError::UnsupportedFeature)
+        ));
+    }
+
+    struct XorShift(u64);
+    impl XorShift {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+    }
+
+    fn read_table(fs: &JFS, blk_id: u32) -> Vec<u32> {
+        fs.get_block(blk_id)
+            .unwrap()
+            .lock()
+            .read_slice(|bs: &[u32]| bs.to_vec())
+    }
+
+    // every block tracked by BlockGC, table blocks included
+    fn free_blocks(fs: &JFS) -> Vec<u32> {
+        let pos = fs.block_gc_pos();
+        let mut rt = Vec::new();
+        let (mut block1, mut block2) = (0, 0);
+        fs.get_block(pos.block_id)
+            .unwrap()
+            .lock()
+            .read(pos.offset, |free: &DiskInode| {
+                assert_eq!(FileType::BlockGC, free.file_type);
+                rt.extend(free.block0s.iter().filter(|b| **b != 0));
+                block1 = free.block1;
+                block2 = free.block2;
             });
-        print_blocks(0, &fs);
-        print_blocks(-1, &fs);
-        print_blocks(-2, &fs);
-    }
-    fn print_blocks(inode_id: i32, fs: &JFS) {
-        let free_pos = match inode_id {
-            -1 => fs.block_gc_pos(),
-            -2 => fs.idle_head_pos(),
-            other => fs.get_inode_pos(other as u32),
+        let add_l1 = |rt: &mut Vec<u32>, blk_id: u32| {
+            rt.push(blk_id);
+            rt.extend(read_table(fs, blk_id).iter().filter(|b| **b != 0));
         };
-        let block = fs.get_block(free_pos.block_id).unwrap();
-        let mut block1 = 0;
-        let mut block2 = 0;
-        let mut is_idle = false;
-        block.lock().read(free_pos.offset, |inode: &DiskInode| {
-            println!("inode: {}: {:?}", inode_id, inode);
-            block1 = inode.block1;
-            block2 = inode.block2;
-            is_idle = inode.file_type == FileType::IdleHead;
-        });
-        if is_idle || block1 == 0 {
-            return;
+        if block1 != 0 {
+            add_l1(&mut rt, block1);
         }
-        let blk1 = fs.get_block(block1).unwrap();
-        blk1.lock().read(0, |bb: &[u32; BlockInBlock]| {
-            println!("block 1 at {}: {:?}", block1, bb);
-        });
-        if block2 == 0 {
-            return;
-        }
-        let blk2 = fs.get_block(block2).unwrap();
-        let mut free_blk2_0 = 0;
-        blk2.lock().read(0, |bb: &[u32; BlockInBlock]| {
-            println!("block 2 at {}: {:?}", block2, bb);
-            free_blk2_0 = bb[0];
-        });
-        let blk2_0 = fs.get_block(free_blk2_0).unwrap();
-        blk2_0.lock().read(0, |bb: &[u32; BlockInBlock]| {
-            println!("block 2.0 at {}: {:?}", free_blk2_0, bb);
-        });
+        while block2 != 0 {
+            rt.push(block2);
+            let l2 = read_table(fs, block2);
+            for &l1 in l2[1..].iter().filter(|b| **b != 0) {
+                add_l1(&mut rt, l1);
+            }
+            block2 = l2[0];
+        }
+        assert_eq!(fs.statfs().unwrap().free_blocks as usize, rt.len());
+        rt
+    }
+
+    fn check_blocks(fs: &JFS, owned: &[u32]) {
+        let mut seen = BTreeSet::new();
+        for &b in free_blocks(fs).iter().chain(owned.iter()) {
+            assert!(seen.insert(b), "block {} is tracked twice", b);
+        }
+        let all: BTreeSet<u32> = (fs.data_start_block..fs.data_end_block).collect();
+        assert_eq!(all, seen);
     }
 }
@@ -0,0 +1,372 @@
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+
+use super::types::*;
+use crate::{
+    cache::{discard_block, unpin_block},
+    jfs::JFS,
+};
+
+// blocks reserved for the journal: a header followed by the logged blocks
+pub const JOURNAL_BLOCKS: u32 = 32;
+const LOG_BLOCKS: usize = JOURNAL_BLOCKS as usize - 1;
+const JOURNAL_MAGIC: [u8; 4] = *b"jlog";
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+// a transaction is committed once its header is on disk:
+// the blocks are logged first, then the header is written,
+// then the blocks are written home and the header is cleared,
+// with a flush between the steps
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct JournalHeader {
+    magic: [u8; 4],
+    count: u32,
+    checksum: u32,
+    blocks: [u32; LOG_BLOCKS],
+}
+
+const _: () = assert!(size_of::<JournalHeader>() <= MIN_BLOCK_SIZE);
+
+impl JournalHeader {
+    fn empty() -> Self {
+        Self {
+            magic: [0; 4],
+            count: 0,
+            checksum: 0,
+            blocks: [0; LOG_BLOCKS],
+        }
+    }
+}
+
+// the open transaction, nested begins join the outermost one
+#[derive(Default)]
+pub(crate) struct Journal {
+    depth: usize,
+    blocks: BTreeSet<u32>,
+}
+
+impl Journal {
+    pub fn active(&self) -> bool {
+        self.depth > 0
+    }
+    // whether blk_id is new to the transaction
+    pub fn add(&mut self, blk_id: u32) -> bool {
+        self.blocks.insert(blk_id)
+    }
+}
+
+// fnv-1a over the logged blocks, a header whose blocks are not all on disk is ignored
+fn checksum(mut sum: u32, data: &[u8]) -> u32 {
+    for &c in data {
+        sum ^= c as u32;
+        sum = sum.wrapping_mul(FNV_PRIME);
+    }
+    sum
+}
+
+impl JFS {
+    pub(crate) fn begin(&self) {
+        self.journal.lock().depth += 1;
+    }
+
+    // only the outermost commit writes the transaction out
+    pub(crate) fn commit(&self) -> IOResult<()> {
+        let blocks = {
+            let mut journal = self.journal.lock();
+            journal.depth -= 1;
+            if journal.active() {
+                return Ok(());
+            }
+            core::mem::take(&mut journal.blocks)
+        };
+        let rt = self.write_transaction(&blocks);
+        for &b in blocks.iter() {
+            unpin_block(b as usize, &self.dev);
+        }
+        rt
+    }
+
+    // run func as one transaction, it is committed even when func fails,
+    // as callers undo their partial work before returning an error
+    pub(crate) fn transaction<V>(&self, func: impl FnOnce() -> IOResult<V>) -> IOResult<V> {
+        self.begin();
+        let rt = func();
+        self.commit()?;
+        rt
+    }
+
+    fn write_transaction(&self, blocks: &BTreeSet<u32>) -> IOResult<()> {
+        let mut dirty = Vec::new();
+        for &b in blocks.iter() {
+            let blk = self.get_block(b)?;
+            if blk.lock().is_dirty() {
+                dirty.push((b, blk));
+            }
+        }
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        // what does not fit in the journal is dropped, so the disk still
+        // holds everything from before the transaction
+        if dirty.len() > LOG_BLOCKS {
+            for (b, _) in dirty.iter() {
+                discard_block(*b as usize, &self.dev);
+            }
+            return Err(IOError::JournalFull);
+        }
+        let mut header = JournalHeader::empty();
+        header.magic = JOURNAL_MAGIC;
+        header.count = dirty.len() as u32;
+        header.checksum = FNV_OFFSET;
+        let mut log = vec![0u8; dirty.len() * self.block_size];
+        for (i, ((b, blk), buf)) in dirty
+            .iter()
+            .zip(log.chunks_exact_mut(self.block_size))
+            .enumerate()
+        {
+            blk.lock()
+                .read_slice(|data: &[u8]| buf.copy_from_slice(data));
+            header.checksum = checksum(header.checksum, buf);
+            header.blocks[i] = *b;
+        }
+        self.dev
+            .write_blocks(self.sector(self.log_block(0)), &log)?;
+        self.dev.flush()?;
+        self.write_header(&header)?;
+        self.dev.flush()?;
+        for (_, blk) in dirty.iter() {
+            blk.lock().write_back()?;
+        }
+        self.dev.flush()?;
+        self.clear_journal()
+    }
+
+    // redo the logged blocks of a committed transaction, returns whether there was one
+    pub(crate) fn replay_journal(&self) -> IOResult<bool> {
+        let header = self.read_header()?;
+        if header.magic != JOURNAL_MAGIC || header.count == 0 {
+            return Ok(false);
+        }
+        let count = header.count as usize;
+        if count > LOG_BLOCKS {
+            return Err(IOError::CorruptedFS);
+        }
+        let mut log = vec![0u8; count * self.block_size];
+        self.dev
+            .read_blocks(self.sector(self.log_block(0)), &mut log)?;
+        if checksum(FNV_OFFSET, &log) != header.checksum {
+            self.clear_journal()?;
+            return Ok(false);
+        }
+        for (&b, data) in header.blocks.iter().zip(log.chunks_exact(self.block_size)) {
+            if b >= self.data_end_block
+                || (b >= self.journal_start_block && b < self.journal_start_block + JOURNAL_BLOCKS)
+            {
+                return Err(IOError::CorruptedFS);
+            }
+            let blk = self.get_block(b)?;
+            let mut blk = blk.lock();
+            blk.write_slice(|home: &mut [u8]| home.copy_from_slice(data));
+            blk.write_back()?;
+        }
+        self.dev.flush()?;
+        self.clear_journal()?;
+        Ok(true)
+    }
+
+    pub(crate) fn clear_journal(&self) -> IOResult<()> {
+        self.write_header(&JournalHeader::empty())
+    }
+
+    fn log_block(&self, i: usize) -> u32 {
+        self.journal_start_block + 1 + i as u32
+    }
+
+    fn read_header(&self) -> IOResult<JournalHeader> {
+        let mut buf = vec![0u8; self.block_size];
+        self.dev
+            .read_blocks(self.sector(self.journal_start_block), &mut buf)?;
+        Ok(unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const JournalHeader) })
+    }
+
+    fn write_header(&self, header: &JournalHeader) -> IOResult<()> {
+        let mut buf = vec![0u8; self.block_size];
+        unsafe { core::ptr::write_unaligned(buf.as_mut_ptr() as *mut JournalHeader, *header) };
+        self.dev
+            .write_blocks(self.sector(self.journal_start_block), &buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::boxed::Box;
+    use alloc::format;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use crate::cache::{discard_device, sync_device};
+    use crate::device::test::{CrashDevice, MemoryBlock, MemoryBlockInner};
+    use crate::device::BlkDev;
+    use crate::fsck::fsck;
+    use crate::inode::DIR_ENTRY_SIZE;
+    use crate::jfs::test::new_device;
+    use crate::jfs::{FileType, MkfsOptions, JFS};
+    use crate::types::*;
+
+    use super::{JOURNAL_MAGIC, LOG_BLOCKS};
+
+    const TOTAL_BLOCKS: usize = 512;
+
+    // create, grow past the direct blocks, shrink and remove files
+    fn workload(fs: &Arc<JFS>) {
+        let root = fs.root_dir();
+        let dir = root.create("dir", FileType::Directory).unwrap();
+        for i in 0..3 {
+            let f = dir.create(&format!("f{}", i), FileType::File).unwrap();
+            f.write_at(0, &vec![i as u8 + 1; SECTOR_SIZE * (i * 15 + 1)])
+                .unwrap();
+        }
+        let big = root.create("big", FileType::File).unwrap();
+        big.write_at(0, &vec![7; SECTOR_SIZE * 40]).unwrap();
+        dir.remove("f1").unwrap();
+        big.resize(SECTOR_SIZE * 3 + 5).unwrap();
+        dir.remove("f2").unwrap();
+        root.create("last", FileType::File).unwrap();
+        sync_device(&fs.dev).unwrap();
+    }
+
+    fn copy_device(blocks: &[[u8; SECTOR_SIZE]]) -> (Arc<dyn BlkDev>, Box<MemoryBlockInner>) {
+        let mut inner = Box::new(MemoryBlockInner {
+            blocks: blocks.to_vec(),
+            read_cnt: 0,
+            write_cnt: 0,
+        });
+        let dev: Arc<dyn BlkDev> = Arc::new(MemoryBlock {
+            inner: &raw mut *inner,
+        });
+        (dev, inner)
+    }
+
+    // run the workload on a disk that loses every write after the limit-th,
+    // returns how many writes the workload issued
+    fn crash_after(image: &[[u8; SECTOR_SIZE]], limit: usize) -> usize {
+        let (disk, _inner) = copy_device(image);
+        let crash = Arc::new(CrashDevice::new(Arc::clone(&disk), limit));
+        let fs = Arc::new(JFS::from_dev(crash.clone()).unwrap());
+        workload(&fs);
+        // power loss: the fs is never unmounted
+        discard_device(&fs.dev);
+        core::mem::forget(fs);
+        let writes = *crash.writes.lock();
+
+        let fs = Arc::new(JFS::from_dev(disk).unwrap());
+        let report = fsck(&fs, false).unwrap();
+        assert!(
+            report.is_clean(),
+            "crash after {} writes: {:?}",
+            limit,
+            report
+                .problems
+                .iter()
+                .map(|p| format!("{}", p))
+                .collect::<Vec<_>>()
+        );
+        if limit >= writes {
+            let dir = fs.root_dir().lookup("dir").unwrap();
+            assert_eq!(vec!["f0"], dir.ls().unwrap());
+            assert_eq!(
+                SECTOR_SIZE * 3 + 5,
+                fs.root_dir().lookup("big").unwrap().size().unwrap()
+            );
+        }
+        writes
+    }
+
+    fn crash_everywhere(opts: &MkfsOptions) {
+        let (dev, inner) = new_device(TOTAL_BLOCKS);
+        JFS::mkfs_with(dev, TOTAL_BLOCKS as u32, 4, opts).unwrap();
+        let image = inner.blocks.clone();
+
+        let writes = crash_after(&image, usize::MAX);
+        for limit in 0..writes {
+            crash_after(&image, limit);
+        }
+    }
+
+    #[test]
+    fn test_crash_consistency() {
+        crash_everywhere(&MkfsOptions::default());
+    }
+
+    #[test]
+    fn test_crash_consistency_bitmaps() {
+        crash_everywhere(&MkfsOptions {
+            extents: true,
+            bitmaps: true,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_replay() {
+        let (dev, inner) = new_device(TOTAL_BLOCKS);
+        JFS::mkfs(dev, TOTAL_BLOCKS as u32, 4).unwrap();
+        let image = inner.blocks.clone();
+        // a single transaction, cut short after limit writes
+        let create = |limit: usize| {
+            let (disk, disk_inner) = copy_device(&image);
+            let crash = Arc::new(CrashDevice::new(Arc::clone(&disk), limit));
+            let fs = Arc::new(JFS::from_dev(crash.clone()).unwrap());
+            fs.root_dir().create("a", FileType::File).unwrap();
+            discard_device(&fs.dev);
+            core::mem::forget(fs);
+            let writes = *crash.writes.lock();
+            (disk, disk_inner, writes)
+        };
+        // the super block on mount, then the logged blocks, the header,
+        // the home blocks and the cleared header
+        let (_, _, writes) = create(usize::MAX);
+        let logged = (writes - 3) / 2;
+        assert_eq!(2 * logged + 3, writes);
+
+        let (disk, disk_inner, _) = create(logged + 2);
+        assert_eq!(JOURNAL_MAGIC, disk_inner.blocks[1][..4]);
+        let fs = Arc::new(JFS::from_dev(disk).unwrap());
+        assert_eq!([0; 4], disk_inner.blocks[1][..4]);
+        assert_eq!(vec!["a"], fs.root_dir().ls().unwrap());
+        assert!(fsck(&fs, false).unwrap().is_clean());
+
+        let (disk, _disk_inner, _) = create(logged + 1);
+        let fs = Arc::new(JFS::from_dev(disk).unwrap());
+        assert!(fs.root_dir().ls().unwrap().is_empty());
+        assert!(fsck(&fs, false).unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_transaction_too_large() {
+        let (dev, _inner) = new_device(TOTAL_BLOCKS);
+        let fs = Arc::new(JFS::mkfs(dev, TOTAL_BLOCKS as u32, 4).unwrap());
+        let root = fs.root_dir();
+        let dir = root.create("dir", FileType::Directory).unwrap();
+        let file = root.create("file", FileType::File).unwrap();
+        // a directory of more entry blocks than the journal logs, in one go
+        let per_block = fs.block_size / DIR_ENTRY_SIZE;
+        let rt = fs.transaction(|| {
+            for i in 0..per_block * (LOG_BLOCKS + 1) {
+                dir.link(&format!("l{}", i), &file)?;
+            }
+            Ok(())
+        });
+        assert!(matches!(rt, Err(IOError::JournalFull)));
+        // none of it happened
+        assert!(dir.ls().unwrap().is_empty());
+        assert_eq!(1, file.stat().unwrap().nlink);
+        sync_device(&fs.dev).unwrap();
+        assert!(fsck(&fs, false).unwrap().is_clean());
+        // and the next transaction goes through
+        dir.link("l", &file).unwrap();
+        assert_eq!(vec!["l"], dir.ls().unwrap());
+    }
+}
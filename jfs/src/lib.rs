@@ -1,9 +1,26 @@
-//#![no_std] //comment it for test
+#![cfg_attr(not(test), no_std)]
 extern crate alloc;
+mod bitmap;
 mod cache;
 mod device;
+mod extent;
+mod fsck;
+mod inode;
 mod jfs;
+mod journal;
+mod time;
 mod types;
+
+pub use cache::{flush_expired, sync_blocks, sync_device, CacheConfig, FlushPolicy};
+pub use device::BlkDev;
+pub use fsck::{fsck, FsckReport, Problem};
+pub use inode::{Inode, Stat, MAY_EXEC, MAY_READ, MAY_WRITE, NAME_LIMIT, SYMLINK_LIMIT};
+pub use jfs::{
+    FileType, FsInfo, MkfsOptions, StatFs, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, JFS, LABEL_LEN,
+};
+pub use journal::JOURNAL_BLOCKS;
+pub use time::set_clock;
+pub use types::*;
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
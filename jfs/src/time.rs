@@ -0,0 +1,17 @@
+use spin::RwLock;
+
+// the wall clock stamped into inodes, in seconds since the unix epoch
+static CLOCK: RwLock<fn() -> u64> = RwLock::new(no_clock);
+
+fn no_clock() -> u64 {
+    0
+}
+
+// install the clock, the kernel passes its rtc and the host tools the system time
+pub fn set_clock(clock: fn() -> u64) {
+    *CLOCK.write() = clock;
+}
+
+pub(crate) fn now() -> u64 {
+    (CLOCK.read())()
+}
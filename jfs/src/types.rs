@@ -1,4 +1,8 @@
-pub const BlockSize: usize = 512;
+// the unit a BlkDev reads and writes
+pub const SECTOR_SIZE: usize = 512;
+// fs block sizes mkfs accepts, powers of two in between
+pub const MIN_BLOCK_SIZE: usize = SECTOR_SIZE;
+pub const MAX_BLOCK_SIZE: usize = 4096;
 
 #[derive(Debug)]
 pub enum IOError {
@@ -8,6 +12,19 @@ pub enum IOError {
     DiskFull,
     CorruptedFS,
     DeviceBusy,
+    NotFound,
+    AlreadyExists,
+    NotDirectory,
+    IsDirectory,
+    DirectoryNotEmpty,
+    BadFileName,
+    FileTooLarge,
+    UnsupportedFeature,
+    InvalidArgument,
+    PermissionDenied,
+    SymlinkLoop,
+    // a transaction dirtied more blocks than the journal logs
+    JournalFull,
 }
 
 pub type IOResult<T> = core::result::Result<T, IOError>;
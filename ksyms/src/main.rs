@@ -0,0 +1,172 @@
+use std::{fs, process::exit};
+use xmas_elf::{
+    sections::SectionData,
+    symbol_table::{Entry, Type},
+    ElfFile,
+};
+
+const USAGE: &str = "usage: ksyms <kernel>";
+// the section the kernel reserves for the table, see backtrace.rs
+const SECTION: &str = ".ksyms";
+
+// fill the symbol table of a linked kernel in, in place; the kernel keeps
+// a fixed size section of zeros for it, so nothing moves
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let elf = ElfFile::new(&data).map_err(|e| format!("{}: {}", path, e))?;
+    let section = elf
+        .find_section_by_name(SECTION)
+        .ok_or(format!("{}: no {} section", path, SECTION))?;
+    let (offset, size) = (section.offset() as usize, section.size() as usize);
+    let table = ksyms_table(&elf);
+    if table.len() > size {
+        return Err(format!(
+            "{}: the table takes {} bytes, {} has room for {}",
+            path,
+            table.len(),
+            SECTION,
+            size
+        ));
+    }
+    // the zeros left after the table end it
+    let out = &mut data[offset..offset + size];
+    out.fill(0);
+    out[..table.len()].copy_from_slice(&table);
+    fs::write(path, &data).map_err(|e| format!("{}: {}", path, e))
+}
+
+// every function by address, each as [addr: u64][size: u32][name len: u16]
+// [name], little endian
+fn ksyms_table(elf: &ElfFile) -> Vec<u8> {
+    let mut syms = Vec::new();
+    for section in elf.section_iter() {
+        let Ok(SectionData::SymbolTable64(entries)) = section.get_data(elf) else {
+            continue;
+        };
+        for e in entries {
+            if e.get_type() != Ok(Type::Func) || e.size() == 0 {
+                continue;
+            }
+            if let Ok(name) = e.get_name(elf) {
+                syms.push((e.value(), e.size(), demangle(name)));
+            }
+        }
+    }
+    syms.sort();
+    syms.dedup_by_key(|s| s.0);
+    let mut table = Vec::new();
+    for (addr, size, name) in syms {
+        let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+        table.extend(addr.to_le_bytes());
+        table.extend((size as u32).to_le_bytes());
+        table.extend((name.len() as u16).to_le_bytes());
+        table.extend(name);
+    }
+    table
+}
+
+// rust's legacy mangling, `_ZN3foo3bar17h0123456789abcdefE` is foo::bar;
+// anything else is kept as it is
+fn demangle(sym: &str) -> String {
+    let Some(mut rest) = sym.strip_prefix("_ZN").and_then(|s| s.strip_suffix('E')) else {
+        return sym.to_string();
+    };
+    let mut parts = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let Ok(len) = rest[..digits].parse::<usize>() else {
+            return sym.to_string();
+        };
+        let Some(part) = rest.get(digits..digits + len) else {
+            return sym.to_string();
+        };
+        parts.push(part);
+        rest = &rest[digits + len..];
+    }
+    // the hash rustc adds to tell instances apart
+    if parts
+        .last()
+        .is_some_and(|h| h.len() == 17 && h.starts_with('h'))
+    {
+        parts.pop();
+    }
+    let parts: Vec<String> = parts.into_iter().map(unescape).collect();
+    parts.join("::")
+}
+
+// `$LT$` is <, `$u20$` a space and `..` a ::, a leading _ only guards a $
+fn unescape(part: &str) -> String {
+    let mut rest = match part.strip_prefix('_') {
+        Some(p) if p.starts_with('$') => p,
+        _ => part,
+    };
+    let mut out = String::new();
+    while let Some(c) = rest.chars().next() {
+        if let Some(r) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = r;
+            continue;
+        }
+        let escaped = rest
+            .strip_prefix('$')
+            .and_then(|r| r.split_once('$'))
+            .and_then(|(code, r)| Some((unescape_code(code)?, r)));
+        match escaped {
+            Some((ch, r)) => {
+                out.push(ch);
+                rest = r;
+            }
+            None => {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    out
+}
+
+fn unescape_code(code: &str) -> Option<char> {
+    match code {
+        "SP" => Some('@'),
+        "BP" => Some('*'),
+        "RF" => Some('&'),
+        "LT" => Some('<'),
+        "GT" => Some('>'),
+        "LP" => Some('('),
+        "RP" => Some(')'),
+        "C" => Some(','),
+        _ => char::from_u32(u32::from_str_radix(code.strip_prefix('u')?, 16).ok()?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::demangle;
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(demangle("_ZN2os4main17h0123456789abcdefE"), "os::main");
+        assert_eq!(
+            demangle(
+                "_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h2a8e5f0c3c1b7d09E"
+            ),
+            "core::ptr::drop_in_place<alloc::vec::Vec<u8>>"
+        );
+        assert_eq!(
+            demangle("_ZN59_$LT$core..fmt..Arguments$u20$as$u20$core..fmt..Display$GT$3fmt17h1234567890abcdefE"),
+            "<core::fmt::Arguments as core::fmt::Display>::fmt"
+        );
+        assert_eq!(demangle("memcpy"), "memcpy");
+    }
+}
@@ -0,0 +1,76 @@
+use core::{arch::asm, slice, str};
+
+use crate::println;
+
+// room for the function names, ../ksyms writes them into the linked kernel
+const KSYMS_SIZE: usize = 512 * 1024;
+
+// zeros as built, so the table is read through sksyms and eksyms and
+// never through KSYMS, which the compiler knows the value of
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+// frames printed before giving up
+const MAX_DEPTH: usize = 32;
+// no kernel stack is bigger than the boot stack
+const STACK_SPAN: usize = 4096 * 16;
+
+fn ksyms() -> &'static [u8] {
+    extern "C" {
+        fn sksyms();
+        fn eksyms();
+    }
+    let start = sksyms as usize;
+    unsafe { slice::from_raw_parts(start as *const u8, eksyms as usize - start) }
+}
+
+// the function holding pc and where it starts
+fn symbol(pc: usize) -> Option<(&'static str, usize)> {
+    let mut rest = ksyms();
+    while rest.len() >= 14 {
+        let addr = u64::from_le_bytes(rest[..8].try_into().unwrap()) as usize;
+        let size = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+        let len = u16::from_le_bytes(rest[12..14].try_into().unwrap()) as usize;
+        let name = rest.get(14..14 + len)?;
+        // the zeros after the last function
+        if addr > pc || size == 0 {
+            return None;
+        }
+        if pc < addr + size {
+            return Some((str::from_utf8(name).unwrap_or("?"), addr));
+        }
+        rest = &rest[14 + len..];
+    }
+    None
+}
+
+// walk the frame pointers from here up; the kernel is built with
+// -Cforce-frame-pointers, so each frame keeps ra at fp - 8 and the fp of
+// its caller at fp - 16. a frame pointer that goes down, leaves the stack
+// or is misaligned ends the walk, as do the frames of trap entries
+pub fn print() {
+    let (mut fp, sp): (usize, usize);
+    unsafe {
+        asm!("mv {}, fp", out(reg) fp);
+        asm!("mv {}, sp", out(reg) sp);
+    }
+    println!("backtrace:");
+    let mut prev = sp;
+    for depth in 0..MAX_DEPTH {
+        if fp <= prev || fp - sp > STACK_SPAN || fp % 8 != 0 {
+            break;
+        }
+        let ra = unsafe { *((fp - 8) as *const usize) };
+        if ra == 0 {
+            break;
+        }
+        // ra is just past the call, which may be a compressed one
+        match symbol(ra - 1) {
+            Some((name, start)) => println!("  {:2}: {:#x} {}+{:#x}", depth, ra, name, ra - start),
+            None => println!("  {:2}: {:#x} ?", depth, ra),
+        }
+        prev = fp;
+        fp = unsafe { *((fp - 16) as *const usize) };
+    }
+}
@@ -1,2 +1,12 @@
 pub const USER_STACK_LIMIT: usize = 8192;
+// how far brk may grow the heap
+pub const USER_HEAP_LIMIT: usize = 0x10_0000;
 pub const KERNEL_STACK_LIMIT: usize = 8192;
+// qemu virt mmio regions, mapped identically into the kernel space
+pub const RTC_BASE: usize = 0x0010_1000;
+pub const VIRTIO0: usize = 0x1000_1000;
+pub const UART0: usize = 0x1000_0000;
+pub const MMIO: &[(usize, usize)] = &[(RTC_BASE, 0x1000), (VIRTIO0, 0x1000), (UART0, 0x1000)];
+// the plic sources these raise
+pub const VIRTIO0_IRQ: u32 = 1;
+pub const UART0_IRQ: u32 = 10;
@@ -1,10 +1,15 @@
 use core::fmt::{self, Write};
 
-use super::sbi;
+use super::{drivers::uart, sbi};
 
 struct Stdout;
 impl Write for Stdout {
+    // the sbi only until the uart driver is up
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if uart::ready() {
+            s.bytes().for_each(uart::put_sync);
+            return Ok(());
+        }
         for c in s.chars() {
             sbi::console_put_char(c as usize);
         }
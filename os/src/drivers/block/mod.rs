@@ -0,0 +1,17 @@
+mod virtio_blk;
+
+use alloc::sync::Arc;
+use jfs::BlkDev;
+use lazy_static::lazy_static;
+
+pub use virtio_blk::VirtIOBlock;
+
+lazy_static! {
+    static ref VIRTIO_BLOCK: Arc<VirtIOBlock> = Arc::new(VirtIOBlock::new());
+    pub static ref BLOCK_DEVICE: Arc<dyn BlkDev> = VIRTIO_BLOCK.clone();
+}
+
+// requests are polled for, the interrupt only has to be acknowledged
+pub fn handle_irq() {
+    VIRTIO_BLOCK.ack_interrupt();
+}
@@ -0,0 +1,282 @@
+use alloc::vec::Vec;
+use core::{
+    hint::spin_loop,
+    mem::size_of,
+    ptr::{addr_of, read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
+use jfs::{BlkDev, IOError, IOResult, SECTOR_SIZE};
+
+use crate::{
+    config::VIRTIO0,
+    mm::{frame_new, FrameGuard, PAGE_SIZE},
+    sync::UCell,
+};
+
+// the legacy virtio mmio registers, what qemu's virt machine exposes
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DRIVER_FEATURES: usize = 0x020;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+
+const MAGIC: u32 = 0x7472_6976;
+const BLOCK_DEVICE_ID: u32 = 2;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+
+// the device takes flush requests, without it writes are done once acked
+const F_FLUSH: u32 = 1 << 9;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+const QUEUE_SIZE: usize = 16;
+// the header and the status take a descriptor each
+const MAX_SEGMENTS: usize = QUEUE_SIZE - 2;
+
+// only the device reads these
+#[allow(dead_code)]
+#[repr(C)]
+struct Desc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct ReqHeader {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+// one request is in flight at a time and polled for, so every request
+// reuses descriptors 0.. and the same header and status
+struct Device {
+    base: usize,
+    // the descriptor table and the avail ring, then the used ring on the
+    // next page, the legacy layout
+    _frames: Vec<FrameGuard>,
+    desc: *mut Desc,
+    // the rings as u16s: flags, idx, then the ring entries
+    avail: *mut u16,
+    used: *const u16,
+    last_used: u16,
+    flush: bool,
+    header: ReqHeader,
+    status: u8,
+}
+
+pub struct VirtIOBlock(UCell<Device>);
+
+// the driver is only touched with interrupts off on a single hart
+unsafe impl Send for VirtIOBlock {}
+
+impl Device {
+    fn new(base: usize) -> Self {
+        let frames: Vec<FrameGuard> = (0..2).map(|_| frame_new().unwrap()).collect();
+        assert_eq!(
+            frames[0].ppn.0 + 1,
+            frames[1].ppn.0,
+            "queue frames are not contiguous"
+        );
+        let queue = frames[0].ppn.0 * PAGE_SIZE;
+        let mut d = Self {
+            base,
+            desc: queue as *mut Desc,
+            avail: (queue + size_of::<Desc>() * QUEUE_SIZE) as *mut u16,
+            used: (queue + PAGE_SIZE) as *const u16,
+            _frames: frames,
+            last_used: 0,
+            flush: false,
+            header: ReqHeader {
+                type_: 0,
+                reserved: 0,
+                sector: 0,
+            },
+            status: 0,
+        };
+        assert!(
+            d.reg(MAGIC_VALUE) == MAGIC
+                && d.reg(VERSION) == 1
+                && d.reg(DEVICE_ID) == BLOCK_DEVICE_ID,
+            "virtio block device not found"
+        );
+        d.set_reg(STATUS, 0);
+        d.set_reg(STATUS, STATUS_ACKNOWLEDGE);
+        d.set_reg(STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let features = d.reg(DEVICE_FEATURES) & F_FLUSH;
+        d.set_reg(DRIVER_FEATURES, features);
+        d.flush = features != 0;
+        d.set_reg(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        d.set_reg(QUEUE_SEL, 0);
+        assert!(
+            d.reg(QUEUE_NUM_MAX) as usize >= QUEUE_SIZE,
+            "virtio block queue too small"
+        );
+        d.set_reg(QUEUE_NUM, QUEUE_SIZE as u32);
+        d.set_reg(QUEUE_ALIGN, PAGE_SIZE as u32);
+        d.set_reg(QUEUE_PFN, (queue / PAGE_SIZE) as u32);
+        d.set_reg(
+            STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        d
+    }
+
+    fn reg(&self, off: usize) -> u32 {
+        unsafe { read_volatile((self.base + off) as *const u32) }
+    }
+
+    fn set_reg(&mut self, off: usize, val: u32) {
+        unsafe { write_volatile((self.base + off) as *mut u32, val) }
+    }
+
+    // move the contiguous blocks from blk on into or out of segs, given as
+    // address and length, with as few requests as the queue allows
+    fn transfer(&mut self, type_: u32, blk: usize, segs: &[(usize, usize)]) -> IOResult<()> {
+        if segs
+            .iter()
+            .any(|&(_, len)| !len.is_multiple_of(SECTOR_SIZE))
+        {
+            return Err(IOError::BadBufSize);
+        }
+        // a descriptor may not be empty
+        let segs: Vec<(usize, usize)> = segs.iter().copied().filter(|&(_, len)| len > 0).collect();
+        let mut blk = blk;
+        for chunk in segs.chunks(MAX_SEGMENTS) {
+            self.request(type_, blk, chunk)?;
+            blk += chunk
+                .iter()
+                .map(|&(_, len)| len / SECTOR_SIZE)
+                .sum::<usize>();
+        }
+        Ok(())
+    }
+
+    // the kernel maps physical memory identically, so the buffers and the
+    // header go to the device by their addresses as they are
+    fn request(&mut self, type_: u32, sector: usize, segs: &[(usize, usize)]) -> IOResult<()> {
+        self.header = ReqHeader {
+            type_,
+            reserved: 0,
+            sector: sector as u64,
+        };
+        self.status = 0xff;
+        let data_flags = match type_ {
+            T_IN => DESC_F_WRITE,
+            _ => 0,
+        };
+        let last = segs.len() + 1;
+        for i in 0..=last {
+            let (addr, len, flags) = match i {
+                0 => (addr_of!(self.header) as usize, size_of::<ReqHeader>(), 0),
+                i if i == last => (addr_of!(self.status) as usize, 1, DESC_F_WRITE),
+                i => (segs[i - 1].0, segs[i - 1].1, data_flags),
+            };
+            let desc = Desc {
+                addr: addr as u64,
+                len: len as u32,
+                flags: if i == last {
+                    flags
+                } else {
+                    flags | DESC_F_NEXT
+                },
+                next: (i + 1) as u16,
+            };
+            unsafe { write_volatile(self.desc.add(i), desc) };
+        }
+        unsafe {
+            let idx = read_volatile(self.avail.add(1));
+            write_volatile(self.avail.add(2 + idx as usize % QUEUE_SIZE), 0);
+            fence(Ordering::SeqCst);
+            write_volatile(self.avail.add(1), idx.wrapping_add(1));
+        }
+        fence(Ordering::SeqCst);
+        self.set_reg(QUEUE_NOTIFY, 0);
+        while unsafe { read_volatile(self.used.add(1)) } == self.last_used {
+            spin_loop();
+        }
+        fence(Ordering::SeqCst);
+        self.last_used = self.last_used.wrapping_add(1);
+        match unsafe { read_volatile(addr_of!(self.status)) } {
+            0 => Ok(()),
+            _ => Err(IOError::Unknown),
+        }
+    }
+}
+
+impl VirtIOBlock {
+    pub fn new() -> Self {
+        Self(unsafe { UCell::new(Device::new(VIRTIO0)) })
+    }
+
+    pub fn ack_interrupt(&self) {
+        let mut d = self.0.exclusive_access();
+        let status = d.reg(INTERRUPT_STATUS);
+        d.set_reg(INTERRUPT_ACK, status);
+    }
+}
+
+// a contiguous run of blocks goes out as one request, its buffers as
+// the segments of that request
+impl BlkDev for VirtIOBlock {
+    fn read(&self, blk: usize, buf: &mut [u8]) -> IOResult<()> {
+        if buf.len() != SECTOR_SIZE {
+            return Err(IOError::BadBufSize);
+        }
+        self.read_blocks(blk, buf)
+    }
+    fn write(&self, blk: usize, buf: &[u8]) -> IOResult<()> {
+        if buf.len() != SECTOR_SIZE {
+            return Err(IOError::BadBufSize);
+        }
+        self.write_blocks(blk, buf)
+    }
+    fn read_blocks(&self, blk: usize, buf: &mut [u8]) -> IOResult<()> {
+        self.read_blocks_vectored(blk, &mut [buf])
+    }
+    fn write_blocks(&self, blk: usize, buf: &[u8]) -> IOResult<()> {
+        self.write_blocks_vectored(blk, &[buf])
+    }
+    fn read_blocks_vectored(&self, blk: usize, bufs: &mut [&mut [u8]]) -> IOResult<()> {
+        let segs: Vec<(usize, usize)> = bufs
+            .iter_mut()
+            .map(|b| (b.as_mut_ptr() as usize, b.len()))
+            .collect();
+        self.0.exclusive_access().transfer(T_IN, blk, &segs)
+    }
+    fn write_blocks_vectored(&self, blk: usize, bufs: &[&[u8]]) -> IOResult<()> {
+        let segs: Vec<(usize, usize)> = bufs
+            .iter()
+            .map(|b| (b.as_ptr() as usize, b.len()))
+            .collect();
+        self.0.exclusive_access().transfer(T_OUT, blk, &segs)
+    }
+    fn flush(&self) -> IOResult<()> {
+        let mut d = self.0.exclusive_access();
+        match d.flush {
+            true => d.request(T_FLUSH, 0, &[]),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// just enough of a flattened device tree walker to find devices by their
+// compatible strings, run before paging while the blob is reachable as is
+use core::{slice, str};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const MAX_DEPTH: usize = 16;
+
+// what a node's reg names first
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub base: usize,
+    pub size: usize,
+}
+
+fn be32(b: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+// a number of cells cells long, big endian
+fn cells(b: &[u8], n: u32) -> usize {
+    b.chunks(4)
+        .take(n as usize)
+        .fold(0, |acc, c| (acc << 32) | be32(c, 0) as usize)
+}
+
+fn cstr(b: &[u8]) -> &str {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    str::from_utf8(&b[..end]).unwrap_or("")
+}
+
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+#[derive(Clone, Copy)]
+struct Level {
+    // the cells this node's children use in reg
+    addr_cells: u32,
+    size_cells: u32,
+    matched: bool,
+    reg: Option<Region>,
+}
+
+const ROOT: Level = Level {
+    addr_cells: 2,
+    size_cells: 1,
+    matched: false,
+    reg: None,
+};
+
+// the first region of the first node compatible with any of compat
+pub fn find_compatible(dtb: usize, compat: &[&str]) -> Option<Region> {
+    let header = unsafe { slice::from_raw_parts(dtb as *const u8, 40) };
+    if be32(header, 0) != FDT_MAGIC {
+        return None;
+    }
+    let total = be32(header, 4) as usize;
+    let blob = unsafe { slice::from_raw_parts(dtb as *const u8, total) };
+    let structs = &blob[be32(header, 8) as usize..];
+    let strings = &blob[be32(header, 12) as usize..];
+
+    // levels[d] is the node at depth d, levels[0] stands above the root
+    let mut levels = [ROOT; MAX_DEPTH + 1];
+    let mut depth = 0;
+    let mut at = 0;
+    loop {
+        let token = be32(structs, at);
+        at += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(&structs[at..]);
+                at = align4(at + name.len() + 1);
+                depth += 1;
+                if depth > MAX_DEPTH {
+                    return None;
+                }
+                levels[depth] = ROOT;
+            }
+            FDT_END_NODE => {
+                let node = levels[depth];
+                if node.matched && node.reg.is_some() {
+                    return node.reg;
+                }
+                depth = depth.checked_sub(1)?;
+            }
+            FDT_PROP => {
+                let len = be32(structs, at) as usize;
+                let name = cstr(&strings[be32(structs, at + 4) as usize..]);
+                let value = &structs[at + 8..at + 8 + len];
+                at = align4(at + 8 + len);
+                let parent = levels[depth.saturating_sub(1)];
+                let node = &mut levels[depth];
+                match name {
+                    "#address-cells" => node.addr_cells = be32(value, 0),
+                    "#size-cells" => node.size_cells = be32(value, 0),
+                    "compatible" => {
+                        node.matched = value
+                            .split(|&c| c == 0)
+                            .any(|s| compat.iter().any(|c| c.as_bytes() == s));
+                    }
+                    "reg" => {
+                        let a = parent.addr_cells;
+                        if value.len() >= (a + parent.size_cells) as usize * 4 {
+                            node.reg = Some(Region {
+                                base: cells(value, a),
+                                size: cells(&value[a as usize * 4..], parent.size_cells),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            // FDT_END, or a token that makes no sense
+            _ => return None,
+        }
+    }
+}
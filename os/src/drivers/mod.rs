@@ -0,0 +1,34 @@
+pub mod block;
+mod fdt;
+pub mod plic;
+pub mod uart;
+
+use riscv::register::sstatus;
+
+use crate::config::{UART0_IRQ, VIRTIO0_IRQ};
+
+// what has to be known before paging, the blob is not mapped after it
+pub fn probe(dtb: usize) {
+    plic::probe(dtb);
+}
+
+pub fn init(hart: usize) {
+    plic::init(hart);
+    uart::init();
+    plic::register(UART0_IRQ, uart::handle_irq);
+    plic::register(VIRTIO0_IRQ, block::handle_irq);
+}
+
+pub fn handle_external() {
+    plic::dispatch();
+}
+
+// nothing to run until a device has something, sleep until it says so;
+// the only place the kernel takes interrupts, in its own trap handler
+pub fn wait_for_irq() {
+    unsafe {
+        sstatus::set_sie();
+        riscv::asm::wfi();
+        sstatus::clear_sie();
+    }
+}
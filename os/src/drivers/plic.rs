@@ -0,0 +1,132 @@
+use alloc::collections::BTreeMap;
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::sync::UCell;
+
+use super::fdt::{self, Region};
+
+const COMPATIBLE: &[&str] = &["riscv,plic0", "sifive,plic-1.0.0"];
+const PRIORITY: usize = 0x0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const THRESHOLD: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CLAIM: usize = THRESHOLD + 4;
+// what registered sources get unless they ask for more
+pub const DEFAULT_PRIORITY: u32 = 1;
+
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static SIZE: AtomicUsize = AtomicUsize::new(0);
+static CONTEXT: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    // what runs when a source is claimed, keyed by its irq number
+    static ref HANDLERS: UCell<BTreeMap<u32, fn()>> = unsafe { UCell::new(BTreeMap::new()) };
+}
+
+// find the plic in the device tree, before paging so the blob is reachable
+pub fn probe(dtb: usize) {
+    match fdt::find_compatible(dtb, COMPATIBLE) {
+        Some(Region { base, size }) => {
+            BASE.store(base, Ordering::Relaxed);
+            SIZE.store(size, Ordering::Relaxed);
+        }
+        None => warn!("[kernel] no plic in the device tree, no device interrupts"),
+    }
+}
+
+// the registers the kernel space has to map, if there is a plic
+pub fn region() -> Option<Region> {
+    match BASE.load(Ordering::Relaxed) {
+        0 => None,
+        base => Some(Region {
+            base,
+            size: SIZE.load(Ordering::Relaxed),
+        }),
+    }
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (BASE.load(Ordering::Relaxed) + offset) as *mut u32
+}
+
+fn context() -> usize {
+    CONTEXT.load(Ordering::Relaxed)
+}
+
+// take interrupts on hart's supervisor context, on qemu virt each hart has
+// its machine context first; every source starts disabled and everything
+// above priority 0 gets through
+pub fn init(hart: usize) {
+    if region().is_none() {
+        return;
+    }
+    let ctx = hart * 2 + 1;
+    CONTEXT.store(ctx, Ordering::Relaxed);
+    unsafe {
+        for word in 0..32 {
+            write_volatile(reg(ENABLE + ENABLE_STRIDE * ctx + word * 4), 0);
+        }
+    }
+    set_threshold(0);
+}
+
+pub fn set_priority(irq: u32, priority: u32) {
+    unsafe { write_volatile(reg(PRIORITY + irq as usize * 4), priority) };
+}
+
+// sources at or below threshold stay masked on this context
+pub fn set_threshold(threshold: u32) {
+    unsafe { write_volatile(reg(THRESHOLD + CONTEXT_STRIDE * context()), threshold) };
+}
+
+fn enable(irq: u32) {
+    let irq = irq as usize;
+    let enable = reg(ENABLE + ENABLE_STRIDE * context() + irq / 32 * 4);
+    unsafe { write_volatile(enable, read_volatile(enable) | 1 << (irq % 32)) };
+}
+
+// run handler whenever irq is raised, replacing any earlier one
+pub fn register(irq: u32, handler: fn()) {
+    if region().is_none() {
+        return;
+    }
+    HANDLERS.exclusive_access().insert(irq, handler);
+    set_priority(irq, DEFAULT_PRIORITY);
+    enable(irq);
+}
+
+// the highest pending source, 0 when there is none
+fn claim() -> u32 {
+    unsafe { read_volatile(reg(CLAIM + CONTEXT_STRIDE * context())) }
+}
+
+// done with irq, it may be raised again
+fn complete(irq: u32) {
+    unsafe { write_volatile(reg(CLAIM + CONTEXT_STRIDE * context()), irq) };
+}
+
+// serve every pending source with its handler
+pub fn dispatch() {
+    if region().is_none() {
+        return;
+    }
+    loop {
+        let irq = claim();
+        if irq == 0 {
+            return;
+        }
+        // the handler may take locks of its own, so look it up first
+        let handler = HANDLERS.exclusive_access().get(&irq).copied();
+        match handler {
+            Some(handler) => handler(),
+            None => warn!("[kernel] irq {} has no handler", irq),
+        }
+        complete(irq);
+    }
+}
@@ -0,0 +1,208 @@
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use lazy_static::lazy_static;
+
+use crate::{config::UART0, sync::UCell, task::WaitQueue};
+
+// ns16550a registers, one byte apart on qemu virt
+const RBR: usize = 0; // receive buffer, read
+const THR: usize = 0; // transmit holding, write
+const IER: usize = 1;
+const FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+
+const IER_RX: u8 = 0x01;
+const IER_THRE: u8 = 0x02;
+const FCR_ENABLE: u8 = 0x01;
+const FCR_CLEAR: u8 = 0x06;
+const LCR_8N1: u8 = 0x03;
+// dtr, rts and out2, qemu only raises interrupts with out2 set
+const MCR_DEFAULT: u8 = 0x0b;
+const LSR_DR: u8 = 0x01;
+const LSR_THRE: u8 = 0x20;
+// how many bytes the transmit fifo takes once it is empty
+const TX_FIFO: usize = 16;
+
+const RX_SIZE: usize = 256;
+const TX_SIZE: usize = 1024;
+
+fn reg(offset: usize) -> *mut u8 {
+    (UART0 + offset) as *mut u8
+}
+
+fn read_reg(offset: usize) -> u8 {
+    unsafe { read_volatile(reg(offset)) }
+}
+
+fn write_reg(offset: usize, value: u8) {
+    unsafe { write_volatile(reg(offset), value) }
+}
+
+struct Ring<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, c: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = c;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let c = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+struct Uart {
+    rx: Ring<RX_SIZE>,
+    tx: Ring<TX_SIZE>,
+    ier: u8,
+}
+
+impl Uart {
+    fn set_ier(&mut self, ier: u8) {
+        if self.ier != ier {
+            self.ier = ier;
+            write_reg(IER, ier);
+        }
+    }
+
+    // move what the fifo takes from tx, and only ask for the
+    // empty-fifo interrupt while there is more to send
+    fn start_tx(&mut self) {
+        if read_reg(LSR) & LSR_THRE != 0 {
+            for _ in 0..TX_FIFO {
+                match self.tx.pop() {
+                    Some(c) => write_reg(THR, c),
+                    None => break,
+                }
+            }
+        }
+        let ier = match self.tx.len {
+            0 => self.ier & !IER_THRE,
+            _ => self.ier | IER_THRE,
+        };
+        self.set_ier(ier);
+    }
+
+    // push out everything queued by polling
+    fn flush(&mut self) {
+        while let Some(c) = self.tx.pop() {
+            put_raw(c);
+        }
+        self.set_ier(self.ier & !IER_THRE);
+    }
+}
+
+lazy_static! {
+    static ref UART: UCell<Uart> = unsafe {
+        UCell::new(Uart {
+            rx: Ring::new(),
+            tx: Ring::new(),
+            ier: 0,
+        })
+    };
+    static ref READERS: WaitQueue = WaitQueue::new();
+}
+
+// until init the console goes through the sbi
+static READY: AtomicBool = AtomicBool::new(false);
+
+pub fn ready() -> bool {
+    READY.load(Ordering::Relaxed)
+}
+
+pub fn init() {
+    write_reg(IER, 0);
+    write_reg(LCR, LCR_8N1);
+    write_reg(FCR, FCR_ENABLE | FCR_CLEAR);
+    write_reg(MCR, MCR_DEFAULT);
+    UART.exclusive_access().set_ier(IER_RX);
+    READY.store(true, Ordering::Relaxed);
+}
+
+fn put_raw(c: u8) {
+    while read_reg(LSR) & LSR_THRE == 0 {}
+    write_reg(THR, c);
+}
+
+// a byte the kernel prints, after what is queued so output keeps its order;
+// a panic while the uart is borrowed skips the queue rather than panic again
+pub fn put_sync(c: u8) {
+    if let Some(mut uart) = UART.try_exclusive_access() {
+        uart.flush();
+    }
+    put_raw(c);
+}
+
+// move received bytes into rx and queued ones out of tx
+pub fn handle_irq() {
+    let mut uart = UART.exclusive_access();
+    let mut got = false;
+    while read_reg(LSR) & LSR_DR != 0 {
+        // a full ring drops what comes in, like the fifo would
+        uart.rx.push(read_reg(RBR));
+        got = true;
+    }
+    uart.start_tx();
+    drop(uart);
+    if got {
+        READERS.wake_all();
+    }
+}
+
+// wait for input and take what has arrived into buf, at least one byte
+pub fn read(buf: &mut [u8]) -> usize {
+    loop {
+        let mut uart = UART.exclusive_access();
+        let mut n = 0;
+        while n < buf.len() {
+            match uart.rx.pop() {
+                Some(c) => buf[n] = c,
+                None => break,
+            }
+            n += 1;
+        }
+        if n > 0 || buf.is_empty() {
+            return n;
+        }
+        drop(uart);
+        READERS.wait();
+    }
+}
+
+// queue data for the interrupt to send, polling out what does not fit
+pub fn write(data: &[u8]) {
+    let mut uart = UART.exclusive_access();
+    for &c in data {
+        if !uart.tx.push(c) {
+            uart.flush();
+            uart.tx.push(c);
+        }
+    }
+    uart.start_tx();
+}
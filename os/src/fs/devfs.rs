@@ -0,0 +1,369 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{any::Any, str};
+use jfs::{BlkDev, SECTOR_SIZE};
+use lazy_static::lazy_static;
+
+use crate::{
+    drivers::{block::BLOCK_DEVICE, uart},
+    mm::{Reader, UserBuf, UserBufMut, Writer},
+    print, println,
+    sbi::console_get_char,
+    sync::UCell,
+    syscall::{Errno, EAGAIN, EFAULT, EINVAL, EIO, EISDIR, ENOENT, EPERM},
+    timer,
+};
+
+use super::{
+    vfs::{Device, FileSystem, Inode},
+    Stat, StatFs, S_IFBLK, S_IFCHR, S_IFDIR,
+};
+
+// f_type of a devfs, the same as linux
+pub const DEVFS_MAGIC: i64 = 0x1373;
+const BUFFER_SIZE: usize = 2048;
+const DIR_MODE: u32 = 0o755;
+const DISK_GID: u32 = 6;
+
+const fn makedev(major: u64, minor: u64) -> u64 {
+    (major << 8) | minor
+}
+
+// a node in /dev, opening it makes a new instance of its device
+struct DevNode {
+    name: &'static str,
+    mode: u32,
+    gid: u32,
+    rdev: u64,
+    open: fn() -> Arc<dyn Device>,
+}
+
+// the linux device numbers, vda as the first virtio disk
+const NODES: &[DevNode] = &[
+    DevNode {
+        name: "console",
+        mode: S_IFCHR | 0o620,
+        gid: 0,
+        rdev: makedev(5, 1),
+        open: || Arc::new(Console),
+    },
+    DevNode {
+        name: "null",
+        mode: S_IFCHR | 0o666,
+        gid: 0,
+        rdev: makedev(1, 3),
+        open: || Arc::new(Null),
+    },
+    DevNode {
+        name: "zero",
+        mode: S_IFCHR | 0o666,
+        gid: 0,
+        rdev: makedev(1, 5),
+        open: || Arc::new(Zero),
+    },
+    DevNode {
+        name: "random",
+        mode: S_IFCHR | 0o666,
+        gid: 0,
+        rdev: makedev(1, 8),
+        open: || Arc::new(Random),
+    },
+    DevNode {
+        name: "vda",
+        mode: S_IFBLK | 0o660,
+        gid: DISK_GID,
+        rdev: makedev(254, 0),
+        open: || Arc::new(Disk::new(BLOCK_DEVICE.clone())),
+    },
+];
+
+// the device nodes the kernel has drivers for, a fixed read-only directory
+pub struct DevFs;
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self)
+    }
+}
+
+impl FileSystem for DevFs {
+    fn fs_type(&self) -> &'static str {
+        "devfs"
+    }
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(DevDir)
+    }
+    fn statfs(&self) -> Result<StatFs, Errno> {
+        Ok(StatFs {
+            fs_type: DEVFS_MAGIC,
+            bsize: SECTOR_SIZE as i64,
+            files: NODES.len() as u64 + 1,
+            namelen: 255,
+            frsize: SECTOR_SIZE as i64,
+            ..Default::default()
+        })
+    }
+}
+
+struct DevDir;
+
+impl Inode for DevDir {
+    fn stat(&self) -> Result<Stat, Errno> {
+        Ok(Stat {
+            ino: 1,
+            mode: S_IFDIR | DIR_MODE,
+            nlink: 2,
+            ..Default::default()
+        })
+    }
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(EISDIR)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(EISDIR)
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match NODES.iter().position(|n| n.name == name) {
+            Some(i) => Ok(Arc::new(DevInode(i))),
+            None => Err(ENOENT),
+        }
+    }
+    fn ls(&self) -> Result<Vec<String>, Errno> {
+        Ok(NODES.iter().map(|n| n.name.to_string()).collect())
+    }
+    // the nodes are fixed
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(EPERM)
+    }
+    fn rmdir(&self, _name: &str) -> Result<(), Errno> {
+        Err(EPERM)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// the node at this index of NODES
+struct DevInode(usize);
+
+impl Inode for DevInode {
+    fn stat(&self) -> Result<Stat, Errno> {
+        let node = &NODES[self.0];
+        Ok(Stat {
+            ino: self.0 as u64 + 2,
+            mode: node.mode,
+            nlink: 1,
+            gid: node.gid,
+            rdev: node.rdev,
+            blksize: SECTOR_SIZE as i32,
+            ..Default::default()
+        })
+    }
+    // the data is only reached through open_device
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(EINVAL)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(EINVAL)
+    }
+    fn open_device(&self) -> Option<Arc<dyn Device>> {
+        Some((NODES[self.0].open)())
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// fill the user buffer chunk by chunk with what f puts in tmp
+fn fill_user(mut buf: UserBufMut, mut f: impl FnMut(&mut [u8])) -> Result<usize, Errno> {
+    let mut tmp = [0u8; BUFFER_SIZE];
+    let mut total = 0;
+    loop {
+        f(&mut tmp);
+        let n = buf.write(&tmp).map_err(|_| EFAULT)?;
+        total += n;
+        if n < tmp.len() {
+            return Ok(total);
+        }
+    }
+}
+
+// the uart, reads wait for input; the sbi stands in until its driver is up
+// and reads there do not block but fail with EAGAIN
+struct Console;
+
+impl Device for Console {
+    fn read(&self, mut buf: UserBufMut) -> Result<usize, Errno> {
+        if uart::ready() {
+            let mut tmp = [0u8; BUFFER_SIZE];
+            let len = buf.remaining().min(tmp.len());
+            let n = uart::read(&mut tmp[..len]);
+            return buf.write(&tmp[..n]).map_err(|_| EFAULT);
+        }
+        let c = console_get_char();
+        if c == 0 {
+            return Err(EAGAIN);
+        }
+        buf.write(&[c as u8]).map_err(|_| EFAULT)
+    }
+    fn write(&self, mut buf: UserBuf) -> Result<usize, Errno> {
+        let mut tmp = [0; BUFFER_SIZE];
+        let mut written = 0;
+        loop {
+            match buf.read(&mut tmp) {
+                Err(err) => {
+                    println!("read from user failed: {}", err.msg);
+                    return Err(EFAULT);
+                }
+                Ok(readed) => {
+                    if uart::ready() {
+                        uart::write(&tmp[..readed]);
+                    } else {
+                        unsafe {
+                            print!("{}", str::from_utf8_unchecked(&tmp[..readed]));
+                        }
+                    }
+                    written += readed;
+                    if readed < tmp.len() {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(written)
+    }
+}
+
+// reads end right away, writes are thrown away
+struct Null;
+
+impl Device for Null {
+    fn read(&self, _buf: UserBufMut) -> Result<usize, Errno> {
+        Ok(0)
+    }
+    fn write(&self, mut buf: UserBuf) -> Result<usize, Errno> {
+        let mut tmp = [0; BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            match buf.read(&mut tmp).map_err(|_| EFAULT)? {
+                n if n < tmp.len() => return Ok(total + n),
+                n => total += n,
+            }
+        }
+    }
+}
+
+// reads give zeros, writes are thrown away
+struct Zero;
+
+impl Device for Zero {
+    fn read(&self, buf: UserBufMut) -> Result<usize, Errno> {
+        fill_user(buf, |tmp| tmp.fill(0))
+    }
+    fn write(&self, buf: UserBuf) -> Result<usize, Errno> {
+        Null.write(buf)
+    }
+}
+
+lazy_static! {
+    // xorshift64 state, seeded from the clocks on first use
+    static ref RANDOM_STATE: UCell<u64> = unsafe {
+        UCell::new((timer::wall_time_secs() ^ ((timer::get_time_ms() as u64) << 20)) | 1)
+    };
+}
+
+// a xorshift64 stream, not fit for keys; writes are thrown away
+struct Random;
+
+impl Device for Random {
+    fn read(&self, buf: UserBufMut) -> Result<usize, Errno> {
+        let mut x = RANDOM_STATE.exclusive_access();
+        fill_user(buf, |tmp| {
+            for chunk in tmp.chunks_mut(8) {
+                *x ^= *x << 13;
+                *x ^= *x >> 7;
+                *x ^= *x << 17;
+                chunk.copy_from_slice(&x.to_le_bytes()[..chunk.len()]);
+            }
+        })
+    }
+    fn write(&self, buf: UserBuf) -> Result<usize, Errno> {
+        Null.write(buf)
+    }
+}
+
+// what a transfer cut short by the disk returns
+fn short(total: usize) -> Result<usize, Errno> {
+    match total {
+        0 => Err(EIO),
+        n => Ok(n),
+    }
+}
+
+// the raw disk, sector by sector past whatever file system is on it
+struct Disk {
+    dev: Arc<dyn BlkDev>,
+    offset: UCell<usize>,
+}
+
+impl Disk {
+    fn new(dev: Arc<dyn BlkDev>) -> Self {
+        Self {
+            dev,
+            offset: unsafe { UCell::new(0) },
+        }
+    }
+}
+
+impl Device for Disk {
+    fn read(&self, mut buf: UserBufMut) -> Result<usize, Errno> {
+        let mut offset = self.offset.exclusive_access();
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut total = 0;
+        loop {
+            // the end of the disk shows up as a failed read
+            if self.dev.read(*offset / SECTOR_SIZE, &mut sector).is_err() {
+                return short(total);
+            }
+            let off = *offset % SECTOR_SIZE;
+            let n = buf.write(&sector[off..]).map_err(|_| EFAULT)?;
+            *offset += n;
+            total += n;
+            if n < SECTOR_SIZE - off {
+                return Ok(total);
+            }
+        }
+    }
+    fn write(&self, mut buf: UserBuf) -> Result<usize, Errno> {
+        let mut offset = self.offset.exclusive_access();
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut data = [0u8; SECTOR_SIZE];
+        let mut total = 0;
+        loop {
+            let (blk, off) = (*offset / SECTOR_SIZE, *offset % SECTOR_SIZE);
+            let n = match buf
+                .read(&mut data[..SECTOR_SIZE - off])
+                .map_err(|_| EFAULT)?
+            {
+                0 => return Ok(total),
+                n => n,
+            };
+            // a partial sector keeps the bytes around what is written
+            if n < SECTOR_SIZE && self.dev.read(blk, &mut sector).is_err() {
+                return short(total);
+            }
+            sector[off..off + n].copy_from_slice(&data[..n]);
+            if self.dev.write(blk, &sector).is_err() {
+                return short(total);
+            }
+            *offset += n;
+            total += n;
+            if off + n < SECTOR_SIZE {
+                return Ok(total);
+            }
+        }
+    }
+}
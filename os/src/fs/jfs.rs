@@ -0,0 +1,223 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use jfs::{BlkDev, CacheConfig, FileType, FlushPolicy, IOError, JFS};
+
+use crate::syscall::{
+    Errno, EACCES, EBUSY, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ELOOP, ENOENT, ENOSPC, ENOTDIR,
+    ENOTEMPTY, EOPNOTSUPP, EPERM, EXDEV,
+};
+
+use super::{
+    vfs::{FileSystem, Inode},
+    Stat, StatFs, JFS_MAGIC, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG,
+};
+
+const SECTOR_SIZE: u64 = 512;
+
+pub fn fs_errno(e: IOError) -> Errno {
+    match e {
+        IOError::NotFound => ENOENT,
+        IOError::IsDirectory => EISDIR,
+        IOError::PermissionDenied => EACCES,
+        IOError::AlreadyExists => EEXIST,
+        IOError::NotDirectory => ENOTDIR,
+        IOError::DirectoryNotEmpty => ENOTEMPTY,
+        IOError::SymlinkLoop => ELOOP,
+        IOError::DiskFull | IOError::JournalFull => ENOSPC,
+        IOError::FileTooLarge => EFBIG,
+        IOError::DeviceBusy => EBUSY,
+        IOError::UnsupportedFeature => EOPNOTSUPP,
+        // jfs rejects names that are too long or hold a '/' alike
+        IOError::BadFileName | IOError::InvalidArgument => EINVAL,
+        IOError::CorruptedFS | IOError::NoSuchBlock | IOError::BadBufSize | IOError::Unknown => EIO,
+    }
+}
+
+// a mounted jfs
+pub struct JfsFs {
+    fs: Arc<JFS>,
+    dev: Arc<dyn BlkDev>,
+}
+
+// the block cache as the mount data asks for it, a comma separated list of
+// cache=<blocks>, readahead=<blocks>, writethrough, writeback and
+// flush=<ms>, which writes back every ms from the timer
+fn cache_config(data: &str) -> Result<CacheConfig, Errno> {
+    let blocks = |val: &str| val.parse::<usize>().map_err(|_| EINVAL);
+    let mut config = CacheConfig::default();
+    for opt in data.split(',').filter(|opt| !opt.is_empty()) {
+        match opt.split_once('=') {
+            Some(("cache", val)) => match blocks(val)? {
+                0 => return Err(EINVAL),
+                n => config.capacity = n,
+            },
+            Some(("readahead", val)) => config.readahead = blocks(val)?,
+            Some(("flush", val)) => {
+                let interval_ms = val.parse::<u64>().map_err(|_| EINVAL)?;
+                config.policy = FlushPolicy::Periodic { interval_ms }
+            }
+            None if opt == "writethrough" => config.policy = FlushPolicy::WriteThrough,
+            None if opt == "writeback" => config.policy = FlushPolicy::WriteBack,
+            _ => return Err(EINVAL),
+        }
+    }
+    Ok(config)
+}
+
+impl JfsFs {
+    pub fn mount(dev: Arc<dyn BlkDev>, data: &str) -> Result<Arc<Self>, Errno> {
+        let fs = JFS::mount(Arc::clone(&dev), cache_config(data)?).map_err(fs_errno)?;
+        Ok(Arc::new(Self {
+            fs: Arc::new(fs),
+            dev,
+        }))
+    }
+}
+
+impl FileSystem for JfsFs {
+    fn fs_type(&self) -> &'static str {
+        "jfs"
+    }
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(JfsInode(self.fs.root_dir()))
+    }
+    fn statfs(&self) -> Result<StatFs, Errno> {
+        let st = self.fs.statfs().map_err(fs_errno)?;
+        Ok(StatFs {
+            fs_type: JFS_MAGIC,
+            bsize: st.block_size as i64,
+            blocks: st.blocks as u64,
+            bfree: st.free_blocks as u64,
+            // nothing is reserved for root
+            bavail: st.free_blocks as u64,
+            files: st.inodes as u64,
+            ffree: st.free_inodes as u64,
+            namelen: st.name_len as i64,
+            frsize: st.block_size as i64,
+            ..Default::default()
+        })
+    }
+    fn sync(&self) -> Result<(), Errno> {
+        jfs::sync_device(&self.dev).map_err(fs_errno)
+    }
+}
+
+pub struct JfsInode(jfs::Inode);
+
+impl JfsInode {
+    // the jfs inode behind another inode of the same file system
+    fn of(inode: &Arc<dyn Inode>) -> Result<&jfs::Inode, Errno> {
+        match inode.as_any().downcast_ref::<JfsInode>() {
+            Some(JfsInode(i)) => Ok(i),
+            None => Err(EXDEV),
+        }
+    }
+
+    // drop the entry name, which is a directory exactly when dir is set
+    fn remove(&self, name: &str, dir: bool) -> Result<(), Errno> {
+        if !self.0.is_dir().map_err(fs_errno)? {
+            return Err(ENOTDIR);
+        }
+        let child = self.0.find(name).map_err(fs_errno)?.ok_or(ENOENT)?;
+        match (dir, child.is_dir().map_err(fs_errno)?) {
+            (true, false) => Err(ENOTDIR),
+            (false, true) => Err(EISDIR),
+            _ => self.0.remove(name).map_err(fs_errno),
+        }
+    }
+}
+
+impl Inode for JfsInode {
+    fn stat(&self) -> Result<Stat, Errno> {
+        let st = self.0.stat().map_err(fs_errno)?;
+        let tp = match st.file_type {
+            FileType::Directory => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+            _ => S_IFREG,
+        };
+        Ok(Stat {
+            ino: st.ino as u64,
+            mode: tp | st.mode as u32,
+            nlink: st.nlink,
+            uid: st.uid,
+            gid: st.gid,
+            size: st.size as i64,
+            blksize: st.block_size as i32,
+            blocks: (st.blocks * st.block_size as u64 / SECTOR_SIZE) as i64,
+            atime: st.atime as i64,
+            mtime: st.mtime as i64,
+            ctime: st.ctime as i64,
+            ..Default::default()
+        })
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.0.read_at(offset, buf).map_err(fs_errno)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.0.write_at(offset, buf).map_err(fs_errno)
+    }
+    fn truncate(&self, size: usize) -> Result<(), Errno> {
+        self.0.resize(size).map_err(fs_errno)
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        self.0.readlink().map_err(fs_errno)
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        if !self.0.is_dir().map_err(fs_errno)? {
+            return Err(ENOTDIR);
+        }
+        match self.0.find(name).map_err(fs_errno)? {
+            Some(i) => Ok(Arc::new(JfsInode(i))),
+            None => Err(ENOENT),
+        }
+    }
+    fn ls(&self) -> Result<Vec<String>, Errno> {
+        self.0.ls().map_err(fs_errno)
+    }
+    fn create(&self, name: &str, mode: u32, uid: u32, gid: u32) -> Result<Arc<dyn Inode>, Errno> {
+        let tp = match mode & S_IFMT {
+            S_IFREG => FileType::File,
+            S_IFDIR => FileType::Directory,
+            _ => return Err(EPERM),
+        };
+        let perm = (mode & 0o7777) as u16;
+        let inode = self
+            .0
+            .create_as(name, tp, perm, uid, gid)
+            .map_err(fs_errno)?;
+        Ok(Arc::new(JfsInode(inode)))
+    }
+    fn symlink(&self, name: &str, target: &str, uid: u32, gid: u32) -> Result<(), Errno> {
+        self.0
+            .symlink_as(name, target, uid, gid)
+            .map(|_| ())
+            .map_err(fs_errno)
+    }
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        self.0
+            .link(name, JfsInode::of(target)?)
+            .map_err(|e| match e {
+                IOError::IsDirectory => EPERM,
+                e => fs_errno(e),
+            })
+    }
+    fn rename(
+        &self,
+        old_name: &str,
+        new_dir: &Arc<dyn Inode>,
+        new_name: &str,
+    ) -> Result<(), Errno> {
+        self.0
+            .rename(old_name, JfsInode::of(new_dir)?, new_name)
+            .map_err(fs_errno)
+    }
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        self.remove(name, false)
+    }
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        self.remove(name, true)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
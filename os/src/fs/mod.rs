@@ -0,0 +1,137 @@
+mod devfs;
+mod jfs;
+mod mount;
+mod path;
+mod pipe;
+mod tmpfs;
+mod vfs;
+
+use alloc::sync::Arc;
+use log::{info, warn};
+
+use crate::{
+    mm::{UserBuf, UserBufMut},
+    syscall::Errno,
+    timer,
+};
+
+pub use mount::{mount, sync_all, umount};
+pub use path::{
+    create_file, link_path, open_file, readlink_path, rename_path, stat_path, statfs_path,
+    symlink_path, unlink_path, OpenFlags,
+};
+pub use pipe::make_pipe;
+pub use vfs::Inode;
+
+const DEV_MODE: u32 = 0o755;
+// anyone may make files in /tmp
+const TMP_MODE: u32 = 0o777;
+// the disk is written back every second, little is lost if the power goes
+const ROOT_OPTIONS: &str = "flush=1000";
+
+pub fn init() {
+    ::jfs::set_clock(timer::wall_time_secs);
+    // a tmpfs stands in as the root until the disk is mounted over it
+    mount::mount_root("tmpfs", "tmpfs", "").expect("mount tmpfs failed");
+    if let Err(e) = mount::mount_root("/dev/vda", "jfs", ROOT_OPTIONS) {
+        warn!("[kernel] mount /dev/vda failed ({}), staying on tmpfs", e);
+    }
+    for (target, fstype, mode) in [("/dev", "devfs", DEV_MODE), ("/tmp", "tmpfs", TMP_MODE)] {
+        let rt = path::mkdir_path(target, mode, 0, 0)
+            .and_then(|_| mount::mount(fstype, target, fstype, "", 0, 0));
+        if let Err(e) = rt {
+            warn!("[kernel] mount {} on {} failed ({})", fstype, target, e);
+        }
+    }
+    for (source, target, fstype) in mount::mounts() {
+        info!("[kernel] mounted {} on {} type {}", source, target, fstype);
+    }
+}
+
+// write back the caches whose flush interval has passed, on every timer tick;
+// it comes from user mode, where no task can be holding a cache lock
+pub fn flush_expired() {
+    if let Err(e) = ::jfs::flush_expired(timer::get_time_ms() as u64) {
+        warn!("[kernel] periodic flush failed ({})", jfs::fs_errno(e));
+    }
+}
+
+// the console opened for stdin, stdout and stderr of the first process
+pub fn open_console(flags: OpenFlags) -> Arc<dyn File> {
+    open_file("/dev/console", flags, 0, 0).expect("open /dev/console failed")
+}
+
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    // copy into the user buffer, returns the bytes read
+    fn read(&self, buf: UserBufMut) -> Result<usize, Errno>;
+    fn write(&self, buf: UserBuf) -> Result<usize, Errno>;
+    fn stat(&self) -> Stat;
+}
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFIFO: u32 = 0o010000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+// the riscv64 linux `struct stat`, st_blocks counts 512 byte units
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    _pad1: u64,
+    pub size: i64,
+    pub blksize: i32,
+    _pad2: i32,
+    pub blocks: i64,
+    pub atime: i64,
+    pub atime_nsec: u64,
+    pub mtime: i64,
+    pub mtime_nsec: u64,
+    pub ctime: i64,
+    pub ctime_nsec: u64,
+    _unused: [u32; 2],
+}
+
+impl Stat {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+}
+
+// f_type of a jfs, its on-disk magic read as a little endian word
+pub const JFS_MAGIC: i64 = 0x7366_6a18;
+
+// the riscv64 linux `struct statfs`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatFs {
+    pub fs_type: i64,
+    pub bsize: i64,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: [i32; 2],
+    pub namelen: i64,
+    pub frsize: i64,
+    pub flags: i64,
+    _spare: [i64; 4],
+}
+
+impl StatFs {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+}
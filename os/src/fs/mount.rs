@@ -0,0 +1,159 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::{
+    drivers::block::BLOCK_DEVICE,
+    sync::UCell,
+    syscall::{Errno, EBUSY, EINVAL, ENODEV, ENOTDIR, EPERM},
+};
+
+use super::{devfs::DevFs, jfs::JfsFs, path, tmpfs::TmpFs, vfs::FileSystem};
+
+struct Mount {
+    source: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+lazy_static! {
+    // mounted file systems keyed by the absolute path they are mounted on
+    static ref MOUNTS: UCell<BTreeMap<String, Mount>> = unsafe { UCell::new(BTreeMap::new()) };
+}
+
+// the file system mounted right on path, which must be absolute and clean
+pub fn mounted_at(path: &str) -> Option<Arc<dyn FileSystem>> {
+    MOUNTS
+        .exclusive_access()
+        .get(path)
+        .map(|m| Arc::clone(&m.fs))
+}
+
+pub fn root_fs() -> Arc<dyn FileSystem> {
+    mounted_at("/").expect("no root file system")
+}
+
+// (source, target, fs type) of every mount, parents before children
+pub fn mounts() -> Vec<(String, String, &'static str)> {
+    MOUNTS
+        .exclusive_access()
+        .iter()
+        .map(|(target, m)| (m.source.clone(), target.clone(), m.fs.fs_type()))
+        .collect()
+}
+
+// the block device source names, for the fs types that need one
+fn device_of<'a>(source: &'a str, fstype: &str) -> Option<&'a str> {
+    match fstype {
+        "jfs" => Some(source.trim_start_matches("/dev/")),
+        _ => None,
+    }
+}
+
+// the file systems the kernel knows how to mount, data holds the options of
+// the fs type
+fn make_fs(source: &str, fstype: &str, data: &str) -> Result<Arc<dyn FileSystem>, Errno> {
+    match (fstype, device_of(source, fstype)) {
+        ("jfs", Some("vda")) => Ok(JfsFs::mount(BLOCK_DEVICE.clone(), data)?),
+        ("tmpfs", _) => Ok(TmpFs::new()),
+        ("devfs", _) => Ok(DevFs::new()),
+        _ => Err(ENODEV),
+    }
+}
+
+// mount the file system every path starts from, replacing the one the
+// kernel booted on as long as nothing is mounted on top of that
+pub fn mount_root(source: &str, fstype: &str, data: &str) -> Result<(), Errno> {
+    let fs = make_fs(source, fstype, data)?;
+    let mut mounts = MOUNTS.exclusive_access();
+    if mounts.keys().any(|k| k != "/") {
+        return Err(EBUSY);
+    }
+    mounts.insert(
+        "/".to_string(),
+        Mount {
+            source: source.to_string(),
+            fs,
+        },
+    );
+    Ok(())
+}
+
+// mount a new fstype instance from source on the directory target, root only
+pub fn mount(
+    source: &str,
+    target: &str,
+    fstype: &str,
+    data: &str,
+    uid: u32,
+    gid: u32,
+) -> Result<(), Errno> {
+    if uid != 0 {
+        return Err(EPERM);
+    }
+    let at = path::resolve(target, true, uid, gid)?;
+    if !at.inode.is_dir()? {
+        return Err(ENOTDIR);
+    }
+    {
+        let mounts = MOUNTS.exclusive_access();
+        // one mount per directory, and a device holds one file system at a time
+        let dev = device_of(source, fstype);
+        if mounts.contains_key(&at.path)
+            || (dev.is_some()
+                && mounts
+                    .values()
+                    .any(|m| device_of(&m.source, m.fs.fs_type()) == dev))
+        {
+            return Err(EBUSY);
+        }
+    }
+    let fs = make_fs(source, fstype, data)?;
+    MOUNTS.exclusive_access().insert(
+        at.path,
+        Mount {
+            source: source.to_string(),
+            fs,
+        },
+    );
+    Ok(())
+}
+
+// detach the file system mounted on target, files still open on it keep
+// it alive until they are closed
+pub fn umount(target: &str, uid: u32, gid: u32) -> Result<(), Errno> {
+    if uid != 0 {
+        return Err(EPERM);
+    }
+    let at = path::resolve(target, true, uid, gid)?;
+    let fs = {
+        let mut mounts = MOUNTS.exclusive_access();
+        if !mounts.contains_key(&at.path) {
+            return Err(EINVAL);
+        }
+        let prefix = alloc::format!("{}/", at.path);
+        if at.path == "/" || mounts.keys().any(|k| k.starts_with(&prefix)) {
+            return Err(EBUSY);
+        }
+        mounts.remove(&at.path).unwrap().fs
+    };
+    fs.sync()
+}
+
+// write back every mounted file system, before the machine goes down
+pub fn sync_all() {
+    let fses: Vec<_> = MOUNTS
+        .exclusive_access()
+        .iter()
+        .map(|(target, m)| (target.clone(), Arc::clone(&m.fs)))
+        .collect();
+    for (target, fs) in fses {
+        if let Err(e) = fs.sync() {
+            warn!("[kernel] sync {} failed ({})", target, e);
+        }
+    }
+}
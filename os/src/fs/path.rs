@@ -0,0 +1,290 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use bitflags::bitflags;
+use jfs::SYMLINK_LIMIT;
+
+use crate::syscall::{
+    Errno, EACCES, EBUSY, EEXIST, EINVAL, EISDIR, ELOOP, ENOENT, ENOTDIR, EPERM, EXDEV,
+};
+
+use super::{
+    mount,
+    vfs::{DeviceFile, FileSystem, Inode, InodeFile, MAY_EXEC, MAY_READ, MAY_WRITE},
+    File, Stat, StatFs, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG,
+};
+
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+bitflags! {
+    #[derive(Clone, Copy)]
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        // O_CREAT and O_TRUNC as linux numbers them
+        const CREATE = 1 << 6;
+        const TRUNC = 1 << 9;
+    }
+}
+
+impl OpenFlags {
+    // (readable, writable)
+    fn read_write(&self) -> (bool, bool) {
+        if self.contains(Self::WRONLY) {
+            (false, true)
+        } else if self.contains(Self::RDWR) {
+            (true, true)
+        } else {
+            (true, false)
+        }
+    }
+}
+
+// one directory passed on the way down, so ".." can go back up,
+// across a mount point too
+struct Step {
+    name: String,
+    inode: Arc<dyn Inode>,
+    fs: Arc<dyn FileSystem>,
+}
+
+// where a path ends up
+pub struct Resolved {
+    // absolute, without ".", ".." or symlinks
+    pub path: String,
+    pub inode: Arc<dyn Inode>,
+    pub fs: Arc<dyn FileSystem>,
+}
+
+fn split_path(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+fn path_of(steps: &[Step]) -> String {
+    if steps.len() == 1 {
+        return "/".to_string();
+    }
+    steps[1..].iter().fold(String::new(), |mut p, s| {
+        p.push('/');
+        p.push_str(&s.name);
+        p
+    })
+}
+
+// walk the path from the root, stepping onto the root of whatever is mounted
+// on a directory passed, every directory passed must be searchable and a
+// symlink as the last component is only followed when follow is set
+pub fn resolve(path: &str, follow: bool, uid: u32, gid: u32) -> Result<Resolved, Errno> {
+    let root = mount::root_fs();
+    let mut steps = vec![Step {
+        name: String::new(),
+        inode: root.root(),
+        fs: root,
+    }];
+    // the names left to resolve, the next one last
+    let mut names: Vec<String> = split_path(path).rev().map(String::from).collect();
+    let mut links = 0;
+    while let Some(name) = names.pop() {
+        match name.as_str() {
+            "." => continue,
+            ".." => {
+                if steps.len() > 1 {
+                    steps.pop();
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let cur = steps.last().unwrap();
+        if !cur.inode.is_dir()? {
+            return Err(ENOTDIR);
+        }
+        if !cur.inode.permits(uid, gid, MAY_EXEC)? {
+            return Err(EACCES);
+        }
+        let next = cur.inode.lookup(&name)?;
+        if (follow || !names.is_empty()) && next.stat()?.mode & S_IFMT == S_IFLNK {
+            links += 1;
+            if links > SYMLINK_LIMIT {
+                return Err(ELOOP);
+            }
+            let target = next.readlink()?;
+            if target.starts_with('/') {
+                steps.truncate(1);
+            }
+            names.extend(split_path(&target).rev().map(String::from));
+            continue;
+        }
+        let fs = Arc::clone(&cur.fs);
+        steps.push(Step {
+            name,
+            inode: next,
+            fs,
+        });
+        if let Some(fs) = mount::mounted_at(&path_of(&steps)) {
+            let top = steps.last_mut().unwrap();
+            top.inode = fs.root();
+            top.fs = fs;
+        }
+    }
+    let path = path_of(&steps);
+    let top = steps.pop().unwrap();
+    Ok(Resolved {
+        path,
+        inode: top.inode,
+        fs: top.fs,
+    })
+}
+
+// the directory holding the last component of path, which the user must be
+// allowed to change, and the name of that component
+fn parent_dir(path: &str, uid: u32, gid: u32) -> Result<(Resolved, &str), Errno> {
+    let (parent, name) = match path.trim_end_matches('/').rsplit_once('/') {
+        Some((parent, name)) => (resolve(parent, true, uid, gid)?, name),
+        None => (resolve("", true, uid, gid)?, path),
+    };
+    if !parent.inode.permits(uid, gid, MAY_WRITE | MAY_EXEC)? {
+        return Err(EACCES);
+    }
+    Ok((parent, name))
+}
+
+// open or create a file as the user uid in group gid, on whatever file
+// system the path leads to
+pub fn open_file(path: &str, flags: OpenFlags, uid: u32, gid: u32) -> Result<Arc<dyn File>, Errno> {
+    let (readable, writable) = flags.read_write();
+    let inode = open_inode(path, flags, uid, gid)?;
+    match inode.open_device() {
+        Some(dev) => Ok(Arc::new(DeviceFile::new(readable, writable, inode, dev))),
+        None => Ok(Arc::new(InodeFile::new(readable, writable, inode))),
+    }
+}
+
+// the regular file at path, emptied or made, for the kernel to fill in
+// itself through write_at
+pub fn create_file(path: &str, uid: u32, gid: u32) -> Result<Arc<dyn Inode>, Errno> {
+    let flags = OpenFlags::WRONLY | OpenFlags::CREATE | OpenFlags::TRUNC;
+    let inode = open_inode(path, flags, uid, gid)?;
+    if inode.stat()?.mode & S_IFMT != S_IFREG {
+        return Err(EINVAL);
+    }
+    Ok(inode)
+}
+
+fn open_inode(path: &str, flags: OpenFlags, uid: u32, gid: u32) -> Result<Arc<dyn Inode>, Errno> {
+    let (readable, writable) = flags.read_write();
+    match resolve(path, true, uid, gid) {
+        Ok(at) => {
+            let inode = at.inode;
+            let mut want = 0;
+            if readable {
+                want |= MAY_READ;
+            }
+            if writable || flags.contains(OpenFlags::TRUNC) {
+                want |= MAY_WRITE;
+            }
+            if !inode.permits(uid, gid, want)? {
+                return Err(EACCES);
+            }
+            if writable && inode.is_dir()? {
+                return Err(EISDIR);
+            }
+            // devices ignore O_TRUNC
+            if flags.contains(OpenFlags::TRUNC) && inode.stat()?.mode & S_IFMT == S_IFREG {
+                inode.truncate(0)?;
+            }
+            Ok(inode)
+        }
+        Err(ENOENT) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = parent_dir(path, uid, gid)?;
+            parent
+                .inode
+                .create(name, S_IFREG | DEFAULT_FILE_MODE, uid, gid)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// make the directory path unless it is there already
+pub fn mkdir_path(path: &str, mode: u32, uid: u32, gid: u32) -> Result<(), Errno> {
+    let (parent, name) = parent_dir(path, uid, gid)?;
+    match parent.inode.create(name, S_IFDIR | mode, uid, gid) {
+        Err(EEXIST) if resolve(path, true, uid, gid)?.inode.is_dir()? => Ok(()),
+        r => r.map(|_| ()),
+    }
+}
+
+pub fn stat_path(path: &str, uid: u32, gid: u32) -> Result<Stat, Errno> {
+    resolve(path, true, uid, gid)?.inode.stat()
+}
+
+// usage of the fs holding path
+pub fn statfs_path(path: &str, uid: u32, gid: u32) -> Result<StatFs, Errno> {
+    resolve(path, true, uid, gid)?.fs.statfs()
+}
+
+// a hard link new to the inode at old, which is not followed if it is a symlink
+pub fn link_path(old: &str, new: &str, uid: u32, gid: u32) -> Result<(), Errno> {
+    let at = resolve(old, false, uid, gid)?;
+    let (parent, name) = parent_dir(new, uid, gid)?;
+    if !Arc::ptr_eq(&at.fs, &parent.fs) {
+        return Err(EXDEV);
+    }
+    // no hard links to directories, whatever the fs
+    if at.inode.is_dir()? {
+        return Err(EPERM);
+    }
+    parent.inode.link(name, &at.inode)
+}
+
+pub fn symlink_path(target: &str, path: &str, uid: u32, gid: u32) -> Result<(), Errno> {
+    let (parent, name) = parent_dir(path, uid, gid)?;
+    parent.inode.symlink(name, target, uid, gid)
+}
+
+pub fn readlink_path(path: &str, uid: u32, gid: u32) -> Result<String, Errno> {
+    resolve(path, false, uid, gid)?.inode.readlink()
+}
+
+// remove the file at path, or the empty directory there when dir is set;
+// a symlink is removed itself, and a mount point stays while it is one
+pub fn unlink_path(path: &str, dir: bool, uid: u32, gid: u32) -> Result<(), Errno> {
+    let (parent, name) = parent_dir(path, uid, gid)?;
+    match name {
+        "" => return Err(EBUSY),
+        "." | ".." => return Err(EINVAL),
+        _ => {}
+    }
+    let at = alloc::format!("{}/{}", parent.path.trim_end_matches('/'), name);
+    if mount::mounted_at(&at).is_some() {
+        return Err(EBUSY);
+    }
+    match dir {
+        true => parent.inode.rmdir(name),
+        false => parent.inode.unlink(name),
+    }
+}
+
+// move old to new in one step, replacing what new named before, both
+// must be on the same fs
+pub fn rename_path(old: &str, new: &str, uid: u32, gid: u32) -> Result<(), Errno> {
+    let (old_parent, old_name) = parent_dir(old, uid, gid)?;
+    let (new_parent, new_name) = parent_dir(new, uid, gid)?;
+    if !Arc::ptr_eq(&old_parent.fs, &new_parent.fs) {
+        return Err(EXDEV);
+    }
+    // a mount point stays where it is
+    for (dir, name) in [(&old_parent, old_name), (&new_parent, new_name)] {
+        let at = alloc::format!("{}/{}", dir.path.trim_end_matches('/'), name);
+        if mount::mounted_at(&at).is_some() {
+            return Err(EBUSY);
+        }
+    }
+    old_parent
+        .inode
+        .rename(old_name, &new_parent.inode, new_name)
+}
@@ -0,0 +1,191 @@
+use alloc::sync::{Arc, Weak};
+
+use crate::{
+    mm::{Reader, UserBuf, UserBufMut, Writer},
+    sync::UCell,
+    syscall::{Errno, EBADF, EFAULT, EPIPE},
+    task::WaitQueue,
+};
+
+use super::{File, Stat, S_IFIFO};
+
+const PIPE_SIZE: usize = 4096;
+const BUFFER_SIZE: usize = 512;
+const PIPE_MODE: u32 = 0o600;
+
+// the bytes in flight between the two ends, the ends are held weakly so
+// each side sees when the other one is closed for good
+struct Ring {
+    buf: [u8; PIPE_SIZE],
+    head: usize,
+    len: usize,
+    reader: Weak<PipeReader>,
+    writer: Weak<PipeWriter>,
+}
+
+impl Ring {
+    // the longest stored run from head on
+    fn front(&self) -> &[u8] {
+        let end = (self.head + self.len).min(PIPE_SIZE);
+        &self.buf[self.head..end]
+    }
+
+    fn pop(&mut self, n: usize) {
+        self.head = (self.head + n) % PIPE_SIZE;
+        self.len -= n;
+    }
+
+    // store what fits of data, returns how much did
+    fn push(&mut self, data: &[u8]) -> usize {
+        let mut n = 0;
+        while n < data.len() && self.len < PIPE_SIZE {
+            self.buf[(self.head + self.len) % PIPE_SIZE] = data[n];
+            self.len += 1;
+            n += 1;
+        }
+        n
+    }
+}
+
+// the ring and who waits on it, a reader for bytes and a writer for room
+struct Pipe {
+    ring: UCell<Ring>,
+    readers: WaitQueue,
+    writers: WaitQueue,
+}
+
+type Shared = Arc<Pipe>;
+
+pub struct PipeReader(Shared);
+pub struct PipeWriter(Shared);
+
+// a pipe as its read end and its write end
+pub fn make_pipe() -> (Arc<PipeReader>, Arc<PipeWriter>) {
+    let pipe = Arc::new(Pipe {
+        ring: unsafe {
+            UCell::new(Ring {
+                buf: [0; PIPE_SIZE],
+                head: 0,
+                len: 0,
+                reader: Weak::new(),
+                writer: Weak::new(),
+            })
+        },
+        readers: WaitQueue::new(),
+        writers: WaitQueue::new(),
+    });
+    let reader = Arc::new(PipeReader(Arc::clone(&pipe)));
+    let writer = Arc::new(PipeWriter(Arc::clone(&pipe)));
+    let mut r = pipe.ring.exclusive_access();
+    r.reader = Arc::downgrade(&reader);
+    r.writer = Arc::downgrade(&writer);
+    drop(r);
+    (reader, writer)
+}
+
+fn pipe_stat() -> Stat {
+    Stat {
+        mode: S_IFIFO | PIPE_MODE,
+        nlink: 1,
+        ..Default::default()
+    }
+}
+
+impl File for PipeReader {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    // wait for some bytes and hand over what is there,
+    // 0 once it is empty and no writer is left
+    fn read(&self, mut buf: UserBufMut) -> Result<usize, Errno> {
+        let mut total = 0;
+        loop {
+            let mut ring = self.0.ring.exclusive_access();
+            if ring.len == 0 {
+                if total > 0 || ring.writer.upgrade().is_none() {
+                    return Ok(total);
+                }
+                drop(ring);
+                self.0.readers.wait();
+                continue;
+            }
+            let n = buf.write(ring.front()).map_err(|_| EFAULT)?;
+            let short = n < ring.front().len();
+            ring.pop(n);
+            drop(ring);
+            self.0.writers.wake_all();
+            total += n;
+            if short {
+                return Ok(total);
+            }
+        }
+    }
+    fn write(&self, _buf: UserBuf) -> Result<usize, Errno> {
+        Err(EBADF)
+    }
+    fn stat(&self) -> Stat {
+        pipe_stat()
+    }
+}
+
+impl File for PipeWriter {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    // store all of buf, waiting whenever the pipe is full,
+    // EPIPE once no reader is left
+    fn write(&self, mut buf: UserBuf) -> Result<usize, Errno> {
+        let mut tmp = [0u8; BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let len = match buf.read(&mut tmp).map_err(|_| EFAULT)? {
+                0 => return Ok(total),
+                n => n,
+            };
+            let mut done = 0;
+            while done < len {
+                let mut ring = self.0.ring.exclusive_access();
+                if ring.reader.upgrade().is_none() {
+                    return Err(EPIPE);
+                }
+                let n = ring.push(&tmp[done..len]);
+                drop(ring);
+                done += n;
+                if n == 0 {
+                    self.0.writers.wait();
+                } else {
+                    self.0.readers.wake_all();
+                }
+            }
+            total += len;
+            if len < tmp.len() {
+                return Ok(total);
+            }
+        }
+    }
+    fn read(&self, _buf: UserBufMut) -> Result<usize, Errno> {
+        Err(EBADF)
+    }
+    fn stat(&self) -> Stat {
+        pipe_stat()
+    }
+}
+
+// the other end sees the close once it wakes and finds this end gone
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.writers.wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.readers.wake_all();
+    }
+}
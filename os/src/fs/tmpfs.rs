@@ -0,0 +1,496 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::{
+    mm::{frame_new, FrameGuard, PAGE_SIZE},
+    sync::UCell,
+    syscall::{Errno, EEXIST, EINVAL, EISDIR, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY, EPERM, EXDEV},
+    timer,
+};
+
+use super::{
+    vfs::{FileSystem, Inode},
+    Stat, StatFs, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG,
+};
+
+// f_type of a tmpfs, the same as linux
+pub const TMPFS_MAGIC: i64 = 0x0102_1994;
+// the most one tmpfs may take, so a full /tmp leaves frames to the processes
+const MAX_PAGES: usize = 256;
+const MAX_INODES: usize = 1024;
+const NAME_LIMIT: usize = 255;
+const SECTOR_SIZE: usize = 512;
+const ROOT_MODE: u32 = 0o1777;
+
+// what the inodes of one tmpfs share
+struct Shared {
+    next_ino: AtomicU32,
+    pages: AtomicUsize,
+    inodes: AtomicUsize,
+}
+
+enum Content {
+    // the file data, one zeroed frame per page as it is written
+    File(Vec<FrameGuard>),
+    Dir(BTreeMap<String, Arc<Node>>),
+    Symlink(String),
+}
+
+struct NodeInner {
+    // the S_IFMT type and the permission bits
+    mode: u32,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    size: usize,
+    atime: i64,
+    mtime: i64,
+    ctime: i64,
+    content: Content,
+}
+
+// an inode of a tmpfs, it lives as long as a directory entry or an open
+// file holds it, and gives its frames back when it goes
+struct Node {
+    ino: u32,
+    shared: Arc<Shared>,
+    inner: UCell<NodeInner>,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Content::File(pages) = &self.inner.exclusive_access().content {
+            self.shared.pages.fetch_sub(pages.len(), Ordering::Relaxed);
+        }
+        self.shared.inodes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn now() -> i64 {
+    timer::wall_time_secs() as i64
+}
+
+fn check_name(name: &str) -> Result<(), Errno> {
+    match name {
+        "" | "." | ".." => Err(EINVAL),
+        _ if name.contains('/') || name.len() > NAME_LIMIT => Err(EINVAL),
+        _ => Ok(()),
+    }
+}
+
+impl Shared {
+    fn new_node(self: &Arc<Self>, mode: u32, uid: u32, gid: u32) -> Result<Arc<Node>, Errno> {
+        if self.inodes.fetch_add(1, Ordering::Relaxed) >= MAX_INODES {
+            self.inodes.fetch_sub(1, Ordering::Relaxed);
+            return Err(ENOSPC);
+        }
+        let content = match mode & S_IFMT {
+            S_IFDIR => Content::Dir(BTreeMap::new()),
+            S_IFLNK => Content::Symlink(String::new()),
+            _ => Content::File(Vec::new()),
+        };
+        let t = now();
+        let inner = NodeInner {
+            mode,
+            nlink: if mode & S_IFMT == S_IFDIR { 2 } else { 1 },
+            uid,
+            gid,
+            size: 0,
+            atime: t,
+            mtime: t,
+            ctime: t,
+            content,
+        };
+        Ok(Arc::new(Node {
+            ino: self.next_ino.fetch_add(1, Ordering::Relaxed),
+            shared: Arc::clone(self),
+            inner: unsafe { UCell::new(inner) },
+        }))
+    }
+
+    // grow or shrink pages to n frames, nothing changes when there are
+    // not enough frames
+    fn resize_pages(&self, pages: &mut Vec<FrameGuard>, n: usize) -> Result<(), Errno> {
+        let old = pages.len();
+        if n <= old {
+            pages.truncate(n);
+            self.pages.fetch_sub(old - n, Ordering::Relaxed);
+            return Ok(());
+        }
+        if self.pages.fetch_add(n - old, Ordering::Relaxed) + n - old > MAX_PAGES {
+            self.pages.fetch_sub(n - old, Ordering::Relaxed);
+            return Err(ENOSPC);
+        }
+        while pages.len() < n {
+            match frame_new() {
+                Some(frame) => pages.push(frame),
+                None => {
+                    pages.truncate(old);
+                    self.pages.fetch_sub(n - old, Ordering::Relaxed);
+                    return Err(ENOSPC);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        self.inner.exclusive_access().mode & S_IFMT == S_IFDIR
+    }
+
+    // whether node is this directory or somewhere below it
+    fn contains(self: &Arc<Self>, node: &Arc<Node>) -> bool {
+        if Arc::ptr_eq(self, node) {
+            return true;
+        }
+        match &self.inner.exclusive_access().content {
+            Content::Dir(entries) => entries.values().any(|e| e.contains(node)),
+            _ => false,
+        }
+    }
+}
+
+// a file system kept in kernel frames, it needs no device and is gone
+// once unmounted and no longer open
+pub struct TmpFs {
+    shared: Arc<Shared>,
+    root: Arc<Node>,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<Self> {
+        let shared = Arc::new(Shared {
+            next_ino: AtomicU32::new(1),
+            pages: AtomicUsize::new(0),
+            inodes: AtomicUsize::new(0),
+        });
+        let root = shared
+            .new_node(S_IFDIR | ROOT_MODE, 0, 0)
+            .expect("tmpfs root");
+        Arc::new(Self { shared, root })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(TmpInode(Arc::clone(&self.root)))
+    }
+    fn statfs(&self) -> Result<StatFs, Errno> {
+        let pages = self.shared.pages.load(Ordering::Relaxed);
+        let inodes = self.shared.inodes.load(Ordering::Relaxed);
+        Ok(StatFs {
+            fs_type: TMPFS_MAGIC,
+            bsize: PAGE_SIZE as i64,
+            blocks: MAX_PAGES as u64,
+            bfree: (MAX_PAGES - pages) as u64,
+            bavail: (MAX_PAGES - pages) as u64,
+            files: MAX_INODES as u64,
+            ffree: (MAX_INODES - inodes) as u64,
+            namelen: NAME_LIMIT as i64,
+            frsize: PAGE_SIZE as i64,
+            ..Default::default()
+        })
+    }
+}
+
+pub struct TmpInode(Arc<Node>);
+
+impl TmpInode {
+    // the node behind another inode of the same tmpfs
+    fn of<'a>(&self, inode: &'a Arc<dyn Inode>) -> Result<&'a Arc<Node>, Errno> {
+        match inode.as_any().downcast_ref::<TmpInode>() {
+            Some(TmpInode(n)) if Arc::ptr_eq(&n.shared, &self.0.shared) => Ok(n),
+            _ => Err(EXDEV),
+        }
+    }
+
+    // add the entry name for node, which must be new in this directory
+    fn add_entry(&self, name: &str, node: Arc<Node>) -> Result<(), Errno> {
+        check_name(name)?;
+        let mut inner = self.0.inner.exclusive_access();
+        let entries = match &mut inner.content {
+            Content::Dir(entries) => entries,
+            _ => return Err(ENOTDIR),
+        };
+        if entries.contains_key(name) {
+            return Err(EEXIST);
+        }
+        let is_dir = node.is_dir();
+        entries.insert(name.to_string(), node);
+        if is_dir {
+            inner.nlink += 1;
+        }
+        inner.mtime = now();
+        inner.ctime = inner.mtime;
+        Ok(())
+    }
+
+    // drop the entry name, which is a directory exactly when dir is set
+    fn remove_entry(&self, name: &str, dir: bool) -> Result<(), Errno> {
+        let mut inner = self.0.inner.exclusive_access();
+        let entries = match &mut inner.content {
+            Content::Dir(entries) => entries,
+            _ => return Err(ENOTDIR),
+        };
+        let node = entries.get(name).ok_or(ENOENT)?;
+        match (dir, &node.inner.exclusive_access().content) {
+            (true, Content::Dir(e)) if !e.is_empty() => return Err(ENOTEMPTY),
+            (true, Content::Dir(_)) => {}
+            (true, _) => return Err(ENOTDIR),
+            (false, Content::Dir(_)) => return Err(EISDIR),
+            _ => {}
+        }
+        let node = entries.remove(name).unwrap();
+        let time = now();
+        if dir {
+            inner.nlink -= 1;
+        }
+        inner.mtime = time;
+        inner.ctime = time;
+        drop(inner);
+        // the node goes once no open file holds it either
+        let mut inner = node.inner.exclusive_access();
+        inner.nlink = match dir {
+            true => 0,
+            false => inner.nlink - 1,
+        };
+        inner.ctime = time;
+        Ok(())
+    }
+}
+
+impl Inode for TmpInode {
+    fn stat(&self) -> Result<Stat, Errno> {
+        let inner = self.0.inner.exclusive_access();
+        let pages = match &inner.content {
+            Content::File(pages) => pages.len(),
+            _ => 0,
+        };
+        Ok(Stat {
+            ino: self.0.ino as u64,
+            mode: inner.mode,
+            nlink: inner.nlink,
+            uid: inner.uid,
+            gid: inner.gid,
+            size: inner.size as i64,
+            blksize: PAGE_SIZE as i32,
+            blocks: (pages * PAGE_SIZE / SECTOR_SIZE) as i64,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+            ..Default::default()
+        })
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut inner = self.0.inner.exclusive_access();
+        let size = inner.size;
+        let pages = match &inner.content {
+            Content::File(pages) => pages,
+            Content::Dir(_) => return Err(EISDIR),
+            Content::Symlink(_) => return Err(EINVAL),
+        };
+        let end = size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let off = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - off).min(end - pos);
+            let page = pages[pos / PAGE_SIZE].ppn.bytes_mut();
+            buf[pos - offset..pos - offset + n].copy_from_slice(&page[off..off + n]);
+            pos += n;
+        }
+        inner.atime = now();
+        Ok(end.saturating_sub(offset))
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        let mut inner = self.0.inner.exclusive_access();
+        let end = offset + buf.len();
+        let NodeInner { size, content, .. } = &mut *inner;
+        let pages = match content {
+            Content::File(pages) => pages,
+            Content::Dir(_) => return Err(EISDIR),
+            Content::Symlink(_) => return Err(EINVAL),
+        };
+        if end > *size {
+            self.0.shared.resize_pages(pages, end.div_ceil(PAGE_SIZE))?;
+            *size = end;
+        }
+        let mut pos = offset;
+        while pos < end {
+            let off = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - off).min(end - pos);
+            let page = pages[pos / PAGE_SIZE].ppn.bytes_mut();
+            page[off..off + n].copy_from_slice(&buf[pos - offset..pos - offset + n]);
+            pos += n;
+        }
+        inner.mtime = now();
+        inner.ctime = inner.mtime;
+        Ok(buf.len())
+    }
+    fn truncate(&self, size: usize) -> Result<(), Errno> {
+        let mut inner = self.0.inner.exclusive_access();
+        let NodeInner {
+            size: old, content, ..
+        } = &mut *inner;
+        let pages = match content {
+            Content::File(pages) => pages,
+            Content::Dir(_) => return Err(EISDIR),
+            Content::Symlink(_) => return Err(EINVAL),
+        };
+        self.0
+            .shared
+            .resize_pages(pages, size.div_ceil(PAGE_SIZE))?;
+        // what is cut off reads back as zeros when the file grows again
+        if size < *old && !size.is_multiple_of(PAGE_SIZE) {
+            pages[size / PAGE_SIZE].ppn.bytes_mut()[size % PAGE_SIZE..].fill(0);
+        }
+        *old = size;
+        inner.mtime = now();
+        inner.ctime = inner.mtime;
+        Ok(())
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        match &self.0.inner.exclusive_access().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(EINVAL),
+        }
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match &self.0.inner.exclusive_access().content {
+            Content::Dir(entries) => match entries.get(name) {
+                Some(node) => Ok(Arc::new(TmpInode(Arc::clone(node)))),
+                None => Err(ENOENT),
+            },
+            _ => Err(ENOTDIR),
+        }
+    }
+    fn ls(&self) -> Result<Vec<String>, Errno> {
+        match &self.0.inner.exclusive_access().content {
+            Content::Dir(entries) => Ok(entries.keys().cloned().collect()),
+            _ => Err(ENOTDIR),
+        }
+    }
+    fn create(&self, name: &str, mode: u32, uid: u32, gid: u32) -> Result<Arc<dyn Inode>, Errno> {
+        if !matches!(mode & S_IFMT, S_IFREG | S_IFDIR) {
+            return Err(EPERM);
+        }
+        let node = self.0.shared.new_node(mode, uid, gid)?;
+        self.add_entry(name, Arc::clone(&node))?;
+        Ok(Arc::new(TmpInode(node)))
+    }
+    fn symlink(&self, name: &str, target: &str, uid: u32, gid: u32) -> Result<(), Errno> {
+        let node = self.0.shared.new_node(S_IFLNK | 0o777, uid, gid)?;
+        {
+            let mut inner = node.inner.exclusive_access();
+            inner.size = target.len();
+            inner.content = Content::Symlink(target.to_string());
+        }
+        self.add_entry(name, node)
+    }
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        let node = self.of(target)?;
+        if node.is_dir() {
+            return Err(EPERM);
+        }
+        self.add_entry(name, Arc::clone(node))?;
+        let mut inner = node.inner.exclusive_access();
+        inner.nlink += 1;
+        inner.ctime = now();
+        Ok(())
+    }
+    fn rename(
+        &self,
+        old_name: &str,
+        new_dir: &Arc<dyn Inode>,
+        new_name: &str,
+    ) -> Result<(), Errno> {
+        check_name(new_name)?;
+        let new_dir = self.of(new_dir)?;
+        let src = match &self.0.inner.exclusive_access().content {
+            Content::Dir(entries) => Arc::clone(entries.get(old_name).ok_or(ENOENT)?),
+            _ => return Err(ENOTDIR),
+        };
+        let src_dir = src.is_dir();
+        // a directory can not move below itself
+        if src_dir && src.contains(new_dir) {
+            return Err(EINVAL);
+        }
+        let target = match &new_dir.inner.exclusive_access().content {
+            Content::Dir(entries) => entries.get(new_name).cloned(),
+            _ => return Err(ENOTDIR),
+        };
+        if let Some(t) = &target {
+            // both names already link the same inode
+            if Arc::ptr_eq(t, &src) {
+                return Ok(());
+            }
+            // a directory holding the old name is never empty
+            if Arc::ptr_eq(t, &self.0) {
+                return Err(ENOTEMPTY);
+            }
+            let t = t.inner.exclusive_access();
+            match (src_dir, &t.content) {
+                (true, Content::Dir(entries)) if !entries.is_empty() => return Err(ENOTEMPTY),
+                (true, Content::Dir(_)) => {}
+                (true, _) => return Err(ENOTDIR),
+                (false, Content::Dir(_)) => return Err(EISDIR),
+                _ => {}
+            }
+        }
+        let time = now();
+        {
+            let mut old = self.0.inner.exclusive_access();
+            if let Content::Dir(entries) = &mut old.content {
+                entries.remove(old_name);
+            }
+            if src_dir {
+                old.nlink -= 1;
+            }
+            old.mtime = time;
+            old.ctime = time;
+        }
+        {
+            let mut new = new_dir.inner.exclusive_access();
+            if let Content::Dir(entries) = &mut new.content {
+                entries.insert(new_name.to_string(), Arc::clone(&src));
+            }
+            // a replaced directory takes its ".." link along
+            if src_dir && target.is_none() {
+                new.nlink += 1;
+            }
+            new.mtime = time;
+            new.ctime = time;
+        }
+        if let Some(node) = target {
+            let mut inner = node.inner.exclusive_access();
+            inner.nlink = match src_dir {
+                true => 0,
+                false => inner.nlink - 1,
+            };
+            inner.ctime = time;
+        }
+        src.inner.exclusive_access().ctime = time;
+        Ok(())
+    }
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        self.remove_entry(name, false)
+    }
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        self.remove_entry(name, true)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,235 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use crate::{
+    mm::{Reader, UserBuf, UserBufMut, Writer},
+    sync::UCell,
+    syscall::{Errno, EFAULT, EINVAL, ENOTDIR},
+};
+
+use super::{File, Stat, StatFs, S_IFDIR, S_IFMT};
+
+const BUFFER_SIZE: usize = 512;
+// access bits for permits, the same for the owner, group and other classes
+pub const MAY_READ: u32 = 0o4;
+pub const MAY_WRITE: u32 = 0o2;
+pub const MAY_EXEC: u32 = 0o1;
+
+// a file system instance, as it is mounted somewhere in the tree
+pub trait FileSystem: Send + Sync {
+    fn fs_type(&self) -> &'static str;
+    fn root(&self) -> Arc<dyn Inode>;
+    fn statfs(&self) -> Result<StatFs, Errno>;
+    // write back what is cached, called on umount
+    fn sync(&self) -> Result<(), Errno> {
+        Ok(())
+    }
+}
+
+// a node of one file system,
+// the directory calls fail with ENOTDIR unless a file system provides them
+pub trait Inode: Send + Sync {
+    fn stat(&self) -> Result<Stat, Errno>;
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno>;
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno>;
+    fn truncate(&self, _size: usize) -> Result<(), Errno> {
+        Err(EINVAL)
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(EINVAL)
+    }
+    // the entry name in this directory, not following a symlink there
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(ENOTDIR)
+    }
+    fn ls(&self) -> Result<Vec<String>, Errno> {
+        Err(ENOTDIR)
+    }
+    // mode holds the S_IFMT type and the permission bits
+    fn create(
+        &self,
+        _name: &str,
+        _mode: u32,
+        _uid: u32,
+        _gid: u32,
+    ) -> Result<Arc<dyn Inode>, Errno> {
+        Err(ENOTDIR)
+    }
+    fn symlink(&self, _name: &str, _target: &str, _uid: u32, _gid: u32) -> Result<(), Errno> {
+        Err(ENOTDIR)
+    }
+    // the path layer makes sure target lives on the same file system
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<(), Errno> {
+        Err(ENOTDIR)
+    }
+    // the path layer makes sure new_dir lives on the same file system
+    fn rename(
+        &self,
+        _old_name: &str,
+        _new_dir: &Arc<dyn Inode>,
+        _new_name: &str,
+    ) -> Result<(), Errno> {
+        Err(ENOTDIR)
+    }
+    // drop the entry name, which is not a directory
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(ENOTDIR)
+    }
+    // drop the entry name, which is an empty directory
+    fn rmdir(&self, _name: &str) -> Result<(), Errno> {
+        Err(ENOTDIR)
+    }
+    // the driver behind a device node, which is opened as it is
+    // instead of as data at offsets
+    fn open_device(&self) -> Option<Arc<dyn Device>> {
+        None
+    }
+    // lets a file system get its own inode type back from an Arc<dyn Inode>
+    fn as_any(&self) -> &dyn Any;
+}
+
+// a character or block device, one per open of its node so a block device
+// can keep its own offset
+pub trait Device: Send + Sync {
+    fn read(&self, buf: UserBufMut) -> Result<usize, Errno>;
+    fn write(&self, buf: UserBuf) -> Result<usize, Errno>;
+}
+
+impl dyn Inode {
+    pub fn is_dir(&self) -> Result<bool, Errno> {
+        Ok(self.stat()?.mode & S_IFMT == S_IFDIR)
+    }
+
+    // whether uid in group gid may access the inode as want asks,
+    // root may do anything but run a file no one may run
+    pub fn permits(&self, uid: u32, gid: u32, want: u32) -> Result<bool, Errno> {
+        let st = self.stat()?;
+        if uid == 0 {
+            return Ok(want & MAY_EXEC == 0 || st.mode & S_IFMT == S_IFDIR || st.mode & 0o111 != 0);
+        }
+        let class = if uid == st.uid {
+            st.mode >> 6
+        } else if gid == st.gid {
+            st.mode >> 3
+        } else {
+            st.mode
+        };
+        Ok(class & want == want)
+    }
+}
+
+struct InodeFileInner {
+    offset: usize,
+    inode: Arc<dyn Inode>,
+}
+
+// an open inode of any file system with its own offset
+pub struct InodeFile {
+    readable: bool,
+    writable: bool,
+    inner: UCell<InodeFileInner>,
+}
+
+impl InodeFile {
+    pub fn new(readable: bool, writable: bool, inode: Arc<dyn Inode>) -> Self {
+        Self {
+            readable,
+            writable,
+            inner: unsafe { UCell::new(InodeFileInner { offset: 0, inode }) },
+        }
+    }
+}
+
+impl File for InodeFile {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, mut buf: UserBufMut) -> Result<usize, Errno> {
+        let mut inner = self.inner.exclusive_access();
+        let mut tmp = [0u8; BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let n = match inner.inode.read_at(inner.offset, &mut tmp)? {
+                0 => break,
+                n => n,
+            };
+            let copied = buf.write(&tmp[..n]).map_err(|_| EFAULT)?;
+            inner.offset += copied;
+            total += copied;
+            if copied < n {
+                break;
+            }
+        }
+        Ok(total)
+    }
+    fn write(&self, mut buf: UserBuf) -> Result<usize, Errno> {
+        let mut inner = self.inner.exclusive_access();
+        let mut tmp = [0u8; BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let n = match buf.read(&mut tmp).map_err(|_| EFAULT)? {
+                0 => break,
+                n => n,
+            };
+            inner.inode.write_at(inner.offset, &tmp[..n])?;
+            inner.offset += n;
+            total += n;
+            if n < tmp.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+    fn stat(&self) -> Stat {
+        self.inner
+            .exclusive_access()
+            .inode
+            .stat()
+            .unwrap_or_default()
+    }
+}
+
+// an open device node, with the access it was opened for
+pub struct DeviceFile {
+    readable: bool,
+    writable: bool,
+    inode: Arc<dyn Inode>,
+    dev: Arc<dyn Device>,
+}
+
+impl DeviceFile {
+    pub fn new(
+        readable: bool,
+        writable: bool,
+        inode: Arc<dyn Inode>,
+        dev: Arc<dyn Device>,
+    ) -> Self {
+        Self {
+            readable,
+            writable,
+            inode,
+            dev,
+        }
+    }
+}
+
+impl File for DeviceFile {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: UserBufMut) -> Result<usize, Errno> {
+        self.dev.read(buf)
+    }
+    fn write(&self, buf: UserBuf) -> Result<usize, Errno> {
+        self.dev.write(buf)
+    }
+    fn stat(&self) -> Stat {
+        self.inode.stat().unwrap_or_default()
+    }
+}
@@ -1,9 +1,15 @@
-use core::panic::PanicInfo;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use crate::println;
+use crate::{backtrace, println};
 
 use super::sbi::shut_down;
 
+// a panic while walking the stack only prints its message
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     if let Some(location) = info.location() {
@@ -16,5 +22,8 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("Panic: {}", info.message())
     }
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        backtrace::print();
+    }
     shut_down(true)
 }
@@ -4,8 +4,11 @@
 
 extern crate alloc;
 
+mod backtrace;
 mod config;
 mod console;
+mod drivers;
+mod fs;
 mod lang_items;
 mod logging;
 mod mm;
@@ -25,15 +28,20 @@ global_asm!(include_str!("link_app.asm"));
 
 #[no_mangle]
 #[allow(unreachable_code)]
-fn rust_main() -> ! {
+fn rust_main(hart: usize, dtb: usize) -> ! {
     clear_bss();
     logging::init();
+    drivers::probe(dtb);
     debug!("[kernel] init mm");
     mm::init();
     debug!("[kernel] init loader");
     loader::init();
     debug!("[kernel] init trap");
     trap::init();
+    debug!("[kernel] init drivers");
+    drivers::init(hart);
+    debug!("[kernel] init fs");
+    fs::init();
     // for test
     // mm::heap_allocator::test_heap();
     //mm::test_frame_alloc();
@@ -43,6 +51,7 @@ fn rust_main() -> ! {
     task::add_init_proc();
     trace!("start loading");
     trap::enable_timer_interrupt();
+    trap::enable_external_interrupt();
     timer::set_next_trigger();
     trace!("start running");
     task::run_tasks();
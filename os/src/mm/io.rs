@@ -1,6 +1,12 @@
+use alloc::vec::Vec;
 use core::cmp::min;
 
-use super::{page_table::PageTable, PhysPageNum, VirtAddress};
+use crate::syscall::{Errno, EFAULT, ENAMETOOLONG};
+
+use super::{
+    page_table::{PTEFlags, PageTable},
+    VirtAddress, PAGE_SIZE,
+};
 
 pub struct IOError {
     pub msg: &'static str,
@@ -13,6 +19,38 @@ pub trait Writer {
     fn write(&mut self, buf: &[u8]) -> Result<usize, IOError>;
 }
 
+const BAD_ADDRESS: IOError = IOError {
+    msg: "bad user address",
+};
+
+// the bytes from va up to end or the end of its page, as long as the page
+// is mapped for the user with need; the trap context and the trampoline
+// are mapped without U and never pass.
+//
+// this walk is all that guards a user copy, there is no exception table
+// fixup: the copy goes through the kernel's identity mapping of the frame,
+// never through the user address itself, so it can not fault. that holds
+// because a user pte only ever points at a frame from frame_new: every U
+// area is MapType::Framed, and the allocator hands out frames from
+// [ekernel, MEMORY_END), which new_kernel_map identity maps R|W. mapping
+// anything else for the user, device memory say, needs a fixup first
+fn user_chunk(
+    pt: &PageTable,
+    va: usize,
+    end: usize,
+    need: PTEFlags,
+) -> Result<&'static mut [u8], IOError> {
+    let addr = VirtAddress::from(va);
+    match pt.translate(addr.floor()) {
+        Some(pte) if pte.is_valid() && pte.flags().contains(need | PTEFlags::U) => {
+            let off = addr.page_offset();
+            let len = min(PAGE_SIZE - off, end - va);
+            Ok(&mut pte.ppn().bytes_mut()[off..off + len])
+        }
+        _ => Err(BAD_ADDRESS),
+    }
+}
+
 pub struct UserBuf {
     start: usize,
     end: usize,
@@ -22,47 +60,20 @@ impl UserBuf {
     pub fn new(satp: usize, ptr: *const u8, len: usize) -> Self {
         Self {
             start: ptr as usize,
-            end: ptr as usize + len,
+            end: (ptr as usize).saturating_add(len),
             pt: PageTable::from_token(satp),
         }
     }
 }
 impl Reader for UserBuf {
-    fn read(&mut self, mut buf: &mut [u8]) -> Result<usize, IOError> {
-        let end_ppn = self.pt.translate(VirtAddress::from(self.end).floor());
-        let end_ppn = match end_ppn {
-            Some(p) => p.ppn(),
-            None => {
-                return Err(IOError {
-                    msg: "bad page mapping",
-                });
-            }
-        };
-        let start_va = VirtAddress::from(self.start);
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
         let mut readed = 0;
-        while self.start < self.end {
-            let start_ppn = match self.pt.translate(start_va.floor()) {
-                Some(p) => p.ppn(),
-                None => {
-                    return Err(IOError {
-                        msg: "bad page mapping",
-                    });
-                }
-            };
-            let read_buf = if start_ppn == end_ppn {
-                let end_va = VirtAddress::from(self.end);
-                &mut start_ppn.bytes_mut()[start_va.page_offset()..end_va.page_offset()]
-            } else {
-                &mut start_ppn.bytes_mut()[start_va.page_offset()..]
-            };
-            let to_read = min(buf.len(), read_buf.len());
-            buf[..to_read].copy_from_slice(&read_buf[..to_read]);
-            readed += to_read;
-            buf = &mut buf[to_read..];
-            self.start += to_read;
-            if buf.len() == 0 {
-                break;
-            }
+        while readed < buf.len() && self.start < self.end {
+            let chunk = user_chunk(&self.pt, self.start, self.end, PTEFlags::R)?;
+            let n = min(chunk.len(), buf.len() - readed);
+            buf[readed..readed + n].copy_from_slice(&chunk[..n]);
+            readed += n;
+            self.start += n;
         }
         Ok(readed)
     }
@@ -72,11 +83,11 @@ pub struct UserBufMut(UserBuf);
 
 impl UserBufMut {
     pub fn new(satp: usize, ptr: *mut u8, len: usize) -> Self {
-        Self(UserBuf {
-            start: ptr as usize,
-            end: ptr as usize + len,
-            pt: PageTable::from_token(satp),
-        })
+        Self(UserBuf::new(satp, ptr, len))
+    }
+    // bytes left to write
+    pub fn remaining(&self) -> usize {
+        self.0.end - self.0.start
     }
 }
 
@@ -87,93 +98,53 @@ impl Reader for UserBufMut {
 }
 
 impl Writer for UserBufMut {
-    fn write(&mut self, mut buf: &[u8]) -> Result<usize, IOError> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IOError> {
         let s = &mut self.0;
-        let end_ppn = s.pt.translate(VirtAddress::from(s.end).floor());
-        let end_ppn = match end_ppn {
-            Some(p) => p.ppn(),
-            None => {
-                return Err(IOError {
-                    msg: "bad page mapping",
-                });
-            }
-        };
-        let start_va = VirtAddress::from(s.start);
         let mut written = 0;
-        while s.start < s.end {
-            let start_ppn = match s.pt.translate(start_va.floor()) {
-                Some(p) => p.ppn(),
-                None => {
-                    return Err(IOError {
-                        msg: "bad page mapping",
-                    });
-                }
-            };
-            let write_buf = if start_ppn == end_ppn {
-                let end_va = VirtAddress::from(s.end);
-                &mut start_ppn.bytes_mut()[start_va.page_offset()..end_va.page_offset()]
-            } else {
-                &mut start_ppn.bytes_mut()[start_va.page_offset()..]
-            };
-            let to_write = min(buf.len(), write_buf.len());
-            write_buf[..to_write].copy_from_slice(&buf[..to_write]);
-            written += to_write;
-            buf = &buf[to_write..];
-            s.start += to_write;
-            if buf.len() == 0 {
-                break;
-            }
+        while written < buf.len() && s.start < s.end {
+            let chunk = user_chunk(&s.pt, s.start, s.end, PTEFlags::W)?;
+            let n = min(chunk.len(), buf.len() - written);
+            chunk[..n].copy_from_slice(&buf[written..written + n]);
+            written += n;
+            s.start += n;
         }
         Ok(written)
     }
 }
 
-pub fn iter_from_user_ptr(ptr: *const u8, token: usize) -> BytePtrIter {
-    BytePtrIter {
-        pt: PageTable::from_token(token),
-        ptr: VirtAddress(ptr as usize),
-        ppn: None,
+// fill dst from the user memory at src, EFAULT unless all of it is there
+// and readable to the user
+pub fn copy_from_user(token: usize, src: *const u8, dst: &mut [u8]) -> Result<(), Errno> {
+    match UserBuf::new(token, src, dst.len()).read(dst) {
+        Ok(n) if n == dst.len() => Ok(()),
+        _ => Err(EFAULT),
     }
 }
 
-pub fn translate_ptr_mut<T>(ptr: *mut T, token: usize) -> Option<&'static mut T> {
-    let pt = PageTable::from_token(token);
-    let va = VirtAddress::from(ptr as usize);
-    match pt.translate(va.floor()) {
-        None => None,
-        Some(e) => {
-            if !e.writable() {
-                None
-            } else {
-                Some(e.ppn().get_mut_at_offset(va.page_offset()))
-            }
-        }
+// store src to the user memory at dst, EFAULT unless all of it is there
+// and writable to the user
+pub fn copy_to_user(token: usize, dst: *mut u8, src: &[u8]) -> Result<(), Errno> {
+    match UserBufMut::new(token, dst, src.len()).write(src) {
+        Ok(n) if n == src.len() => Ok(()),
+        _ => Err(EFAULT),
     }
 }
 
-pub struct BytePtrIter {
-    pt: PageTable,
-    ptr: VirtAddress,
-    ppn: Option<PhysPageNum>,
-}
-
-impl Iterator for BytePtrIter {
-    type Item = u8;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.ppn.is_none() {
-            let entry = self.pt.translate(self.ptr.floor());
-            match entry {
-                None => {
-                    return None;
-                }
-                Some(e) => self.ppn = Some(e.ppn()),
-            }
-        }
-        let b: u8 = *self.ppn.unwrap().get_mut_at_offset(self.ptr.page_offset());
-        self.ptr.0 += 1;
-        if self.ptr.page_offset() == 0 {
-            self.ppn = None
+// the nul terminated string at src without its nul, ENAMETOOLONG when
+// there is no nul in the first max bytes
+pub fn strncpy_from_user(token: usize, src: *const u8, max: usize) -> Result<Vec<u8>, Errno> {
+    let pt = PageTable::from_token(token);
+    let end = (src as usize).saturating_add(max + 1);
+    let mut va = src as usize;
+    let mut s = Vec::new();
+    while va < end {
+        let chunk = user_chunk(&pt, va, end, PTEFlags::R).map_err(|_| EFAULT)?;
+        if let Some(nul) = chunk.iter().position(|&c| c == 0) {
+            s.extend_from_slice(&chunk[..nul]);
+            return Ok(s);
         }
-        Some(b)
+        s.extend_from_slice(chunk);
+        va += chunk.len();
     }
+    Err(ENAMETOOLONG)
 }
@@ -3,7 +3,7 @@ use core::{
     cmp::{max, min},
 };
 
-use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec, vec::Vec};
 use bitflags::bitflags;
 use lazy_static::lazy_static;
 use log::debug;
@@ -11,15 +11,18 @@ use riscv::register;
 use xmas_elf;
 
 use crate::{
-    config::{KERNEL_STACK_LIMIT, USER_STACK_LIMIT},
+    config::{KERNEL_STACK_LIMIT, MMIO, USER_HEAP_LIMIT, USER_STACK_LIMIT},
+    drivers::plic,
     mm::address::PhysAddress,
     println,
     sync::UCell,
+    timer,
 };
 
 use super::{
     address::{VPNRange, VirtAddress, VirtPageNum, PAGE_SIZE},
     frame_allocator::{frame_new, FrameGuard, MEMORY_END},
+    io::copy_to_user,
     page_table::{PTEFlags, PageTable},
 };
 
@@ -100,35 +103,64 @@ impl MapArea {
         pt.unmap(vpn);
     }
 
-    fn copy_data(&mut self, data: &[u8], pt: &PageTable) {
+    // data goes offset bytes into the first page, segments of an elf need
+    // not start on a page boundary
+    fn copy_data(&mut self, data: &[u8], offset: usize, pt: &PageTable) {
         assert_eq!(MapType::Framed, self.map_type);
         let len = data.len();
         let mut wrote = 0;
+        let mut offset = offset;
         for vpn in &self.vpns {
-            let to_wrote = min(PAGE_SIZE, len - wrote);
+            let to_wrote = min(PAGE_SIZE - offset, len - wrote);
             if to_wrote <= 0 {
                 break;
             }
             let ppn = pt.translate(vpn).unwrap().ppn();
-            let dst = &mut ppn.bytes_mut()[..to_wrote];
+            let dst = &mut ppn.bytes_mut()[offset..offset + to_wrote];
             let src = &data[wrote..wrote + to_wrote];
             dst.copy_from_slice(src);
-            wrote += to_wrote
+            wrote += to_wrote;
+            offset = 0;
         }
         assert_eq!(len, wrote)
     }
+    // map or unmap pages at the top so the area ends at r
+    fn resize(&mut self, r: VirtPageNum, pt: &mut PageTable) {
+        while self.vpns.r < r {
+            self.map_one(self.vpns.r, pt);
+            self.vpns.r += 1;
+        }
+        while self.vpns.r > r {
+            self.vpns.r.0 -= 1;
+            self.unmap_one(self.vpns.r, pt);
+        }
+    }
 }
 
 pub struct MemorySet {
     pub page_table: PageTable,
     areas: Vec<MapArea>,
+    // the heap brk(2) moves, from heap_bottom up to brk
+    heap_bottom: usize,
+    brk: usize,
 }
 
+// keys of the aux vector a libc reads at startup
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
 impl MemorySet {
     pub fn bare_new() -> Self {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            heap_bottom: 0,
+            brk: 0,
         }
     }
     pub fn fork(&self) -> Self {
@@ -148,15 +180,21 @@ impl MemorySet {
         let mut ms = Self {
             page_table: pt,
             areas: areas,
+            heap_bottom: self.heap_bottom,
+            brk: self.brk,
         };
         ms.map_trampoline();
         ms
     }
 
-    fn push(&mut self, mut area: MapArea, data: Option<&[u8]>) {
+    fn push(&mut self, area: MapArea, data: Option<&[u8]>) {
+        self.push_at(area, data, 0)
+    }
+
+    fn push_at(&mut self, mut area: MapArea, data: Option<&[u8]>, offset: usize) {
         area.map(&mut self.page_table);
         if let Some(data) = data {
-            area.copy_data(data, &self.page_table)
+            area.copy_data(data, offset, &self.page_table)
         }
         self.areas.push(area);
     }
@@ -180,6 +218,40 @@ impl MemorySet {
         })
     }
 
+    // brk(2), move the end of the heap to new and return where it ends up,
+    // which stays put when new is out of range; 0 asks where it is
+    pub fn set_brk(&mut self, new: usize) -> usize {
+        if new < self.heap_bottom || new > self.heap_bottom + USER_HEAP_LIMIT {
+            return self.brk;
+        }
+        let bottom = VirtAddress(self.heap_bottom).floor();
+        let heap = self
+            .areas
+            .iter_mut()
+            .find(|a| a.vpns.l == bottom)
+            .expect("no heap area");
+        heap.resize(VirtAddress(new).ceil(), &mut self.page_table);
+        self.brk = new;
+        new
+    }
+
+    // the start, end and permission of each area user code can reach,
+    // lowest first
+    pub fn user_areas(&self) -> Vec<(usize, usize, MapPermission)> {
+        let mut areas: Vec<_> = self
+            .areas
+            .iter()
+            .filter(|a| a.map_perm.contains(MapPermission::U) && a.vpns.l < a.vpns.r)
+            .map(|a| {
+                let start = VirtAddress::from(a.vpns.l).0;
+                let end = VirtAddress::from(a.vpns.r).0;
+                (start, end, a.map_perm.clone())
+            })
+            .collect();
+        areas.sort_by_key(|a| a.0);
+        areas
+    }
+
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -198,13 +270,17 @@ impl MemorySet {
             PTEFlags::X | PTEFlags::R,
         );
     }
-    pub fn new_app_from_elf(elf: &[u8]) -> (Self, usize, usize) {
+    // the address space for elf, with name as argv[0] on its stack;
+    // returns it with the initial sp and the entry point
+    pub fn new_app_from_elf(elf: &[u8], name: &str) -> (Self, usize, usize) {
         let mut ms = MemorySet::bare_new();
         ms.map_trampoline();
         let elf = xmas_elf::ElfFile::new(elf).unwrap();
         let magic = elf.header.pt1.magic;
         assert_eq!([0x7f, 0x45, 0x4c, 0x46], magic, "bad elf file");
         let mut max_end_vpn: VirtPageNum = VirtPageNum(0);
+        let ph_offset = elf.header.pt2.ph_offset() as usize;
+        let mut phdr = None;
         for header in elf.program_iter() {
             if header.get_type().unwrap() == xmas_elf::program::Type::Load {
                 let start_va: VirtAddress = (header.virtual_addr() as usize).into();
@@ -222,9 +298,13 @@ impl MemorySet {
                 }
                 let area = MapArea::new(start_va, end_va, MapType::Framed, flag);
                 max_end_vpn = max(max_end_vpn, area.vpns.r);
-                let data = &elf.input
-                    [header.offset() as usize..(header.offset() + header.file_size()) as usize];
-                ms.push(area, Some(data))
+                let offset = header.offset() as usize;
+                let data = &elf.input[offset..offset + header.file_size() as usize];
+                // the program headers, if this segment loads them
+                if (offset..offset + data.len()).contains(&ph_offset) {
+                    phdr = Some(start_va.0 + ph_offset - offset);
+                }
+                ms.push_at(area, Some(data), start_va.page_offset())
             }
         }
 
@@ -242,6 +322,18 @@ impl MemorySet {
             ),
             None,
         );
+        // an empty heap a gap page above the stack
+        ms.heap_bottom = stack_top.0 + PAGE_SIZE;
+        ms.brk = ms.heap_bottom;
+        ms.push(
+            MapArea::new(
+                ms.heap_bottom.into(),
+                ms.heap_bottom.into(),
+                MapType::Framed,
+                MapPermission::U | MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
         // map the trap context page
         ms.push(
             MapArea::new(
@@ -252,7 +344,53 @@ impl MemorySet {
             ),
             None,
         );
-        (ms, stack_top.0, elf.header.pt2.entry_point() as usize)
+        let entry = elf.header.pt2.entry_point() as usize;
+        let mut auxv = vec![
+            (AT_PHENT, elf.header.pt2.ph_entry_size() as usize),
+            (AT_PHNUM, elf.header.pt2.ph_count() as usize),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_ENTRY, entry),
+        ];
+        if let Some(phdr) = phdr {
+            auxv.push((AT_PHDR, phdr));
+        }
+        let sp = ms.push_initial_stack(stack_top.0, name, auxv);
+        (ms, sp, entry)
+    }
+
+    // what linux leaves on the stack for _start: argc at sp, then argv,
+    // envp and the aux vector, with the strings they point to above
+    fn push_initial_stack(&self, top: usize, name: &str, mut auxv: Vec<(usize, usize)>) -> usize {
+        let token = self.page_table.token();
+        let mut sp = top;
+        let mut push = |bytes: &[u8]| {
+            sp -= bytes.len();
+            copy_to_user(token, sp as *mut u8, bytes).expect("user stack not mapped");
+            sp
+        };
+        // not secret, only different from one run to the next
+        let mut random = [0u8; 16];
+        let mut x = ((timer::wall_time_secs() << 20) ^ timer::get_time_ms() as u64) | 1;
+        for chunk in random.chunks_mut(8) {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            chunk.copy_from_slice(&x.to_le_bytes());
+        }
+        let random = push(&random);
+        push(&[0]);
+        let argv0 = push(name.as_bytes());
+        auxv.push((AT_RANDOM, random));
+        auxv.push((AT_NULL, 0));
+        // argc, argv[0], the end of argv, the end of envp
+        let mut words = vec![1, argv0, 0, 0];
+        for (k, v) in auxv {
+            words.extend([k, v]);
+        }
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        sp = (sp - bytes.len()) & !0xf;
+        copy_to_user(token, sp as *mut u8, &bytes).expect("user stack not mapped");
+        sp
     }
 }
 
@@ -323,6 +461,19 @@ fn new_kernel_map() -> MemorySet {
         ),
         None,
     );
+    debug!("map mmio");
+    let plic = plic::region().map(|r| (r.base, r.size));
+    for &(start, len) in MMIO.iter().chain(plic.iter()) {
+        ms.push(
+            MapArea::new(
+                start.into(),
+                (start + len).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+    }
     ms
 }
 
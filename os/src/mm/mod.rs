@@ -13,8 +13,11 @@ pub fn init() {
     memory_set::KERNEL_SPACE.exclusive_access().activate();
 }
 
-pub use address::{PhysPageNum, VirtAddress};
-pub use io::{iter_from_user_ptr, translate_ptr_mut, Reader, UserBuf, UserBufMut, Writer};
+pub use address::{PhysPageNum, VirtAddress, PAGE_SIZE};
+pub use frame_allocator::{frame_new, FrameGuard};
+pub use io::{
+    copy_from_user, copy_to_user, strncpy_from_user, Reader, UserBuf, UserBufMut, Writer,
+};
 pub use memory_set::{
     kernel_stack_position, MapPermission, MemorySet, KERNEL_SPACE, TRAMPOLINE, TRAP_CONTEXT,
 };
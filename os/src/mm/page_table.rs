@@ -42,7 +42,7 @@ impl PageTableEntry {
             bits: ppn.0 << 10 | flags.bits() as usize,
         }
     }
-    fn is_valid(&self) -> bool {
+    pub fn is_valid(&self) -> bool {
         self.flags().contains(PTEFlags::V)
     }
     pub fn readable(&self) -> bool {
@@ -1,66 +1,294 @@
-use core::str;
+use alloc::{string::String, sync::Arc};
 
 use crate::{
-    mm::{translate_ptr_mut, Reader, UserBuf},
-    print, println,
-    sbi::console_get_char,
-    task::get_current_token,
+    fs::{
+        link_path, make_pipe, mount, open_file, readlink_path, rename_path, stat_path, statfs_path,
+        symlink_path, umount, unlink_path, File, OpenFlags,
+    },
+    mm::{copy_from_user, copy_to_user, strncpy_from_user, UserBuf, UserBufMut},
+    task::{get_current_task, get_current_token},
 };
 
-use super::{EAGAIN, EBADARG};
-
-const STDIN: usize = 0;
-const STDOUT: usize = 1;
-const BUFFER_SIZE: usize = 2048;
-pub fn sys_write(fd: usize, address: *const u8, len: usize) -> isize {
-    match fd {
-        STDOUT => {
-            let mut buffers = UserBuf::new(get_current_token(), address, len);
-            let mut buf = [0; BUFFER_SIZE];
-            let mut written = 0;
-            loop {
-                match buffers.read(&mut buf) {
-                    Err(err) => {
-                        println!("read from user failed: {}", err.msg);
-                        return -1;
-                    }
-                    Ok(readed) => {
-                        let valid_buf = &buf[..readed];
-                        unsafe {
-                            print!("{}", str::from_utf8_unchecked(valid_buf));
-                        }
-                        written += readed;
-                        if readed < buf.len() {
-                            break;
-                        }
-                    }
+use super::{Errno, EBADF, EINVAL, EMFILE};
+
+const PATH_LENGTH_LIMIT: usize = 128;
+// dirfd meaning the working directory, which is always the root for now
+const AT_FDCWD: isize = -100;
+// unlinkat removes a directory instead of a file
+const AT_REMOVEDIR: u32 = 0x200;
+// the most iovecs one writev takes
+const IOV_MAX: usize = 1024;
+
+fn current_file(fd: usize) -> Result<Arc<dyn File>, Errno> {
+    get_current_task()
+        .unwrap()
+        .exclusive_access()
+        .get_file(fd)
+        .ok_or(EBADF)
+}
+
+pub fn sys_write(fd: usize, address: *const u8, len: usize) -> Result<usize, Errno> {
+    let token = get_current_token();
+    let file = current_file(fd)?;
+    if !file.writable() {
+        return Err(EBADF);
+    }
+    file.write(UserBuf::new(token, address, len))
+}
+
+// writev(2), the iovecs one after another until one comes up short
+pub fn sys_writev(fd: usize, iov: *const u8, count: usize) -> Result<usize, Errno> {
+    if count > IOV_MAX {
+        return Err(EINVAL);
+    }
+    let token = get_current_token();
+    let mut total = 0;
+    for i in 0..count {
+        // struct iovec { void *base; size_t len; }
+        let mut vec = [0u8; 16];
+        copy_from_user(token, iov.wrapping_add(i * vec.len()), &mut vec)?;
+        let base = usize::from_ne_bytes(vec[..8].try_into().unwrap());
+        let len = usize::from_ne_bytes(vec[8..].try_into().unwrap());
+        match sys_write(fd, base as *const u8, len) {
+            Ok(n) => {
+                total += n;
+                if n < len {
+                    break;
                 }
             }
-            written as isize
+            Err(e) if total == 0 => return Err(e),
+            Err(_) => break,
         }
-        _ => -1,
     }
+    Ok(total)
 }
-pub fn sys_read(fd: usize, address: *mut u8, len: usize) -> isize {
-    match fd {
-        STDIN => {
-            // seems get_char will block
-            let c = console_get_char();
-            if c == 0 {
-                EAGAIN
-            } else {
-                if len < 1 {
-                    return EBADARG;
-                }
-                match translate_ptr_mut(address, get_current_token()) {
-                    Some(ptr) => {
-                        *ptr = c as u8;
-                        1
-                    }
-                    None => -1,
-                }
-            }
+
+pub fn sys_read(fd: usize, address: *mut u8, len: usize) -> Result<usize, Errno> {
+    if len < 1 {
+        return Err(EINVAL);
+    }
+    let token = get_current_token();
+    let file = current_file(fd)?;
+    if !file.readable() {
+        return Err(EBADF);
+    }
+    file.read(UserBufMut::new(token, address, len))
+}
+
+fn current_ids() -> (u32, u32) {
+    let task = get_current_task().unwrap();
+    let t = task.exclusive_access();
+    (t.uid, t.gid)
+}
+
+fn read_path(ptr: *const u8) -> Result<String, Errno> {
+    let path = strncpy_from_user(get_current_token(), ptr, PATH_LENGTH_LIMIT)?;
+    String::from_utf8(path).map_err(|_| EINVAL)
+}
+
+// the path at ptr, for an *at call given dirfd; only absolute paths and
+// ones from the working directory resolve yet
+fn read_path_at(dirfd: isize, ptr: *const u8) -> Result<String, Errno> {
+    let path = read_path(ptr)?;
+    if dirfd != AT_FDCWD && !path.starts_with('/') {
+        return Err(EBADF);
+    }
+    Ok(path)
+}
+
+// openat(2); new files always get the default mode, so mode goes unused,
+// and like linux the flags not known here are ignored
+pub fn sys_openat(dirfd: isize, path: *const u8, flags: u32, _mode: u32) -> Result<usize, Errno> {
+    let path = read_path_at(dirfd, path)?;
+    let flags = OpenFlags::from_bits_truncate(flags);
+    let (uid, gid) = current_ids();
+    let file = open_file(&path, flags, uid, gid)?;
+    let task = get_current_task().unwrap();
+    let fd = task.exclusive_access().alloc_fd(file).ok_or(EMFILE)?;
+    Ok(fd)
+}
+
+pub fn sys_close(fd: usize) -> Result<usize, Errno> {
+    let task = get_current_task().unwrap();
+    let mut t = task.exclusive_access();
+    match t.get_fd_table().and_then(|table| table.get_mut(fd)) {
+        Some(f) if f.is_some() => {
+            f.take();
+            Ok(0)
         }
-        _ => -1,
+        _ => Err(EBADF),
+    }
+}
+
+// pipe2(2), no flags are known yet
+pub fn sys_pipe(fds: *mut i32, flags: u32) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(EINVAL);
+    }
+    let token = get_current_token();
+    let (reader, writer) = make_pipe();
+    let task = get_current_task().unwrap();
+    let mut t = task.exclusive_access();
+    let rfd = t.alloc_fd(reader).ok_or(EMFILE)?;
+    let wfd = match t.alloc_fd(writer) {
+        Some(fd) => fd,
+        None => {
+            t.get_fd_table().unwrap()[rfd] = None;
+            return Err(EMFILE);
+        }
+    };
+    let mut pair = [0u8; 8];
+    pair[..4].copy_from_slice(&(rfd as i32).to_ne_bytes());
+    pair[4..].copy_from_slice(&(wfd as i32).to_ne_bytes());
+    copy_to_user(token, fds as *mut u8, &pair).inspect_err(|_| {
+        let table = t.get_fd_table().unwrap();
+        table[rfd] = None;
+        table[wfd] = None;
+    })?;
+    Ok(0)
+}
+
+// the lowest free fd for the file at fd
+pub fn sys_dup(fd: usize) -> Result<usize, Errno> {
+    let task = get_current_task().unwrap();
+    let mut t = task.exclusive_access();
+    let file = t.get_file(fd).ok_or(EBADF)?;
+    t.alloc_fd(file).ok_or(EMFILE)
+}
+
+// new_fd now names the file at old_fd, whatever it named before is closed;
+// like dup3(2) the two must differ and no flags are known yet
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> Result<usize, Errno> {
+    if flags != 0 || old_fd == new_fd {
+        return Err(EINVAL);
+    }
+    let task = get_current_task().unwrap();
+    let mut t = task.exclusive_access();
+    let file = t.get_file(old_fd).ok_or(EBADF)?;
+    t.set_fd(new_fd, file).ok_or(EBADF)?;
+    Ok(new_fd)
+}
+
+fn copy_stat(st: &crate::fs::Stat, ptr: *mut u8) -> Result<usize, Errno> {
+    copy_to_user(get_current_token(), ptr, st.as_bytes())?;
+    Ok(0)
+}
+
+pub fn sys_fstat(fd: usize, st: *mut u8) -> Result<usize, Errno> {
+    copy_stat(&current_file(fd)?.stat(), st)
+}
+
+// fstatat(2), no flags are known yet
+pub fn sys_fstatat(dirfd: isize, path: *const u8, st: *mut u8, flags: u32) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(EINVAL);
+    }
+    let path = read_path_at(dirfd, path)?;
+    let (uid, gid) = current_ids();
+    copy_stat(&stat_path(&path, uid, gid)?, st)
+}
+
+pub fn sys_statfs(path: *const u8, buf: *mut u8) -> Result<usize, Errno> {
+    let path = read_path(path)?;
+    let (uid, gid) = current_ids();
+    let st = statfs_path(&path, uid, gid)?;
+    copy_to_user(get_current_token(), buf, st.as_bytes())?;
+    Ok(0)
+}
+
+// linkat(2), no flags are known yet
+pub fn sys_linkat(
+    old_dirfd: isize,
+    old: *const u8,
+    new_dirfd: isize,
+    new: *const u8,
+    flags: u32,
+) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(EINVAL);
+    }
+    let (uid, gid) = current_ids();
+    let old = read_path_at(old_dirfd, old)?;
+    let new = read_path_at(new_dirfd, new)?;
+    link_path(&old, &new, uid, gid)?;
+    Ok(0)
+}
+
+// symlinkat(2), the target is stored as it is and not resolved
+pub fn sys_symlinkat(target: *const u8, dirfd: isize, path: *const u8) -> Result<usize, Errno> {
+    let (uid, gid) = current_ids();
+    let target = read_path(target)?;
+    let path = read_path_at(dirfd, path)?;
+    symlink_path(&target, &path, uid, gid)?;
+    Ok(0)
+}
+
+// unlinkat(2), AT_REMOVEDIR makes it rmdir
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> Result<usize, Errno> {
+    if flags & !AT_REMOVEDIR != 0 {
+        return Err(EINVAL);
+    }
+    let (uid, gid) = current_ids();
+    let path = read_path_at(dirfd, path)?;
+    unlink_path(&path, flags & AT_REMOVEDIR != 0, uid, gid)?;
+    Ok(0)
+}
+
+pub fn sys_renameat(
+    old_dirfd: isize,
+    old: *const u8,
+    new_dirfd: isize,
+    new: *const u8,
+) -> Result<usize, Errno> {
+    let (uid, gid) = current_ids();
+    let old = read_path_at(old_dirfd, old)?;
+    let new = read_path_at(new_dirfd, new)?;
+    rename_path(&old, &new, uid, gid)?;
+    Ok(0)
+}
+
+// like readlinkat(2) the target is not nul terminated and cut to fit the buffer
+pub fn sys_readlinkat(
+    dirfd: isize,
+    path: *const u8,
+    buf: *mut u8,
+    len: usize,
+) -> Result<usize, Errno> {
+    let (uid, gid) = current_ids();
+    let target = readlink_path(&read_path_at(dirfd, path)?, uid, gid)?;
+    let n = target.len().min(len);
+    copy_to_user(get_current_token(), buf, &target.as_bytes()[..n])?;
+    Ok(n)
+}
+
+// mount(2); no flags are known yet, data is the option string of the fs
+// type and may be null
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fstype: *const u8,
+    flags: usize,
+    data: *const u8,
+) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(EINVAL);
+    }
+    let (uid, gid) = current_ids();
+    let (source, target) = (read_path(source)?, read_path(target)?);
+    let data = match data.is_null() {
+        true => String::new(),
+        false => read_path(data)?,
+    };
+    mount(&source, &target, &read_path(fstype)?, &data, uid, gid)?;
+    Ok(0)
+}
+
+// umount2(2), no flags are known yet
+pub fn sys_umount(target: *const u8, flags: u32) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(EINVAL);
     }
+    let (uid, gid) = current_ids();
+    umount(&read_path(target)?, uid, gid)?;
+    Ok(0)
 }
@@ -1,33 +1,98 @@
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_SYMLINKAT: usize = 36;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_RENAMEAT: usize = 38;
+const SYSCALL_UMOUNT2: usize = 39;
+const SYSCALL_MOUNT: usize = 40;
+const SYSCALL_STATFS: usize = 43;
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE2: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_WRITEV: usize = 66;
+const SYSCALL_READLINKAT: usize = 78;
+const SYSCALL_FSTATAT: usize = 79;
+const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_EXIT: usize = 93;
-const SYSCALL_GET_TASKINFO: usize = 94;
+const SYSCALL_EXIT_GROUP: usize = 94;
+const SYSCALL_SET_TID_ADDRESS: usize = 96;
 const SYSCALL_YIELD: usize = 124;
-const SYSCALL_GET_TIME: usize = 201;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_BRK: usize = 214;
 const SYSCALL_FORK: usize = 220;
-const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_EXEC: usize = 221;
-const SYSCALL_GETPID: usize = 172;
+const SYSCALL_WAITPID: usize = 260;
+// the calls of this kernel's own, well past the numbers linux uses
+const SYSCALL_GET_TASKINFO: usize = 1000;
+const SYSCALL_GET_TIME: usize = 1001;
+const SYSCALL_TRACE: usize = 1002;
+const SYSCALL_CORE_DUMP: usize = 1003;
+
+pub use errno::Errno::{self, *};
 
-const EBADARG: isize = -1;
-const EAGAIN: isize = -2;
-const ENOCHILDREN: isize = -3;
+use log::debug;
 
 mod fs;
 mod process;
+mod trace;
+
+pub use trace::enter as trace_enter;
 
-pub fn syscall(syscall_id: usize, a1: usize, a2: usize, a3: usize) -> Option<isize> {
-    match syscall_id {
-        SYSCALL_WRITE => Some(fs::sys_write(a1, a2 as *const u8, a3)),
-        SYSCALL_READ => Some(fs::sys_read(a1, a2 as *mut u8, a3)),
-        SYSCALL_EXIT => process::sys_exit(a1 as i32),
-        SYSCALL_GET_TASKINFO => Some(process::sys_get_task_info(a1 as *mut u8, a2)),
-        SYSCALL_YIELD => Some(process::sys_yield()),
-        SYSCALL_GET_TIME => Some(process::sys_get_time()),
-        SYSCALL_GETPID => Some(process::sys_get_pid()),
-        SYSCALL_FORK => Some(process::sys_fork()),
-        SYSCALL_EXEC => Some(process::sys_exec(a1 as *mut u8)),
-        SYSCALL_WAITPID => Some(process::sys_waitpid(a1 as isize, a2 as *mut i32)),
-        _ => None,
-    }
+// the value for a0, given a7 as id and a0-a5 as args
+pub fn syscall(id: usize, args: [usize; 6]) -> isize {
+    let [a0, a1, a2, a3, a4, _a5] = args;
+    let rt = match id {
+        SYSCALL_WRITE => fs::sys_write(a0, a1 as *const u8, a2),
+        SYSCALL_WRITEV => fs::sys_writev(a0, a1 as *const u8, a2),
+        SYSCALL_READ => fs::sys_read(a0, a1 as *mut u8, a2),
+        SYSCALL_OPENAT => fs::sys_openat(a0 as isize, a1 as *const u8, a2 as u32, a3 as u32),
+        SYSCALL_CLOSE => fs::sys_close(a0),
+        SYSCALL_PIPE2 => fs::sys_pipe(a0 as *mut i32, a1 as u32),
+        SYSCALL_DUP => fs::sys_dup(a0),
+        SYSCALL_DUP3 => fs::sys_dup3(a0, a1, a2 as u32),
+        SYSCALL_FSTATAT => fs::sys_fstatat(a0 as isize, a1 as *const u8, a2 as *mut u8, a3 as u32),
+        SYSCALL_FSTAT => fs::sys_fstat(a0, a1 as *mut u8),
+        SYSCALL_STATFS => fs::sys_statfs(a0 as *const u8, a1 as *mut u8),
+        SYSCALL_LINKAT => fs::sys_linkat(
+            a0 as isize,
+            a1 as *const u8,
+            a2 as isize,
+            a3 as *const u8,
+            a4 as u32,
+        ),
+        SYSCALL_UNLINKAT => fs::sys_unlinkat(a0 as isize, a1 as *const u8, a2 as u32),
+        SYSCALL_SYMLINKAT => fs::sys_symlinkat(a0 as *const u8, a1 as isize, a2 as *const u8),
+        SYSCALL_READLINKAT => fs::sys_readlinkat(a0 as isize, a1 as *const u8, a2 as *mut u8, a3),
+        SYSCALL_RENAMEAT => {
+            fs::sys_renameat(a0 as isize, a1 as *const u8, a2 as isize, a3 as *const u8)
+        }
+        SYSCALL_MOUNT => fs::sys_mount(
+            a0 as *const u8,
+            a1 as *const u8,
+            a2 as *const u8,
+            a3,
+            a4 as *const u8,
+        ),
+        SYSCALL_UMOUNT2 => fs::sys_umount(a0 as *const u8, a1 as u32),
+        SYSCALL_EXIT | SYSCALL_EXIT_GROUP => process::sys_exit(a0 as i32),
+        SYSCALL_SET_TID_ADDRESS => process::sys_set_tid_address(),
+        SYSCALL_BRK => process::sys_brk(a0),
+        SYSCALL_GET_TASKINFO => process::sys_get_task_info(a0 as *mut u8, a1),
+        SYSCALL_YIELD => process::sys_yield(),
+        SYSCALL_GET_TIME => process::sys_get_time(),
+        SYSCALL_TRACE => process::sys_trace(a0 != 0),
+        SYSCALL_CORE_DUMP => process::sys_core_dump(a0 != 0),
+        SYSCALL_GETPID => process::sys_get_pid(),
+        SYSCALL_FORK => process::sys_fork(),
+        SYSCALL_EXEC => process::sys_exec(a0 as *mut u8),
+        SYSCALL_WAITPID => process::sys_waitpid(a0 as isize, a1 as *mut i32),
+        _ => {
+            debug!("[kernel] unsupported syscall {}", id);
+            Err(ENOSYS)
+        }
+    };
+    errno::encode(rt)
 }
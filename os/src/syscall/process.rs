@@ -3,14 +3,14 @@ use core::str;
 use alloc::sync::Arc;
 
 use crate::loader::get_app_info_by_name;
-use crate::mm::{translate_ptr_mut, Writer};
+use crate::mm::{copy_to_user, strncpy_from_user};
 use crate::task::{
     exec_current, exit_current_task, fork_current, get_current_app, get_current_task,
     get_current_token, suspend_current_task,
 };
-use crate::{mm, println, timer};
+use crate::{println, timer};
 
-use super::{EAGAIN, EBADARG, ENOCHILDREN};
+use super::{Errno, EAGAIN, ECHILD, EINVAL, ENOENT, ERANGE};
 
 #[allow(unreachable_code)]
 pub fn sys_exit(code: i32) -> ! {
@@ -18,84 +18,85 @@ pub fn sys_exit(code: i32) -> ! {
     panic!("should not run here")
 }
 
-pub fn sys_get_task_info(ptr: *mut u8, len: usize) -> isize {
-    let mut buf = mm::UserBufMut::new(get_current_token(), ptr, len);
+// the app name, ERANGE when it does not fit in len bytes
+pub fn sys_get_task_info(ptr: *mut u8, len: usize) -> Result<usize, Errno> {
     let name = get_current_app().name;
-    let result = buf.write(name.as_bytes());
-    match result {
-        Err(e) => {
-            println!("[kernel] write task info error: {}", e.msg);
-            return -1;
-        }
-        Ok(writen) => {
-            if writen != name.len() {
-                return -2;
-            } else {
-                return writen as isize;
-            }
-        }
+    if name.len() > len {
+        return Err(ERANGE);
     }
+    copy_to_user(get_current_token(), ptr, name.as_bytes())?;
+    Ok(name.len())
 }
 
-pub fn sys_yield() -> isize {
+pub fn sys_yield() -> Result<usize, Errno> {
     suspend_current_task();
-    0
+    Ok(0)
+}
+
+pub fn sys_get_time() -> Result<usize, Errno> {
+    Ok(timer::get_time_ms())
 }
 
-pub fn sys_get_time() -> isize {
-    timer::get_time_ms() as isize
+// log the syscalls of this task and the children it forks from now on,
+// or stop; returns whether they were logged before
+pub fn sys_trace(on: bool) -> Result<usize, Errno> {
+    let current = get_current_task().unwrap();
+    let mut cur = current.exclusive_access();
+    Ok(core::mem::replace(&mut cur.trace, on) as usize)
+}
+
+// write a core file when this task or a child it forks from now on is
+// killed by an exception, or stop; returns whether it did before
+pub fn sys_core_dump(on: bool) -> Result<usize, Errno> {
+    let current = get_current_task().unwrap();
+    let mut cur = current.exclusive_access();
+    Ok(core::mem::replace(&mut cur.core_dump, on) as usize)
 }
 
-pub fn sys_get_pid() -> isize {
+pub fn sys_get_pid() -> Result<usize, Errno> {
     let current = get_current_task().unwrap();
     let cur = current.exclusive_access();
-    let pid = cur.get_pid() as isize;
-    pid
+    Ok(cur.get_pid())
 }
 
-pub fn sys_fork() -> isize {
-    fork_current() as isize
+// set_tid_address(2); there are no threads to clear a tid for on exit, so
+// only the tid, which is the pid, comes back
+pub fn sys_set_tid_address() -> Result<usize, Errno> {
+    sys_get_pid()
 }
 
-const PATH_LENGTH_LIMIT: usize = 128;
+// brk(2), the end of the heap after moving it to new
+pub fn sys_brk(new: usize) -> Result<usize, Errno> {
+    let current = get_current_task().unwrap();
+    let mut cur = current.exclusive_access();
+    Ok(cur.get_mem_mut().unwrap().set_brk(new))
+}
 
-pub fn sys_exec(ptr: *mut u8) -> isize {
-    let mut path_buf = [0u8; PATH_LENGTH_LIMIT];
-    let mut wptr = 0;
-    for c in mm::iter_from_user_ptr(ptr, get_current_token()) {
-        if c != 0 && wptr < PATH_LENGTH_LIMIT {
-            path_buf[wptr] = c;
-            wptr += 1;
-        } else {
-            break;
-        }
-    }
-    if wptr >= PATH_LENGTH_LIMIT {
-        return -2;
-    }
+pub fn sys_fork() -> Result<usize, Errno> {
+    Ok(fork_current())
+}
+
+const PATH_LENGTH_LIMIT: usize = 128;
 
-    let name = match str::from_utf8(&path_buf[..wptr]) {
+pub fn sys_exec(ptr: *mut u8) -> Result<usize, Errno> {
+    let path = strncpy_from_user(get_current_token(), ptr, PATH_LENGTH_LIMIT)?;
+    let name = match str::from_utf8(&path) {
         Ok(s) => s,
         Err(e) => {
             println!(
                 "[kernel] read path while exec error: bad utf8 at {}",
                 e.valid_up_to()
             );
-            return -2;
+            return Err(EINVAL);
         }
     };
 
-    let app = get_app_info_by_name(name);
-    match app {
-        Some(app) => {
-            exec_current(app);
-            0
-        }
-        None => EBADARG,
-    }
+    let app = get_app_info_by_name(name).ok_or(ENOENT)?;
+    exec_current(app);
+    Ok(0)
 }
 
-pub fn sys_waitpid(pid: isize, code_ptr: *mut i32) -> isize {
+pub fn sys_waitpid(pid: isize, code_ptr: *mut i32) -> Result<usize, Errno> {
     let current = get_current_task().unwrap();
     let mut cur = current.exclusive_access();
     if pid != -1
@@ -104,7 +105,7 @@ pub fn sys_waitpid(pid: isize, code_ptr: *mut i32) -> isize {
             .iter()
             .all(|k| k.exclusive_access().get_pid() as isize != pid)
     {
-        return EBADARG;
+        return Err(ECHILD);
     }
     let found = cur.children.iter().enumerate().find(|(_idx, kid)| {
         let k = kid.exclusive_access();
@@ -112,10 +113,10 @@ pub fn sys_waitpid(pid: isize, code_ptr: *mut i32) -> isize {
     });
     match found {
         None => {
-            if cur.children.len() == 0 {
-                ENOCHILDREN
+            if cur.children.is_empty() {
+                Err(ECHILD)
             } else {
-                EAGAIN
+                Err(EAGAIN)
             }
         }
         Some((idx, _)) => {
@@ -125,15 +126,12 @@ pub fn sys_waitpid(pid: isize, code_ptr: *mut i32) -> isize {
             let found = k.exclusive_access().get_pid();
             // get current toke will lock current process, so drop cur
             drop(cur);
-            match translate_ptr_mut(code_ptr, get_current_token()) {
-                None => {
-                    return -1;
-                }
-                Some(code_ref) => {
-                    *code_ref = code;
-                }
-            }
-            found as isize
+            copy_to_user(
+                get_current_token(),
+                code_ptr as *mut u8,
+                &code.to_ne_bytes(),
+            )?;
+            Ok(found)
         }
     }
 }
@@ -0,0 +1,163 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    mm::{copy_from_user, strncpy_from_user},
+    println,
+    task::{get_current_task, get_current_token},
+    timer,
+};
+
+use super::*;
+
+// how an argument is shown
+#[derive(Clone, Copy)]
+enum Arg {
+    Int,
+    Hex,
+    Path,
+    // the bytes at the pointer, as many as the argument at this index says
+    Bytes(usize),
+}
+
+use Arg::*;
+
+// the bytes of a write shown before the rest is cut
+const BYTES_SHOWN: usize = 32;
+const PATH_SHOWN: usize = 128;
+
+fn signature(id: usize) -> Option<(&'static str, &'static [Arg])> {
+    let sig: (&'static str, &'static [Arg]) = match id {
+        SYSCALL_DUP => ("dup", &[Int]),
+        SYSCALL_DUP3 => ("dup3", &[Int, Int, Hex]),
+        SYSCALL_UNLINKAT => ("unlinkat", &[Int, Path, Hex]),
+        SYSCALL_SYMLINKAT => ("symlinkat", &[Path, Int, Path]),
+        SYSCALL_LINKAT => ("linkat", &[Int, Path, Int, Path, Hex]),
+        SYSCALL_RENAMEAT => ("renameat", &[Int, Path, Int, Path]),
+        SYSCALL_UMOUNT2 => ("umount2", &[Path, Hex]),
+        SYSCALL_MOUNT => ("mount", &[Path, Path, Path, Hex, Hex]),
+        SYSCALL_STATFS => ("statfs", &[Path, Hex]),
+        SYSCALL_OPENAT => ("openat", &[Int, Path, Hex, Hex]),
+        SYSCALL_CLOSE => ("close", &[Int]),
+        SYSCALL_PIPE2 => ("pipe2", &[Hex, Hex]),
+        SYSCALL_READ => ("read", &[Int, Hex, Int]),
+        SYSCALL_WRITE => ("write", &[Int, Bytes(2), Int]),
+        SYSCALL_WRITEV => ("writev", &[Int, Hex, Int]),
+        SYSCALL_READLINKAT => ("readlinkat", &[Int, Path, Hex, Int]),
+        SYSCALL_FSTATAT => ("fstatat", &[Int, Path, Hex, Hex]),
+        SYSCALL_FSTAT => ("fstat", &[Int, Hex]),
+        SYSCALL_EXIT => ("exit", &[Int]),
+        SYSCALL_EXIT_GROUP => ("exit_group", &[Int]),
+        SYSCALL_SET_TID_ADDRESS => ("set_tid_address", &[Hex]),
+        SYSCALL_YIELD => ("sched_yield", &[]),
+        SYSCALL_GETPID => ("getpid", &[]),
+        SYSCALL_BRK => ("brk", &[Hex]),
+        SYSCALL_FORK => ("clone", &[]),
+        SYSCALL_EXEC => ("execve", &[Path]),
+        SYSCALL_WAITPID => ("wait4", &[Int, Hex]),
+        SYSCALL_GET_TASKINFO => ("get_task_info", &[Hex, Int]),
+        SYSCALL_GET_TIME => ("get_time", &[]),
+        SYSCALL_TRACE => ("trace", &[Int]),
+        SYSCALL_CORE_DUMP => ("core_dump", &[Int]),
+        _ => return None,
+    };
+    Some(sig)
+}
+
+// printable ascii as it is, the rest escaped like a c string
+fn escape(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in bytes {
+        match b {
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            b'"' | b'\\' => {
+                s.push('\\');
+                s.push(b as char);
+            }
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    s
+}
+
+fn show(arg: Arg, args: &[usize; 6], v: usize) -> String {
+    let token = get_current_token();
+    match arg {
+        Int => format!("{}", v as isize),
+        Hex => format!("{:#x}", v),
+        Path => match strncpy_from_user(token, v as *const u8, PATH_SHOWN) {
+            Ok(path) => format!("\"{}\"", escape(&path)),
+            Err(_) => format!("{:#x}", v),
+        },
+        Bytes(len_at) => {
+            let len = args[len_at];
+            let mut buf: Vec<u8> = alloc::vec![0; len.min(BYTES_SHOWN)];
+            match copy_from_user(token, v as *const u8, &mut buf) {
+                Ok(()) if len > buf.len() => format!("\"{}\"...", escape(&buf)),
+                Ok(()) => format!("\"{}\"", escape(&buf)),
+                Err(_) => format!("{:#x}", v),
+            }
+        }
+    }
+}
+
+// a syscall of a traced task on its way in
+pub struct Trace {
+    pid: usize,
+    call: String,
+    start: usize,
+}
+
+fn traced_pid() -> Option<usize> {
+    let task = get_current_task()?;
+    let t = task.exclusive_access();
+    t.trace.then(|| t.get_pid())
+}
+
+// the call as strace shows it, None unless the current task is traced;
+// the arguments are read now, before the call may change them
+pub fn enter(id: usize, args: &[usize; 6]) -> Option<Trace> {
+    let pid = traced_pid()?;
+    let call = match signature(id) {
+        Some((name, kinds)) => {
+            let shown: Vec<String> = kinds
+                .iter()
+                .zip(args)
+                .map(|(&k, &v)| show(k, args, v))
+                .collect();
+            format!("{}({})", name, shown.join(", "))
+        }
+        None => format!(
+            "syscall_{}({:#x}, {:#x}, {:#x})",
+            id, args[0], args[1], args[2]
+        ),
+    };
+    // exit does not come back to be logged
+    if id == SYSCALL_EXIT || id == SYSCALL_EXIT_GROUP {
+        println!("[trace {}] {} = ?", pid, call);
+        return None;
+    }
+    Some(Trace {
+        pid,
+        call,
+        start: timer::get_time_us(),
+    })
+}
+
+impl Trace {
+    pub fn exit(self, rt: isize) {
+        let took = timer::get_time_us() - self.start;
+        match errno::decode(rt) {
+            Ok(v) => println!("[trace {}] {} = {} <{}us>", self.pid, self.call, v, took),
+            Err(e) => println!(
+                "[trace {}] {} = -1 {:?} ({}) <{}us>",
+                self.pid,
+                self.call,
+                e,
+                e.description(),
+                took
+            ),
+        }
+    }
+}
@@ -3,9 +3,12 @@ mod pid;
 mod processor;
 mod switch;
 mod task;
+mod wait_queue;
 
 pub use processor::{
-    exec_current, exit_current_task, fork_current, get_current_app, get_current_task,
-    get_current_token, get_current_trap_cx, run_tasks, suspend_current_task,
+    block_current_task, exec_current, exit_current_task, fork_current, get_current_app,
+    get_current_task, get_current_token, get_current_trap_cx, run_tasks, suspend_current_task,
+    wake_task,
 };
 pub use task::add_init_proc;
+pub use wait_queue::WaitQueue;
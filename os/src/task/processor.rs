@@ -1,10 +1,14 @@
-use core::cell::RefMut;
+use core::{
+    cell::RefMut,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use lazy_static::lazy_static;
 
 use alloc::sync::Arc;
 use log::debug;
 
 use crate::{
+    drivers, fs,
     loader::AppInfo,
     println,
     sbi::shut_down,
@@ -64,6 +68,10 @@ impl Processor {
         let mut t = self.current_mut().unwrap();
         t.status = TaskStatus::READY;
     }
+    fn mark_current_task_blocked(&mut self) {
+        let mut t = self.current_mut().unwrap();
+        t.status = TaskStatus::BLOCKED;
+    }
 
     pub fn get_current_token(&self) -> usize {
         self.current()
@@ -75,6 +83,9 @@ impl Processor {
     }
 }
 
+// tasks waiting for a device, the idle loop waits for them
+static BLOCKED_TASKS: AtomicUsize = AtomicUsize::new(0);
+
 lazy_static! {
     static ref PROCESSOR: UCell<Processor> = unsafe { UCell::new(Processor::new()) };
 }
@@ -109,8 +120,13 @@ pub fn run_tasks() {
             unsafe {
                 __switch(cur, nxt);
             }
+        } else if BLOCKED_TASKS.load(Ordering::Relaxed) > 0 {
+            drop(tm);
+            drop(processor);
+            drivers::wait_for_irq();
         } else {
             println!("[kernel] all apps exited, will shutdown");
+            fs::sync_all();
             shut_down(false)
         }
     }
@@ -140,6 +156,28 @@ pub fn suspend_current_task() {
     schedule(cur);
 }
 
+// switch away and stay off the ready queue until wake_task
+pub fn block_current_task() {
+    let mut p = PROCESSOR.exclusive_access();
+    p.mark_current_task_blocked();
+    let cur = p.current().unwrap().get_task_ctx_ptr();
+    drop(p);
+    BLOCKED_TASKS.fetch_add(1, Ordering::Relaxed);
+    schedule(cur);
+}
+
+// put a task block_current_task took off back on the ready queue
+pub fn wake_task(task: Arc<UCell<TaskControlBlock>>) {
+    let mut t = task.exclusive_access();
+    if t.status != TaskStatus::BLOCKED {
+        return;
+    }
+    t.status = TaskStatus::READY;
+    drop(t);
+    BLOCKED_TASKS.fetch_sub(1, Ordering::Relaxed);
+    TASK_MANAGER.exclusive_access().add(task);
+}
+
 fn mark_current_task_exited(code: i32) {
     PROCESSOR.exclusive_access().mark_current_task_exited(code)
 }
@@ -1,8 +1,10 @@
 use alloc::collections::vec_deque::VecDeque;
 use alloc::sync::{Arc, Weak};
+use alloc::vec;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 
+use crate::fs::{open_console, File, OpenFlags};
 use crate::loader::{get_app_info_by_name, AppInfo};
 use crate::mm::{MemorySet, PhysPageNum, VirtAddress, KERNEL_SPACE, TRAP_CONTEXT};
 use crate::sync::UCell;
@@ -12,12 +14,17 @@ use crate::trap::trap_handler;
 use super::context::TaskContext;
 use super::pid::{KernelStack, PIDHandle};
 
+// fds dup2 may pick from
+const FD_LIMIT: usize = 128;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TaskStatus {
     #[allow(unused)]
     UnInit,
     READY,
     RUNNING,
+    // off the ready queue until a wait queue wakes it
+    BLOCKED,
     EXITED(i32),
 }
 
@@ -26,6 +33,13 @@ pub struct TaskControlBlock {
     pub status: TaskStatus,
     cx: TaskContext,
     app_info: AppInfo,
+    // the user and group the task runs as, inherited on fork
+    pub uid: u32,
+    pub gid: u32,
+    // log every syscall, inherited on fork and kept over exec
+    pub trace: bool,
+    // write a core file when killed by an exception, inherited like trace
+    pub core_dump: bool,
     pub parent: Option<Weak<UCell<TaskControlBlock>>>,
     pub children: Vec<Arc<UCell<TaskControlBlock>>>,
     pub inner: Option<TaskControlBlockInner>,
@@ -40,11 +54,12 @@ pub struct TaskControlBlockInner {
     // base_size to allow brk
     #[allow(unused)]
     base_size: usize,
+    pub fd_table: Vec<Option<Arc<dyn File>>>,
 }
 
 impl TaskControlBlock {
     pub fn exec(&mut self, app: AppInfo) {
-        let (mem_set, usp, entry) = MemorySet::new_app_from_elf(&app.mem);
+        let (mem_set, usp, entry) = MemorySet::new_app_from_elf(&app.mem, app.name);
         self.app_info = app;
         let trap_ctx_ppn = mem_set
             .page_table
@@ -78,6 +93,9 @@ impl TaskControlBlock {
     pub fn get_mem(&self) -> Option<&MemorySet> {
         self.inner.as_ref().map(|b| &b.mem_set)
     }
+    pub fn get_mem_mut(&mut self) -> Option<&mut MemorySet> {
+        self.inner.as_mut().map(|b| &mut b.mem_set)
+    }
 
     pub fn exit_code(&self) -> Option<i32> {
         match self.status {
@@ -89,6 +107,44 @@ impl TaskControlBlock {
     pub fn get_app_info(&self) -> &AppInfo {
         &self.app_info
     }
+
+    pub fn get_fd_table(&mut self) -> Option<&mut Vec<Option<Arc<dyn File>>>> {
+        self.inner.as_mut().map(|b| &mut b.fd_table)
+    }
+
+    pub fn get_file(&self, fd: usize) -> Option<Arc<dyn File>> {
+        self.inner
+            .as_ref()
+            .and_then(|b| b.fd_table.get(fd).cloned().flatten())
+    }
+
+    // the lowest free fd, none once FD_LIMIT are open
+    pub fn alloc_fd(&mut self, file: Arc<dyn File>) -> Option<usize> {
+        let table = self.get_fd_table()?;
+        let fd = match table.iter().position(|f| f.is_none()) {
+            Some(fd) => fd,
+            None if table.len() >= FD_LIMIT => return None,
+            None => {
+                table.push(None);
+                table.len() - 1
+            }
+        };
+        table[fd] = Some(file);
+        Some(fd)
+    }
+
+    // put file at fd, closing what was there, fails past FD_LIMIT
+    pub fn set_fd(&mut self, fd: usize, file: Arc<dyn File>) -> Option<()> {
+        if fd >= FD_LIMIT {
+            return None;
+        }
+        let table = self.get_fd_table()?;
+        if table.len() <= fd {
+            table.resize(fd + 1, None);
+        }
+        table[fd] = Some(file);
+        Some(())
+    }
 }
 
 fn new_task(app: AppInfo) -> Arc<UCell<TaskControlBlock>> {
@@ -100,11 +156,21 @@ fn new_task(app: AppInfo) -> Arc<UCell<TaskControlBlock>> {
         mem_set: MemorySet::bare_new(),
         trap_ctx_ppn: PhysPageNum(0),
         base_size: 0,
+        fd_table: vec![
+            // 0 stdin, 1 stdout, 2 stderr
+            Some(open_console(OpenFlags::RDONLY)),
+            Some(open_console(OpenFlags::WRONLY)),
+            Some(open_console(OpenFlags::WRONLY)),
+        ],
     };
     let mut block = TaskControlBlock {
         pid,
         status,
         app_info: app.clone(),
+        uid: 0,
+        gid: 0,
+        trace: false,
+        core_dump: false,
         cx: TaskContext::zero_init(),
         children: Vec::new(),
         parent: None,
@@ -131,11 +197,16 @@ pub fn fork(parent: Arc<UCell<TaskControlBlock>>) -> Arc<UCell<TaskControlBlock>
         mem_set,
         trap_ctx_ppn,
         base_size: src.inner.as_ref().unwrap().base_size,
+        fd_table: src.inner.as_ref().unwrap().fd_table.clone(),
     };
     let block = TaskControlBlock {
         pid,
         status,
         app_info: src.app_info.clone(),
+        uid: src.uid,
+        gid: src.gid,
+        trace: src.trace,
+        core_dump: src.core_dump,
         cx: TaskContext::goto_trap_return(ksp),
         children: Vec::new(),
         parent: Some(Arc::downgrade(&parent)),
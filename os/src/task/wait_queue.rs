@@ -0,0 +1,37 @@
+use alloc::{collections::VecDeque, sync::Arc};
+
+use crate::sync::UCell;
+
+use super::{
+    processor::{block_current_task, get_current_task, wake_task},
+    task::TaskControlBlock,
+};
+
+// tasks blocked until some event, a driver wakes them all when it happens
+// and each one checks again whether what it waits for is there
+pub struct WaitQueue {
+    tasks: UCell<VecDeque<Arc<UCell<TaskControlBlock>>>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            tasks: unsafe { UCell::new(VecDeque::new()) },
+        }
+    }
+
+    // block the current task until wake_all, interrupts are off in the
+    // kernel so no wakeup gets lost in between
+    pub fn wait(&self) {
+        let task = get_current_task().unwrap();
+        self.tasks.exclusive_access().push_back(task);
+        block_current_task();
+    }
+
+    pub fn wake_all(&self) {
+        let tasks: VecDeque<_> = self.tasks.exclusive_access().drain(..).collect();
+        for task in tasks {
+            wake_task(task);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use riscv::register::time;
 
+use crate::config::RTC_BASE;
+
 fn get_time() -> usize {
     time::read()
 }
@@ -14,3 +16,19 @@ pub fn set_next_trigger() {
 pub fn get_time_ms() -> usize {
     get_time() / (CLOCK_FREQ / MILLI_PER_SEC)
 }
+
+const MICRO_PER_SEC: usize = 1_000_000;
+
+pub fn get_time_us() -> usize {
+    get_time() / (CLOCK_FREQ / MICRO_PER_SEC)
+}
+
+const NANO_PER_SEC: u64 = 1_000_000_000;
+
+// seconds since the unix epoch from the goldfish rtc,
+// reading the low word latches the high one
+pub fn wall_time_secs() -> u64 {
+    let lo = unsafe { (RTC_BASE as *const u32).read_volatile() } as u64;
+    let hi = unsafe { ((RTC_BASE + 4) as *const u32).read_volatile() } as u64;
+    (hi << 32 | lo) / NANO_PER_SEC
+}
@@ -37,3 +37,11 @@ impl TrapContext {
         self.registers[2] = sp;
     }
 }
+
+// what __kerneltrap saves below the interrupted kernel sp
+#[repr(C)]
+pub struct KernelTrapContext {
+    pub registers: [usize; 32],
+    pub sstatus: Sstatus,
+    pub sepc: usize,
+}
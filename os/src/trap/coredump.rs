@@ -0,0 +1,216 @@
+use alloc::{format, sync::Arc, vec, vec::Vec};
+
+use crate::{
+    fs::{create_file, Inode},
+    mm::{copy_from_user, MapPermission, PAGE_SIZE},
+    println,
+    syscall::{Errno, ENOSPC},
+    task::{get_current_app, get_current_task, get_current_token},
+};
+
+use super::context::TrapContext;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const ET_CORE: u16 = 4;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+// struct elf_prstatus and elf_prpsinfo of riscv64 linux
+const PRSTATUS_SIZE: usize = 376;
+const PRPSINFO_SIZE: usize = 136;
+
+// what is kept of the task before its pages are written out
+struct Task {
+    pid: u32,
+    ppid: u32,
+    uid: u32,
+    gid: u32,
+    areas: Vec<(usize, usize, MapPermission)>,
+}
+
+fn put16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn pad(buf: &mut Vec<u8>, align: usize) {
+    buf.resize(buf.len().next_multiple_of(align), 0);
+}
+
+// a note named CORE; the desc starts and ends 4 aligned, as gdb expects
+fn note(buf: &mut Vec<u8>, kind: u32, desc: &[u8]) {
+    put32(buf, 5);
+    put32(buf, desc.len() as u32);
+    put32(buf, kind);
+    buf.extend_from_slice(b"CORE\0");
+    pad(buf, 4);
+    buf.extend_from_slice(desc);
+    pad(buf, 4);
+}
+
+// the registers as gdb reads them, the pc in place of x0
+fn prstatus(cx: &TrapContext, signal: i32, task: &Task) -> Vec<u8> {
+    let mut desc = vec![0u8; PRSTATUS_SIZE];
+    desc[0..4].copy_from_slice(&signal.to_le_bytes());
+    desc[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+    desc[32..36].copy_from_slice(&task.pid.to_le_bytes());
+    desc[36..40].copy_from_slice(&task.ppid.to_le_bytes());
+    let regs = &mut desc[112..112 + 32 * 8];
+    regs[..8].copy_from_slice(&(cx.sepc as u64).to_le_bytes());
+    for (i, &r) in cx.registers.iter().enumerate().skip(1) {
+        regs[i * 8..i * 8 + 8].copy_from_slice(&(r as u64).to_le_bytes());
+    }
+    desc
+}
+
+fn prpsinfo(name: &str, task: &Task) -> Vec<u8> {
+    let mut desc = vec![0u8; PRPSINFO_SIZE];
+    desc[1] = b'R';
+    desc[16..20].copy_from_slice(&task.uid.to_le_bytes());
+    desc[20..24].copy_from_slice(&task.gid.to_le_bytes());
+    desc[24..28].copy_from_slice(&task.pid.to_le_bytes());
+    desc[28..32].copy_from_slice(&task.ppid.to_le_bytes());
+    let fname = &name.as_bytes()[..name.len().min(15)];
+    desc[40..40 + fname.len()].copy_from_slice(fname);
+    let psargs = &name.as_bytes()[..name.len().min(79)];
+    desc[56..56 + psargs.len()].copy_from_slice(psargs);
+    desc
+}
+
+fn flags_of(perm: &MapPermission) -> u32 {
+    let mut flags = 0;
+    if perm.contains(MapPermission::R) {
+        flags |= PF_R;
+    }
+    if perm.contains(MapPermission::W) {
+        flags |= PF_W;
+    }
+    if perm.contains(MapPermission::X) {
+        flags |= PF_X;
+    }
+    flags
+}
+
+// the elf header, the program headers and the notes, everything but the
+// pages which follow from the first page boundary on
+fn headers(cx: &TrapContext, signal: i32, name: &str, task: &Task) -> Vec<u8> {
+    let mut notes = Vec::new();
+    note(&mut notes, NT_PRSTATUS, &prstatus(cx, signal, task));
+    note(&mut notes, NT_PRPSINFO, &prpsinfo(name, task));
+    let phnum = 1 + task.areas.len();
+    let notes_at = EHDR_SIZE + phnum * PHDR_SIZE;
+    let mut data_at = (notes_at + notes.len()).next_multiple_of(PAGE_SIZE);
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    buf.resize(16, 0);
+    put16(&mut buf, ET_CORE);
+    put16(&mut buf, EM_RISCV);
+    put32(&mut buf, 1);
+    put64(&mut buf, 0);
+    put64(&mut buf, EHDR_SIZE as u64);
+    put64(&mut buf, 0);
+    put32(&mut buf, 0);
+    put16(&mut buf, EHDR_SIZE as u16);
+    put16(&mut buf, PHDR_SIZE as u16);
+    put16(&mut buf, phnum as u16);
+    put16(&mut buf, 0);
+    put16(&mut buf, 0);
+    put16(&mut buf, 0);
+
+    put32(&mut buf, PT_NOTE);
+    put32(&mut buf, 0);
+    put64(&mut buf, notes_at as u64);
+    put64(&mut buf, 0);
+    put64(&mut buf, 0);
+    put64(&mut buf, notes.len() as u64);
+    put64(&mut buf, 0);
+    put64(&mut buf, 4);
+    for (start, end, perm) in task.areas.iter() {
+        let size = (end - start) as u64;
+        put32(&mut buf, PT_LOAD);
+        put32(&mut buf, flags_of(perm));
+        put64(&mut buf, data_at as u64);
+        put64(&mut buf, *start as u64);
+        put64(&mut buf, 0);
+        put64(&mut buf, size);
+        put64(&mut buf, size);
+        put64(&mut buf, PAGE_SIZE as u64);
+        data_at += end - start;
+    }
+    buf.extend_from_slice(&notes);
+    buf
+}
+
+fn write_all(inode: &Arc<dyn Inode>, offset: usize, buf: &[u8]) -> Result<(), Errno> {
+    let mut done = 0;
+    while done < buf.len() {
+        match inode.write_at(offset + done, &buf[done..])? {
+            0 => return Err(ENOSPC),
+            n => done += n,
+        }
+    }
+    Ok(())
+}
+
+fn write_core(path: &str, cx: &TrapContext, signal: i32, task: &Task) -> Result<(), Errno> {
+    let token = get_current_token();
+    let inode = create_file(path, task.uid, task.gid)?;
+    let head = headers(cx, signal, get_current_app().name, task);
+    write_all(&inode, 0, &head)?;
+    let mut at = head.len().next_multiple_of(PAGE_SIZE);
+    let mut page = vec![0u8; PAGE_SIZE];
+    for &(start, end, _) in task.areas.iter() {
+        for va in (start..end).step_by(PAGE_SIZE) {
+            // a page that cannot be read is left as zeros
+            if copy_from_user(token, va as *const u8, &mut page).is_err() {
+                page.fill(0);
+            }
+            write_all(&inode, at, &page)?;
+            at += PAGE_SIZE;
+        }
+    }
+    Ok(())
+}
+
+// write the current task to core.<pid> at the root of the file system,
+// if it asked for core files, so gdb can open it next to its binary
+pub fn dump_core(cx: &TrapContext, signal: i32) {
+    let current = get_current_task().unwrap();
+    let cur = current.exclusive_access();
+    if !cur.core_dump {
+        return;
+    }
+    let ppid = cur
+        .parent
+        .as_ref()
+        .and_then(|p| p.upgrade())
+        .map_or(0, |p| p.exclusive_access().get_pid());
+    let task = Task {
+        pid: cur.get_pid() as u32,
+        ppid: ppid as u32,
+        uid: cur.uid,
+        gid: cur.gid,
+        areas: cur.get_mem().unwrap().user_areas(),
+    };
+    // writing may sleep on the disk, so the task is let go first
+    drop(cur);
+    let path = format!("/core.{}", task.pid);
+    match write_core(&path, cx, signal, &task) {
+        Ok(()) => println!("[kernel] core dumped to {}", path),
+        Err(e) => println!("[kernel] core dump to {} failed: {:?}", path, e),
+    }
+}
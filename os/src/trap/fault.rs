@@ -0,0 +1,92 @@
+use riscv::register::scause::Scause;
+
+use crate::{
+    mm::copy_from_user,
+    println,
+    task::{get_current_app, get_current_task, get_current_token},
+};
+
+use super::context::TrapContext;
+
+// a killed process exits with 128 + the signal, as a shell reports it
+pub const SIGNAL_EXIT_BASE: i32 = 128;
+const SIGILL: i32 = 4;
+const SIGTRAP: i32 = 5;
+const SIGBUS: i32 = 7;
+const SIGKILL: i32 = 9;
+const SIGSEGV: i32 = 11;
+
+// user frames printed before giving up
+const MAX_DEPTH: usize = 16;
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+// the signal linux sends for an exception, by its scause code
+fn signal_of(code: usize) -> i32 {
+    match code {
+        // instruction, load and store misaligned
+        0 | 4 | 6 => SIGBUS,
+        // access and page faults
+        1 | 5 | 7 | 12 | 13 | 15 => SIGSEGV,
+        2 => SIGILL,
+        3 => SIGTRAP,
+        _ => SIGKILL,
+    }
+}
+
+// the user frames from fp up, read through the page table of token; like
+// the kernel, user programs keep ra at fp - 8 and the caller's fp at fp - 16
+fn user_backtrace(token: usize, pc: usize, mut fp: usize) {
+    println!("[kernel] user backtrace:");
+    println!("  {:2}: {:#x}", 0, pc);
+    for depth in 1..MAX_DEPTH {
+        let mut frame = [0u8; 16];
+        if fp % 8 != 0 || fp < 16 {
+            break;
+        }
+        if copy_from_user(token, (fp - 16) as *const u8, &mut frame).is_err() {
+            break;
+        }
+        let prev = usize::from_ne_bytes(frame[..8].try_into().unwrap());
+        let ra = usize::from_ne_bytes(frame[8..].try_into().unwrap());
+        if ra == 0 {
+            break;
+        }
+        println!("  {:2}: {:#x}", depth, ra);
+        if prev <= fp {
+            break;
+        }
+        fp = prev;
+    }
+}
+
+// tell what the current process did wrong before it is killed, returns the
+// signal it is killed by
+pub fn report_user_fault(cx: &TrapContext, scause: Scause, stval: usize) -> i32 {
+    let token = get_current_token();
+    let pid = get_current_task().unwrap().exclusive_access().get_pid();
+    let app = get_current_app();
+    let signal = signal_of(scause.code());
+    println!(
+        "[kernel] {} (pid {}) killed by {:?} at {:#x}, stval {:#x}, signal {}",
+        app.name,
+        pid,
+        scause.cause(),
+        cx.sepc,
+        stval,
+        signal
+    );
+    for row in cx.registers.chunks(4).zip(REG_NAMES.chunks(4)) {
+        let (values, names) = row;
+        println!(
+            "  {:>4}: {:#018x} {:>4}: {:#018x} {:>4}: {:#018x} {:>4}: {:#018x}",
+            names[0], values[0], names[1], values[1], names[2], values[2], names[3], values[3]
+        );
+    }
+    user_backtrace(token, cx.sepc, cx.registers[8]);
+    signal
+}
@@ -0,0 +1,43 @@
+.altmacro
+
+.macro SAVE_GP n
+    sd x\n, \n*8(sp)
+.endm
+
+.macro LOAD_GP n
+    ld x\n, \n*8(sp)
+.endm
+
+    .section .text
+    .global __kerneltrap
+    .align 2
+# a trap taken in the kernel, the interrupted code keeps running on its
+# own stack afterwards, so a KernelTrapContext goes right below its sp
+__kerneltrap:
+    addi sp, sp, -34*8
+    sd x1, 1*8(sp)
+    .set n, 3
+    .rept 29
+        SAVE_GP %n
+        .set n, n+1
+    .endr
+    addi t0, sp, 34*8
+    sd t0, 2*8(sp)
+    csrr t0, sstatus
+    csrr t1, sepc
+    sd t0, 32*8(sp)
+    sd t1, 33*8(sp)
+    mv a0, sp
+    call kernel_trap_handler
+    ld t0, 32*8(sp)
+    ld t1, 33*8(sp)
+    csrw sstatus, t0
+    csrw sepc, t1
+    ld x1, 1*8(sp)
+    .set n, 3
+    .rept 29
+        LOAD_GP %n
+        .set n, n+1
+    .endr
+    addi sp, sp, 34*8
+    sret
@@ -1,9 +1,13 @@
 pub mod context;
+mod coredump;
+mod fault;
+
+use context::KernelTrapContext;
 
 use crate::{
+    drivers, fs,
     mm::{TRAMPOLINE, TRAP_CONTEXT},
-    println,
-    syscall::syscall,
+    syscall::{syscall, trace_enter},
     task::{exit_current_task, get_current_token, get_current_trap_cx, suspend_current_task},
     timer,
 };
@@ -15,28 +19,36 @@ use riscv::register::{
 };
 
 global_asm!(include_str!("trap.asm"));
+global_asm!(include_str!("kernel.asm"));
 
+// trap_return points stvec at the trampoline on the way to user mode
 pub fn init() {
-    extern "C" {
-        fn __alltraps();
-    }
-    unsafe {
-        stvec::write(__alltraps as usize, stvec::TrapMode::Direct);
-    }
+    set_trap_from_kernel();
 }
 
 fn set_trap_from_kernel() {
+    extern "C" {
+        fn __kerneltrap();
+    }
     unsafe {
-        stvec::write(trap_from_kernel as usize, stvec::TrapMode::Direct);
+        stvec::write(__kerneltrap as usize, stvec::TrapMode::Direct);
     }
 }
 
+// the kernel is never preempted, a timer tick only rearms the timer
 #[no_mangle]
-fn trap_from_kernel() -> ! {
-    panic!("no trap from kernel")
+fn kernel_trap_handler(cx: &KernelTrapContext) {
+    let scause = scause::read();
+    let stval = stval::read();
+    match scause.cause() {
+        Trap::Interrupt(Interrupt::SupervisorTimer) => timer::set_next_trigger(),
+        Trap::Interrupt(Interrupt::SupervisorExternal) => drivers::handle_external(),
+        cause => panic!(
+            "trap from kernel: {:?} at {:#x}, stval {:#x}",
+            cause, cx.sepc, stval
+        ),
+    }
 }
-const ECODE_BAD_PROCESS_HEHAVIOR: i32 = 137;
-
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_trap_from_kernel();
@@ -46,33 +58,32 @@ pub fn trap_handler() -> ! {
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
             cx.sepc += 4;
-            let rt = syscall(
-                cx.registers[17],
-                cx.registers[10],
-                cx.registers[11],
-                cx.registers[12],
-            );
-            match rt {
-                Some(rt) => {
-                    // exec may change trap context
-                    let cx = get_current_trap_cx();
-                    cx.registers[10] = rt as usize;
-                }
-                None => {
-                    println!("[kernel] bad syscall {}, killing process", cx.registers[17]);
-                    exit_current_task(ECODE_BAD_PROCESS_HEHAVIOR);
-                }
+            let mut args = [0; 6];
+            args.copy_from_slice(&cx.registers[10..16]);
+            let id = cx.registers[17];
+            let trace = trace_enter(id, &args);
+            let rt = syscall(id, args);
+            if let Some(trace) = trace {
+                trace.exit(rt);
             }
+            // exec may change trap context
+            let cx = get_current_trap_cx();
+            cx.registers[10] = rt as usize;
         }
         Trap::Exception(_) => {
-            println!("[kernel] process exception, killing process");
-            exit_current_task(ECODE_BAD_PROCESS_HEHAVIOR);
+            let signal = fault::report_user_fault(cx, scause, stval);
+            coredump::dump_core(cx, signal);
+            exit_current_task(fault::SIGNAL_EXIT_BASE + signal);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             timer::set_next_trigger();
             debug!("[kernel] clock interrupted");
+            fs::flush_expired();
             suspend_current_task();
         }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            drivers::handle_external();
+        }
         Trap::Interrupt(_) => {
             panic!(
                 "unsupported interrupt: scause {:?}, stval {}",
@@ -119,3 +130,9 @@ pub fn enable_timer_interrupt() {
         sie::set_stimer();
     }
 }
+
+pub fn enable_external_interrupt() {
+    unsafe {
+        sie::set_sext();
+    }
+}
@@ -1,10 +1,13 @@
 import os
+import shutil
 
 BASE_ADDRESS=0x80400000
 STEP = 0x20000
 LINKER='src/linker.ld'
 LINKER_SRC='src/linker_src.ld'
 RUST_BIN_FILE='.rs'
+MUSL_CC='riscv64-linux-musl-gcc'
+MUSL_DIR='musl'
 
 def main():
     apps = map(
@@ -25,14 +28,33 @@ def main():
         if code!=0:
             raise Exception("shell exit with %d" % code)
         binmap.append(name)
+    files = [(name, 'target/riscv64gc-unknown-none-elf/release/%s' % name) for name in binmap]
+    files += build_musl()
     bins = []
-    for name in binmap:
+    for (name, file) in files:
         bins.append('[[bin]]\n')
         bins.append('name="%s"\n' % name)
-        bins.append('file="target/riscv64gc-unknown-none-elf/release/%s"\n' % name)
+        bins.append('file="%s"\n' % file)
         bins.append('\n')
     with open('binmap.toml', 'w') as f:
         f.writelines(bins)
 
+# the c programs linked statically against musl, only when there is a
+# cross compiler for them
+def build_musl():
+    if shutil.which(MUSL_CC) is None:
+        return []
+    built = []
+    for src in sorted(os.listdir(MUSL_DIR)):
+        if not src.endswith('.c'):
+            continue
+        name = 'musl_' + src.removesuffix('.c')
+        out = '%s/%s' % (MUSL_DIR, name)
+        code = os.system('%s -static -O2 -o %s %s/%s' % (MUSL_CC, out, MUSL_DIR, src))
+        if code != 0:
+            raise Exception("%s exit with %d" % (MUSL_CC, code))
+        built.append((name, out))
+    return built
+
 if __name__ == "__main__":
     main()
\ No newline at end of file
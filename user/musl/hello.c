@@ -0,0 +1,8 @@
+// a static musl program run as it is, to check the kernel speaks enough of
+// the linux abi for one
+#include <stdio.h>
+
+int main(void) {
+    printf("hello from musl\n");
+    return 0;
+}
@@ -11,11 +11,11 @@ fn main() -> i32 {
     let mut namebuf = [0u8; 128];
     let name = user_lib::get_task_info(&mut namebuf[..]);
     match name {
-        Some(name) => {
+        Ok(name) => {
             println!("my app name is: {}, going to exit", name);
         }
-        None => {
-            println!("get name failed");
+        Err(e) => {
+            println!("get name failed: {}", e);
         }
     }
     0
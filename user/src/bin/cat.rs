@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{println, read, write, FD_STDIN, FD_STDOUT};
+
+// copy stdin to stdout until the end of the input
+#[no_mangle]
+fn main() -> i32 {
+    let mut buf = [0u8; 256];
+    loop {
+        match read(FD_STDIN, &mut buf) {
+            Ok(0) => return 0,
+            Ok(n) => {
+                if let Err(e) = write(FD_STDOUT, &buf[..n]) {
+                    println!("cat: write failed: {}", e);
+                    return 1;
+                }
+            }
+            Err(e) => {
+                println!("cat: read failed: {}", e);
+                return 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    close, fstat, open, println, read, stat, write, OpenFlags, Stat, S_IFBLK, S_IFCHR, S_IFMT,
+};
+
+fn mode_of(path: &str) -> u32 {
+    let mut st = Stat::default();
+    match stat(path, &mut st) {
+        Ok(()) => st.mode & S_IFMT,
+        Err(_) => 0,
+    }
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    // stdout came from /dev/console
+    let mut st = Stat::default();
+    if fstat(1, &mut st).is_err() || st.mode & S_IFMT != S_IFCHR || st.rdev != (5 << 8) | 1 {
+        println!("stdout is not the console");
+        return 1;
+    }
+    if mode_of("/dev/null") != S_IFCHR || mode_of("/dev/vda") != S_IFBLK {
+        println!("bad device nodes in /dev");
+        return 1;
+    }
+
+    let mut buf = [1u8; 64];
+    let Ok(fd) = open("/dev/null", OpenFlags::RDWR) else {
+        println!("can not open /dev/null");
+        return 1;
+    };
+    if write(fd, &buf) != Ok(64) || read(fd, &mut buf) != Ok(0) {
+        println!("/dev/null misbehaves");
+        return 1;
+    }
+    let _ = close(fd);
+
+    let Ok(fd) = open("/dev/zero", OpenFlags::RDONLY) else {
+        println!("can not open /dev/zero");
+        return 1;
+    };
+    if read(fd, &mut buf) != Ok(64) || buf.iter().any(|&b| b != 0) {
+        println!("/dev/zero misbehaves");
+        return 1;
+    }
+    let _ = close(fd);
+
+    let mut other = [0u8; 64];
+    let Ok(fd) = open("/dev/random", OpenFlags::RDONLY) else {
+        println!("can not open /dev/random");
+        return 1;
+    };
+    if read(fd, &mut buf) != Ok(64) || read(fd, &mut other) != Ok(64) {
+        println!("can not read /dev/random");
+        return 1;
+    }
+    let _ = close(fd);
+    if buf == other {
+        println!("/dev/random repeats itself");
+        return 1;
+    }
+
+    // the jfs superblock starts the disk
+    let mut sector = [0u8; 512];
+    let Ok(fd) = open("/dev/vda", OpenFlags::RDONLY) else {
+        println!("can not open /dev/vda");
+        return 1;
+    };
+    if read(fd, &mut sector) != Ok(512) || sector[..4] == [0; 4] {
+        println!("can not read /dev/vda");
+        return 1;
+    }
+    let _ = close(fd);
+    println!("dev_test passed");
+    0
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{println, statfs, StatFs};
+
+#[no_mangle]
+fn main() -> i32 {
+    let mut st = StatFs::default();
+    if let Err(e) = statfs("/", &mut st) {
+        println!("statfs failed: {}", e);
+        return 1;
+    }
+    let kib = |blocks: u64| blocks * st.bsize as u64 / 1024;
+    println!(
+        "{:>10} {:>10} {:>10} {:>8} {:>8}",
+        "1K-blocks", "used", "avail", "inodes", "ifree"
+    );
+    println!(
+        "{:>10} {:>10} {:>10} {:>8} {:>8}",
+        kib(st.blocks),
+        kib(st.blocks - st.bfree),
+        kib(st.bavail),
+        st.files,
+        st.ffree
+    );
+    0
+}
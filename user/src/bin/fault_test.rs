@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+use core::slice;
+
+use user_lib::{close, open, println, read, write, Errno, OpenFlags};
+
+// the kernel maps these two pages into every process, but not for it
+const TRAMPOLINE: usize = usize::MAX - 4096 + 1;
+const TRAP_CONTEXT: usize = TRAMPOLINE - 4096;
+
+#[no_mangle]
+fn main() -> i32 {
+    let fd = match open("/dev/zero", OpenFlags::RDONLY) {
+        Ok(fd) => fd,
+        Err(e) => {
+            println!("can not open /dev/zero: {}", e);
+            return 1;
+        }
+    };
+    // zeros over the saved registers would wreck this process on return
+    let ctx = unsafe { slice::from_raw_parts_mut(TRAP_CONTEXT as *mut u8, 64) };
+    let rt = read(fd, ctx);
+    let _ = close(fd);
+    if rt != Err(Errno::EFAULT) {
+        println!("read into the trap context gave {:?}", rt);
+        return 1;
+    }
+    let Ok(fd) = open("/dev/null", OpenFlags::WRONLY) else {
+        println!("can not open /dev/null");
+        return 1;
+    };
+    let code = unsafe { slice::from_raw_parts(TRAMPOLINE as *const u8, 64) };
+    let rt = write(fd, code);
+    let _ = close(fd);
+    if rt != Err(Errno::EFAULT) {
+        println!("write from the trampoline gave {:?}", rt);
+        return 1;
+    }
+    println!("fault_test passed");
+    0
+}
@@ -1,32 +1,33 @@
 #![no_std]
 #![no_main]
 
-use user_lib::{close, open, println, read, write, OpenFlags};
+use user_lib::{close, fstat, open, println, read, stat, write, OpenFlags, Stat};
 
 #[no_mangle]
 fn main() -> i32 {
     let filename = "sample.txt";
     let content = "hello str";
-    let fd = open(filename, OpenFlags::WRONLY | OpenFlags::CREATE);
-    if fd < 0 {
-        println!("can not open file to write, code: {}", fd);
-        return 1;
-    }
-    let fd = fd as usize;
-    let written = write(fd, content.as_bytes());
-    if written != content.len() as isize {
+    let fd = match open(filename, OpenFlags::WRONLY | OpenFlags::CREATE) {
+        Ok(fd) => fd,
+        Err(e) => {
+            println!("can not open file to write: {}", e);
+            return 1;
+        }
+    };
+    if write(fd, content.as_bytes()) != Ok(content.len()) {
         println!("can not write to file");
         return 1;
     }
-    close(fd);
-    let fd = open(filename, OpenFlags::RDONLY);
-    if fd < 0 {
-        println!("can not open file to read code: {}", fd);
-        return 1;
-    }
-    let fd = fd as usize;
+    let _ = close(fd);
+    let fd = match open(filename, OpenFlags::RDONLY) {
+        Ok(fd) => fd,
+        Err(e) => {
+            println!("can not open file to read: {}", e);
+            return 1;
+        }
+    };
     let mut buf = [0; 128];
-    let readed = read(fd, &mut buf) as usize;
+    let readed = read(fd, &mut buf).unwrap_or(0);
     if readed != content.len() {
         println!("bad read");
         return 1;
@@ -35,5 +36,24 @@ fn main() -> i32 {
         println!("readed content not consist");
         return 1;
     }
+    let mut st = Stat::default();
+    if fstat(fd, &mut st).is_err() || st.size != content.len() as i64 || st.is_dir() {
+        println!("bad fstat");
+        return 1;
+    }
+    let _ = close(fd);
+    let mut root = Stat::default();
+    if stat("/", &mut root).is_err() || !root.is_dir() {
+        println!("bad stat of /");
+        return 1;
+    }
+    println!(
+        "{}: mode {:o} uid {} size {} mtime {}",
+        filename,
+        st.perm(),
+        st.uid,
+        st.size,
+        st.mtime
+    );
     0
 }
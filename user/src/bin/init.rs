@@ -13,25 +13,26 @@ fn main() -> i32 {
 }
 
 fn exec_shell() {
-    if fork() == 0 {
-        let v = exec("shell");
-        if v != 0 {
-            panic!("exec user shell failed")
-        }
+    if fork() == Ok(0) {
+        let e = exec("shell");
+        panic!("exec user shell failed: {}", e)
     }
 }
 
 fn init_loop() {
     let mut exit_code = 0;
     loop {
-        let pid = wait(&mut exit_code);
-        if pid > 0 {
-            println!("[init] accept exit code {} from {}", exit_code, pid);
-        } else if pid == ENOCHILDREN {
-            println!("[init] no sub process found existing");
-            break;
-        } else {
-            panic!("bad wait pid code");
+        match wait(&mut exit_code) {
+            Ok(pid) => {
+                println!("[init] accept exit code {} from {}", exit_code, pid);
+            }
+            Err(Errno::ECHILD) => {
+                println!("[init] no sub process found existing");
+                break;
+            }
+            Err(e) => {
+                panic!("bad wait: {}", e);
+            }
         }
         yield_();
     }
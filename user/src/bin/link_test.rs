@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    close, link, open, println, readlink, rename, stat, symlink, write, Errno, OpenFlags, Stat,
+};
+
+fn stat_of(path: &str) -> Option<Stat> {
+    let mut st = Stat::default();
+    stat(path, &mut st).ok().map(|_| st)
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    let fd = match open(
+        "ln_a",
+        OpenFlags::WRONLY | OpenFlags::CREATE | OpenFlags::TRUNC,
+    ) {
+        Ok(fd) => fd,
+        Err(e) => {
+            println!("can not create ln_a: {}", e);
+            return 1;
+        }
+    };
+    let _ = write(fd, b"linked");
+    let _ = close(fd);
+    let a = stat_of("ln_a").unwrap();
+
+    // the names are left behind, so a second run finds them in place
+    match link("ln_a", "ln_b") {
+        Ok(()) | Err(Errno::EEXIST) => {}
+        Err(e) => {
+            println!("link failed: {}", e);
+            return 1;
+        }
+    }
+    match stat_of("ln_b") {
+        Some(b) if b.ino == a.ino && b.nlink == 2 => {}
+        _ => {
+            println!("ln_b is not a second link to ln_a");
+            return 1;
+        }
+    }
+
+    match symlink("ln_a", "ln_s") {
+        Ok(()) | Err(Errno::EEXIST) => {}
+        Err(e) => {
+            println!("symlink failed: {}", e);
+            return 1;
+        }
+    }
+    let mut buf = [0u8; 32];
+    match readlink("ln_s", &mut buf) {
+        Ok(n) if &buf[..n] == b"ln_a" => {}
+        rt => {
+            println!("bad readlink: {:?}", rt);
+            return 1;
+        }
+    }
+    if stat_of("ln_s").map(|s| s.ino) != Some(a.ino) {
+        println!("ln_s does not lead to ln_a");
+        return 1;
+    }
+
+    if rename("ln_b", "ln_c").is_err() || stat_of("ln_b").is_some() {
+        println!("rename failed");
+        return 1;
+    }
+    if stat_of("ln_c").map(|c| c.ino) != Some(a.ino) || rename("ln_c", "ln_b").is_err() {
+        println!("ln_c lost the inode of ln_a");
+        return 1;
+    }
+    println!("link_test passed");
+    0
+}
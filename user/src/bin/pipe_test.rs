@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{close, dup, dup2, exit, fork, pipe, println, read, wait4, write};
+
+// more than the pipe holds, so the writer has to wait for the reader
+const TOTAL: usize = 10000;
+
+#[no_mangle]
+fn main() -> i32 {
+    let mut fds = [0usize; 2];
+    if let Err(e) = pipe(&mut fds) {
+        println!("pipe failed: {}", e);
+        return 1;
+    }
+    let [rfd, wfd] = fds;
+    let pid = match fork() {
+        Ok(0) => {
+            let _ = close(rfd);
+            let chunk = [b'p'; 100];
+            for _ in 0..TOTAL / chunk.len() {
+                if write(wfd, &chunk).is_err() {
+                    exit(1);
+                }
+            }
+            let _ = close(wfd);
+            exit(0);
+        }
+        Ok(pid) => pid,
+        Err(e) => {
+            println!("fork failed: {}", e);
+            return 1;
+        }
+    };
+    // the reader only sees the end once this copy of the write end is gone
+    let _ = close(wfd);
+    let mut buf = [0u8; 300];
+    let mut total = 0;
+    loop {
+        match read(rfd, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if buf[..n].iter().any(|&b| b != b'p') {
+                    println!("bad byte in the pipe");
+                    return 1;
+                }
+                total += n;
+            }
+            Err(e) => {
+                println!("read failed: {}", e);
+                return 1;
+            }
+        }
+    }
+    let mut code = 0;
+    if wait4(pid, &mut code).is_err() || total != TOTAL || code != 0 {
+        println!("read {} of {} bytes", total, TOTAL);
+        return 1;
+    }
+
+    let Ok(copy) = dup(rfd) else {
+        println!("dup of the read end failed");
+        return 1;
+    };
+    if dup2(copy, 20) != Ok(20) || read(20, &mut buf) != Ok(0) {
+        println!("dup of the read end misbehaves");
+        return 1;
+    }
+    let _ = close(copy);
+    let _ = close(20);
+    let _ = close(rfd);
+    println!("pipe_test passed");
+    0
+}
@@ -1,6 +1,9 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+use alloc::vec::Vec;
 use core::str;
 
 use user_lib::*;
@@ -18,28 +21,195 @@ fn main() -> i32 {
         let cmd = cmd.unwrap();
         if cmd == "exit" {
             break;
-        } else {
-            let code = exec_cmd(cmd);
-            println!("[shell] program exit with code: {}", code);
+        } else if cmd.trim() == "core on" || cmd.trim() == "core off" {
+            // `core on` has the programs run from here on leave core files
+            let _ = core_dump(cmd.trim() == "core on");
+        } else if !cmd.trim().is_empty() {
+            // `trace cmd` has the kernel log the syscalls of cmd
+            let code = match cmd.trim_start().strip_prefix("trace ") {
+                Some(line) => run_line(line, true),
+                None => run_line(cmd, false),
+            };
+            match signal_name(code) {
+                Some(sig) => println!("[shell] program killed by {}", sig),
+                None => println!("[shell] program exit with code: {}", code),
+            }
         }
     }
     0
 }
 
-fn exec_cmd(cmd: &str) -> i32 {
-    match fork() {
-        0 => {
-            let code = exec(cmd);
-            println!("exec cmd {} err code {}", cmd, code);
-            exit(code as i32);
-            panic!("should exit")
+// the kernel kills a faulting program with 128 + the signal as exit code
+fn signal_name(code: i32) -> Option<&'static str> {
+    match code - 128 {
+        4 => Some("SIGILL"),
+        5 => Some("SIGTRAP"),
+        7 => Some("SIGBUS"),
+        9 => Some("SIGKILL"),
+        11 => Some("SIGSEGV"),
+        _ => None,
+    }
+}
+
+// one program of a pipeline, with the files its stdin and stdout are
+// redirected to
+struct Stage<'a> {
+    name: &'a str,
+    input: Option<&'a str>,
+    output: Option<&'a str>,
+}
+
+// split on whitespace, `<` and `>` are tokens of their own
+fn tokens(s: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = None;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() || c == '<' || c == '>' {
+            if let Some(st) = start.take() {
+                out.push(&s[st..i]);
+            }
+            if !c.is_whitespace() {
+                out.push(&s[i..i + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(st) = start {
+        out.push(&s[st..]);
+    }
+    out
+}
+
+// `a < in | b | c > out`, programs take no arguments yet
+fn parse(line: &str) -> Result<Vec<Stage<'_>>, &'static str> {
+    let parts: Vec<&str> = line.split('|').collect();
+    let mut stages = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        let mut stage = Stage {
+            name: "",
+            input: None,
+            output: None,
+        };
+        let mut toks = tokens(part).into_iter();
+        while let Some(tok) = toks.next() {
+            match tok {
+                "<" | ">" => {
+                    let path = match toks.next() {
+                        Some("<") | Some(">") | None => return Err("redirection without a file"),
+                        Some(path) => path,
+                    };
+                    let slot = match tok {
+                        "<" if i == 0 => &mut stage.input,
+                        ">" if i == parts.len() - 1 => &mut stage.output,
+                        _ => return Err("redirection in the middle of a pipeline"),
+                    };
+                    if slot.replace(path).is_some() {
+                        return Err("redirected twice");
+                    }
+                }
+                name if stage.name.is_empty() => stage.name = name,
+                _ => return Err("programs take no arguments"),
+            }
+        }
+        if stage.name.is_empty() {
+            return Err("empty command");
+        }
+        stages.push(stage);
+    }
+    Ok(stages)
+}
+
+fn run_line(line: &str, traced: bool) -> i32 {
+    let stages = match parse(line) {
+        Ok(stages) => stages,
+        Err(msg) => {
+            println!("[shell] {}", msg);
+            return -1;
         }
-        pid => {
-            let mut rcode = 0;
-            wait4(pid as usize, &mut rcode);
-            rcode
+    };
+    // pipes[i] joins stage i to stage i + 1
+    let mut pipes = Vec::new();
+    for _ in 1..stages.len() {
+        let mut fds = [0; 2];
+        if let Err(e) = pipe(&mut fds) {
+            println!("[shell] pipe failed: {}", e);
+            close_all(&pipes);
+            return -1;
         }
+        pipes.push(fds);
+    }
+    let mut pids = Vec::new();
+    for (i, stage) in stages.iter().enumerate() {
+        let input = i.checked_sub(1).map(|j| pipes[j][0]);
+        let output = pipes.get(i).map(|p| p[1]);
+        match fork() {
+            Ok(0) => run_stage(stage, input, output, &pipes, traced),
+            Ok(pid) => pids.push(pid),
+            Err(e) => println!("[shell] fork failed: {}", e),
+        }
+    }
+    // the children hold the ends now, a reader only sees the end of its
+    // pipe once every copy of the write end is closed
+    close_all(&pipes);
+    let mut code = 0;
+    for pid in pids {
+        let _ = wait4(pid, &mut code);
+    }
+    code
+}
+
+fn close_all(pipes: &[[usize; 2]]) {
+    for &fd in pipes.iter().flatten() {
+        let _ = close(fd);
+    }
+}
+
+fn open_or_exit(path: &str, flags: OpenFlags) -> usize {
+    match open(path, flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            println!("[shell] can not open {}: {}", path, e);
+            exit(-(e.as_raw() as i32))
+        }
+    }
+}
+
+// in the child, wire up stdin and stdout and become the program
+fn run_stage(
+    stage: &Stage,
+    input: Option<usize>,
+    output: Option<usize>,
+    pipes: &[[usize; 2]],
+    traced: bool,
+) -> ! {
+    // files a redirection opens, these win over the pipe ends
+    let opened_in = stage
+        .input
+        .map(|path| open_or_exit(path, OpenFlags::RDONLY));
+    let opened_out = stage.output.map(|path| {
+        open_or_exit(
+            path,
+            OpenFlags::WRONLY | OpenFlags::CREATE | OpenFlags::TRUNC,
+        )
+    });
+    if let Some(fd) = opened_in.or(input) {
+        let _ = dup2(fd, FD_STDIN);
+    }
+    if let Some(fd) = opened_out.or(output) {
+        let _ = dup2(fd, FD_STDOUT);
+    }
+    // the pipe ends are among pipes, only the opened files are left
+    close_all(pipes);
+    for fd in opened_in.into_iter().chain(opened_out) {
+        let _ = close(fd);
+    }
+    if traced {
+        let _ = trace(true);
     }
+    let e = exec(stage.name);
+    println!("exec cmd {} failed: {}", stage.name, e);
+    exit(-(e.as_raw() as i32))
 }
 
 fn readline(buf: &mut [u8]) -> Option<&str> {
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    close, link, open, println, read, rename, rmdir, stat, statfs, unlink, write, Errno, OpenFlags,
+    Stat, StatFs,
+};
+
+const TMPFS_MAGIC: i64 = 0x0102_1994;
+
+fn fs_type(path: &str) -> i64 {
+    let mut st = StatFs::default();
+    match statfs(path, &mut st) {
+        Ok(()) => st.fs_type,
+        Err(_) => 0,
+    }
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    if fs_type("/tmp") != TMPFS_MAGIC || fs_type("/") == TMPFS_MAGIC {
+        println!("/tmp is not a tmpfs of its own");
+        return 1;
+    }
+    let fd = match open(
+        "/tmp/t_a",
+        OpenFlags::WRONLY | OpenFlags::CREATE | OpenFlags::TRUNC,
+    ) {
+        Ok(fd) => fd,
+        Err(e) => {
+            println!("can not create /tmp/t_a: {}", e);
+            return 1;
+        }
+    };
+    // more than a page, so the file takes a second frame
+    let data = [b'x'; 5000];
+    let _ = write(fd, &data);
+    let _ = close(fd);
+
+    let Ok(fd) = open("/tmp/t_a", OpenFlags::RDONLY) else {
+        println!("can not open /tmp/t_a");
+        return 1;
+    };
+    let mut buf = [0u8; 6000];
+    let n = read(fd, &mut buf);
+    let _ = close(fd);
+    if n != Ok(data.len()) || buf[..data.len()] != data {
+        println!("read back {:?} bytes from /tmp/t_a", n);
+        return 1;
+    }
+    let mut st = Stat::default();
+    if stat("/tmp/t_a", &mut st).is_err() || st.size != data.len() as i64 {
+        println!("bad stat of /tmp/t_a");
+        return 1;
+    }
+
+    // names do not cross from one file system to another
+    let rt = link("/tmp/t_a", "t_b");
+    if rt != Err(Errno::EXDEV) || rename("/tmp/t_a", "t_b") != Err(Errno::EXDEV) {
+        println!("link out of /tmp gave {:?}", rt);
+        return 1;
+    }
+    if rename("/tmp/t_a", "/tmp/t_b").is_err() || stat("/tmp/../tmp/t_b", &mut st).is_err() {
+        println!("rename in /tmp failed");
+        return 1;
+    }
+    // the name goes, a file is no directory and /tmp stays as a mount point
+    if rmdir("/tmp/t_b") != Err(Errno::ENOTDIR) || unlink("/tmp/t_b").is_err() {
+        println!("unlink in /tmp failed");
+        return 1;
+    }
+    if stat("/tmp/t_b", &mut st) != Err(Errno::ENOENT) {
+        println!("/tmp/t_b outlived its unlink");
+        return 1;
+    }
+    if rmdir("/tmp") != Err(Errno::EBUSY) {
+        println!("rmdir took away a mount point");
+        return 1;
+    }
+    println!("tmp_test passed");
+    0
+}
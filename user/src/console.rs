@@ -4,7 +4,8 @@ struct Stdout;
 const STDOUT: usize = 1;
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        super::write(STDOUT, s.as_bytes());
+        // nowhere to report a failed print
+        super::write(STDOUT, s.as_bytes()).ok();
         Ok(())
     }
 }
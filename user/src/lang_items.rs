@@ -15,6 +15,5 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("Panic: {}", info.message())
     }
-    exit(1);
-    loop {}
+    exit(1)
 }
@@ -16,8 +16,7 @@ mod syscall;
 pub extern "C" fn _start() -> ! {
     clear_bss();
     heap::init_heap();
-    exit(main());
-    unreachable!("should exit after main")
+    exit(main())
 }
 
 fn clear_bss() {
@@ -38,48 +37,59 @@ fn main() -> i32 {
     panic!("can not find main")
 }
 
-pub fn write(fd: usize, buf: &[u8]) -> isize {
-    syscall::sys_write(fd, buf)
+// what went wrong in a failed syscall, the wrappers below hand it back
+// as the Err of their Result
+pub use errno::Errno;
+
+// the result of a syscall that returns a count or an fd
+fn check(ret: isize) -> Result<usize, Errno> {
+    errno::decode(ret)
 }
-pub fn exit(exit_code: i32) -> isize {
-    syscall::sys_exit(exit_code)
+
+// the result of a syscall that returns 0 on success
+fn check_zero(ret: isize) -> Result<(), Errno> {
+    errno::decode(ret).map(|_| ())
 }
-pub fn get_task_info(name_buf: &mut [u8]) -> Option<&str> {
-    let l = syscall::sys_get_task_info(name_buf);
-    if l < 0 {
-        return None;
-    }
-    let name = core::str::from_utf8(&name_buf[..(l as usize)]).unwrap();
-    Some(name)
+
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, Errno> {
+    check(syscall::sys_write(fd, buf))
+}
+pub fn exit(exit_code: i32) -> ! {
+    syscall::sys_exit(exit_code);
+    unreachable!("should not return from exit")
+}
+pub fn get_task_info(name_buf: &mut [u8]) -> Result<&str, Errno> {
+    let l = check(syscall::sys_get_task_info(name_buf))?;
+    core::str::from_utf8(&name_buf[..l]).map_err(|_| Errno::EINVAL)
 }
 
-pub fn yield_() -> isize {
-    sys_yield()
+pub fn yield_() {
+    sys_yield();
 }
 
-pub fn get_time() -> isize {
-    sys_get_time()
+// milliseconds since boot
+pub fn get_time() -> usize {
+    sys_get_time() as usize
 }
 
-const EAGAIN: isize = -2;
-pub const ENOCHILDREN: isize = -3;
-pub fn wait(code: &mut i32) -> isize {
+// the pid of any exited child, ECHILD once there are none left
+pub fn wait(code: &mut i32) -> Result<usize, Errno> {
     loop {
-        match syscall::sys_waitpid(-1, code as *mut i32) {
-            EAGAIN => {
+        match check(syscall::sys_waitpid(-1, code as *mut i32)) {
+            Err(Errno::EAGAIN) => {
                 yield_();
             }
-            pid => return pid,
+            other => return other,
         }
     }
 }
-pub fn wait4(pid: usize, code: &mut i32) -> isize {
+pub fn wait4(pid: usize, code: &mut i32) -> Result<usize, Errno> {
     if pid > isize::MAX as usize {
-        return -1;
+        return Err(Errno::EINVAL);
     }
     loop {
-        match syscall::sys_waitpid(pid as isize, code as *mut i32) {
-            EAGAIN => {
+        match check(syscall::sys_waitpid(pid as isize, code as *mut i32)) {
+            Err(Errno::EAGAIN) => {
                 yield_();
             }
             other => return other,
@@ -87,52 +97,182 @@ pub fn wait4(pid: usize, code: &mut i32) -> isize {
     }
 }
 
-pub fn fork() -> isize {
-    syscall::sys_fork()
+// have the kernel log every syscall of this process and the ones it forks
+// from now on, or stop; returns whether it did before
+pub fn trace(on: bool) -> Result<bool, Errno> {
+    check(syscall::sys_trace(on)).map(|was| was != 0)
 }
 
-pub fn exec(path: &str) -> isize {
+// have the kernel write core.<pid> when this process or one it forks from
+// now on is killed by an exception, or stop; returns whether it did before
+pub fn core_dump(on: bool) -> Result<bool, Errno> {
+    check(syscall::sys_core_dump(on)).map(|was| was != 0)
+}
+
+// the child pid in the parent, 0 in the child
+pub fn fork() -> Result<usize, Errno> {
+    check(syscall::sys_fork())
+}
+
+// only returns when path could not be run
+pub fn exec(path: &str) -> Errno {
     let mut buf: [u8; 128] = [0; 128];
-    let ptr = ensure_cstr(path, &mut buf);
-    match ptr {
-        None => -1,
-        Some(cstr) => syscall::sys_exec(cstr),
+    let rt = ensure_cstr(path, &mut buf).and_then(|cstr| check(syscall::sys_exec(cstr)));
+    match rt {
+        Err(e) => e,
+        Ok(_) => unreachable!("exec returned without an error"),
     }
 }
 
-fn ensure_cstr(path: &str, buf: &mut [u8]) -> Option<*const u8> {
+fn ensure_cstr(path: &str, buf: &mut [u8]) -> Result<*const u8, Errno> {
     let len = path.len();
     if len > 0 && path.as_bytes()[len - 1] == b'\0' {
-        return Some(path.as_ptr());
+        return Ok(path.as_ptr());
     }
     if len >= buf.len() {
-        println!("path is too long");
-        return None;
+        return Err(Errno::ENAMETOOLONG);
     }
     buf[..len].copy_from_slice(path.as_bytes());
     buf[len] = 0;
-    Some(buf.as_ptr())
+    Ok(buf.as_ptr())
 }
 
-pub fn open(path: &str, flags: OpenFlags) -> isize {
+pub fn open(path: &str, flags: OpenFlags) -> Result<usize, Errno> {
     let mut buf: [u8; 128] = [0; 128];
-    let ptr = ensure_cstr(path, &mut buf);
-    match ptr {
-        None => -1,
-        Some(cstr) => syscall::sys_open(cstr, flags.bits()),
-    }
+    let cstr = ensure_cstr(path, &mut buf)?;
+    check(syscall::sys_openat(AT_FDCWD, cstr, flags.bits(), 0o644))
+}
+pub fn close(fd: usize) -> Result<(), Errno> {
+    check_zero(syscall::sys_close(fd))
+}
+
+const AT_FDCWD: isize = -100;
+const AT_REMOVEDIR: u32 = 0x200;
+
+pub fn fstat(fd: usize, st: &mut Stat) -> Result<(), Errno> {
+    check_zero(syscall::sys_fstat(fd, st as *mut Stat as *mut u8))
+}
+
+pub fn stat(path: &str, st: &mut Stat) -> Result<(), Errno> {
+    let mut buf: [u8; 128] = [0; 128];
+    let cstr = ensure_cstr(path, &mut buf)?;
+    check_zero(syscall::sys_fstatat(
+        AT_FDCWD,
+        cstr,
+        st as *mut Stat as *mut u8,
+        0,
+    ))
+}
+
+// usage of the fs holding path
+pub fn statfs(path: &str, st: &mut StatFs) -> Result<(), Errno> {
+    let mut buf: [u8; 128] = [0; 128];
+    let cstr = ensure_cstr(path, &mut buf)?;
+    check_zero(syscall::sys_statfs(cstr, st as *mut StatFs as *mut u8))
+}
+
+// call f with both paths nul terminated
+fn with_cstrs(
+    a: &str,
+    b: &str,
+    f: impl FnOnce(*const u8, *const u8) -> isize,
+) -> Result<(), Errno> {
+    let mut buf_a: [u8; 128] = [0; 128];
+    let mut buf_b: [u8; 128] = [0; 128];
+    let a = ensure_cstr(a, &mut buf_a)?;
+    let b = ensure_cstr(b, &mut buf_b)?;
+    check_zero(f(a, b))
+}
+
+// a hard link new to the file at old
+pub fn link(old: &str, new: &str) -> Result<(), Errno> {
+    with_cstrs(old, new, |old, new| {
+        syscall::sys_linkat(AT_FDCWD, old, AT_FDCWD, new, 0)
+    })
+}
+
+// a symlink at path pointing to target
+pub fn symlink(target: &str, path: &str) -> Result<(), Errno> {
+    with_cstrs(target, path, |target, path| {
+        syscall::sys_symlinkat(target, AT_FDCWD, path)
+    })
+}
+
+// remove the file or symlink at path
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    let mut path_buf: [u8; 128] = [0; 128];
+    let cstr = ensure_cstr(path, &mut path_buf)?;
+    check_zero(syscall::sys_unlinkat(AT_FDCWD, cstr, 0))
+}
+
+// remove the empty directory at path
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    let mut path_buf: [u8; 128] = [0; 128];
+    let cstr = ensure_cstr(path, &mut path_buf)?;
+    check_zero(syscall::sys_unlinkat(AT_FDCWD, cstr, AT_REMOVEDIR))
+}
+
+// the target of the symlink at path, returns its length
+pub fn readlink(path: &str, buf: &mut [u8]) -> Result<usize, Errno> {
+    let mut path_buf: [u8; 128] = [0; 128];
+    let cstr = ensure_cstr(path, &mut path_buf)?;
+    check(syscall::sys_readlinkat(AT_FDCWD, cstr, buf))
 }
-pub fn close(fd: usize) -> isize {
-    syscall::sys_close(fd)
+
+pub fn rename(old: &str, new: &str) -> Result<(), Errno> {
+    with_cstrs(old, new, |old, new| {
+        syscall::sys_renameat(AT_FDCWD, old, AT_FDCWD, new)
+    })
 }
 
-const FD_STDIN: usize = 0;
-const FD_STDOUT: usize = 1;
+// mount a new fstype file system from source on the directory target,
+// data holds its options, like "cache=64,flush=500" for a jfs
+pub fn mount(source: &str, target: &str, fstype: &str, data: &str) -> Result<(), Errno> {
+    let mut buf: [u8; 128] = [0; 128];
+    let fstype = ensure_cstr(fstype, &mut buf)?;
+    let mut data_buf: [u8; 128] = [0; 128];
+    let data = ensure_cstr(data, &mut data_buf)?;
+    with_cstrs(source, target, |source, target| {
+        syscall::sys_mount(source, target, fstype, 0, data)
+    })
+}
+
+pub fn umount(target: &str) -> Result<(), Errno> {
+    let mut buf: [u8; 128] = [0; 128];
+    let cstr = ensure_cstr(target, &mut buf)?;
+    check_zero(syscall::sys_umount2(cstr, 0))
+}
+
+// a pipe as fds, the read end first
+pub fn pipe(fds: &mut [usize; 2]) -> Result<(), Errno> {
+    let mut raw = [0i32; 2];
+    check_zero(syscall::sys_pipe2(&mut raw, 0))?;
+    *fds = [raw[0] as usize, raw[1] as usize];
+    Ok(())
+}
+
+pub fn dup(fd: usize) -> Result<usize, Errno> {
+    check(syscall::sys_dup(fd))
+}
 
-pub fn read(fd: usize, buf: &mut [u8]) -> isize {
+// new_fd names the file at old_fd from now on
+pub fn dup2(old_fd: usize, new_fd: usize) -> Result<usize, Errno> {
+    if old_fd != new_fd {
+        return check(syscall::sys_dup3(old_fd, new_fd, 0));
+    }
+    // nothing to do, as long as old_fd is open
+    let mut st = Stat::default();
+    fstat(old_fd, &mut st)?;
+    Ok(new_fd)
+}
+
+pub const FD_STDIN: usize = 0;
+pub const FD_STDOUT: usize = 1;
+
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, Errno> {
     loop {
-        match syscall::sys_read(fd, buf) {
-            EAGAIN => {
+        match check(syscall::sys_read(fd, buf)) {
+            Err(Errno::EAGAIN) => {
                 yield_();
                 continue;
             }
@@ -146,25 +286,22 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn get_char() -> Option<u8> {
     let mut buf = [0u8; 1];
     match read(FD_STDIN, &mut buf) {
-        0 => None,
-        1 => Some(buf[0]),
-        _other => {
-            panic!("read stdin failed")
+        Ok(0) => None,
+        Ok(_) => Some(buf[0]),
+        Err(e) => {
+            panic!("read stdin failed: {}", e)
         }
     }
 }
 pub fn put_char(c: u8) {
     let buf = [c; 1];
-    match write(FD_STDOUT, &buf) {
-        1 => {}
-        _other => {
-            panic!("read stdin failed")
-        }
+    if let Err(e) = write(FD_STDOUT, &buf) {
+        panic!("write stdout failed: {}", e)
     }
 }
 
-pub fn get_pid() -> isize {
-    syscall::sys_get_pid()
+pub fn get_pid() -> usize {
+    syscall::sys_get_pid() as usize
 }
 
 bitflags! {
@@ -172,7 +309,67 @@ bitflags! {
         const RDONLY=0;
         const WRONLY=1;
         const RDWR = 1<<1;
-        const CREATE=1<<9;
-        const TRUNC=1<<10;
+        const CREATE=1<<6;
+        const TRUNC=1<<9;
+    }
+}
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+// the riscv64 linux `struct stat`, filled by stat and fstat
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    _pad1: u64,
+    pub size: i64,
+    pub blksize: i32,
+    _pad2: i32,
+    pub blocks: i64,
+    pub atime: i64,
+    pub atime_nsec: u64,
+    pub mtime: i64,
+    pub mtime_nsec: u64,
+    pub ctime: i64,
+    pub ctime_nsec: u64,
+    _unused: [u32; 2],
+}
+
+// the riscv64 linux `struct statfs`, filled by statfs
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatFs {
+    pub fs_type: i64,
+    pub bsize: i64,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: [i32; 2],
+    pub namelen: i64,
+    pub frsize: i64,
+    pub flags: i64,
+    _spare: [i64; 4],
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+    // the permission bits
+    pub fn perm(&self) -> u32 {
+        self.mode & 0o7777
     }
 }
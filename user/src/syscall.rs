@@ -1,67 +1,227 @@
 use core::arch::asm;
 
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_SYMLINKAT: usize = 36;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_RENAMEAT: usize = 38;
+const SYSCALL_UMOUNT2: usize = 39;
+const SYSCALL_MOUNT: usize = 40;
+const SYSCALL_STATFS: usize = 43;
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE2: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_READLINKAT: usize = 78;
+const SYSCALL_FSTATAT: usize = 79;
+const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_EXIT: usize = 93;
-const SYSCALL_GET_TASKINFO: usize = 94;
 const SYSCALL_YIELD: usize = 124;
-const SYSCALL_GET_TIME: usize = 201;
+const SYSCALL_GETPID: usize = 172;
 const SYSCALL_FORK: usize = 220;
-const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_EXEC: usize = 221;
-const SYSCALL_OPEN: usize = 56;
-const SYSCALL_CLOSE: usize = 57;
-const SYSCALL_GETPID: usize = 172;
+const SYSCALL_WAITPID: usize = 260;
+// the kernel's own calls, numbered past linux's
+const SYSCALL_GET_TASKINFO: usize = 1000;
+const SYSCALL_GET_TIME: usize = 1001;
+const SYSCALL_TRACE: usize = 1002;
+const SYSCALL_CORE_DUMP: usize = 1003;
 
 pub fn sys_write(fd: usize, buf: &[u8]) -> isize {
-    syscall(SYSCALL_WRITE, [fd, buf.as_ptr() as usize, buf.len()])
+    syscall(
+        SYSCALL_WRITE,
+        [fd, buf.as_ptr() as usize, buf.len(), 0, 0, 0],
+    )
 }
 
 pub fn sys_read(fd: usize, buf: &mut [u8]) -> isize {
-    syscall(SYSCALL_READ, [fd, buf.as_ptr() as usize, buf.len()])
+    syscall(
+        SYSCALL_READ,
+        [fd, buf.as_ptr() as usize, buf.len(), 0, 0, 0],
+    )
 }
 pub fn sys_get_pid() -> isize {
-    syscall(SYSCALL_GETPID, [0; 3])
+    syscall(SYSCALL_GETPID, [0; 6])
 }
 
 pub fn sys_exit(code: i32) -> isize {
-    syscall(SYSCALL_EXIT, [code as usize, 0, 0])
+    syscall(SYSCALL_EXIT, [code as usize, 0, 0, 0, 0, 0])
 }
 
 pub fn sys_get_task_info(name_buf: &mut [u8]) -> isize {
     let ptr = name_buf.as_ptr();
     syscall(
         SYSCALL_GET_TASKINFO,
-        [ptr as usize, name_buf.len() as usize, 0],
+        [ptr as usize, name_buf.len(), 0, 0, 0, 0],
     )
 }
 
 pub fn sys_yield() -> isize {
-    syscall(SYSCALL_YIELD, [0; 3])
+    syscall(SYSCALL_YIELD, [0; 6])
 }
 
 pub fn sys_get_time() -> isize {
-    syscall(SYSCALL_GET_TIME, [0; 3])
+    syscall(SYSCALL_GET_TIME, [0; 6])
+}
+
+pub fn sys_trace(on: bool) -> isize {
+    syscall(SYSCALL_TRACE, [on as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_core_dump(on: bool) -> isize {
+    syscall(SYSCALL_CORE_DUMP, [on as usize, 0, 0, 0, 0, 0])
 }
 
 pub fn sys_fork() -> isize {
-    syscall(SYSCALL_FORK, [0; 3])
+    syscall(SYSCALL_FORK, [0; 6])
 }
 pub fn sys_waitpid(pid: isize, code: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, code as usize, 0])
+    syscall(SYSCALL_WAITPID, [pid as usize, code as usize, 0, 0, 0, 0])
 }
 pub fn sys_exec(path: *const u8) -> isize {
-    syscall(SYSCALL_EXEC, [path as usize, 0, 0])
+    syscall(SYSCALL_EXEC, [path as usize, 0, 0, 0, 0, 0])
 }
 
-pub fn sys_open(path: *const u8, flag: u32) -> isize {
-    syscall(SYSCALL_OPEN, [path as usize, flag as usize, 0])
+pub fn sys_openat(dirfd: isize, path: *const u8, flag: u32, mode: u32) -> isize {
+    syscall(
+        SYSCALL_OPENAT,
+        [
+            dirfd as usize,
+            path as usize,
+            flag as usize,
+            mode as usize,
+            0,
+            0,
+        ],
+    )
 }
 pub fn sys_close(fd: usize) -> isize {
-    syscall(SYSCALL_CLOSE, [fd, 0, 0])
+    syscall(SYSCALL_CLOSE, [fd, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_fstat(fd: usize, st: *mut u8) -> isize {
+    syscall(SYSCALL_FSTAT, [fd, st as usize, 0, 0, 0, 0])
+}
+pub fn sys_fstatat(dirfd: isize, path: *const u8, st: *mut u8, flags: u32) -> isize {
+    syscall(
+        SYSCALL_FSTATAT,
+        [
+            dirfd as usize,
+            path as usize,
+            st as usize,
+            flags as usize,
+            0,
+            0,
+        ],
+    )
+}
+pub fn sys_statfs(path: *const u8, buf: *mut u8) -> isize {
+    syscall(SYSCALL_STATFS, [path as usize, buf as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_linkat(
+    old_dirfd: isize,
+    old: *const u8,
+    new_dirfd: isize,
+    new: *const u8,
+    flags: u32,
+) -> isize {
+    syscall(
+        SYSCALL_LINKAT,
+        [
+            old_dirfd as usize,
+            old as usize,
+            new_dirfd as usize,
+            new as usize,
+            flags as usize,
+            0,
+        ],
+    )
+}
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    syscall(
+        SYSCALL_UNLINKAT,
+        [dirfd as usize, path as usize, flags as usize, 0, 0, 0],
+    )
+}
+pub fn sys_symlinkat(target: *const u8, dirfd: isize, path: *const u8) -> isize {
+    syscall(
+        SYSCALL_SYMLINKAT,
+        [target as usize, dirfd as usize, path as usize, 0, 0, 0],
+    )
+}
+pub fn sys_readlinkat(dirfd: isize, path: *const u8, buf: &mut [u8]) -> isize {
+    syscall(
+        SYSCALL_READLINKAT,
+        [
+            dirfd as usize,
+            path as usize,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            0,
+            0,
+        ],
+    )
+}
+pub fn sys_renameat(old_dirfd: isize, old: *const u8, new_dirfd: isize, new: *const u8) -> isize {
+    syscall(
+        SYSCALL_RENAMEAT,
+        [
+            old_dirfd as usize,
+            old as usize,
+            new_dirfd as usize,
+            new as usize,
+            0,
+            0,
+        ],
+    )
+}
+
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fstype: *const u8,
+    flags: usize,
+    data: *const u8,
+) -> isize {
+    syscall(
+        SYSCALL_MOUNT,
+        [
+            source as usize,
+            target as usize,
+            fstype as usize,
+            flags,
+            data as usize,
+            0,
+        ],
+    )
+}
+
+pub fn sys_umount2(target: *const u8, flags: u32) -> isize {
+    syscall(
+        SYSCALL_UMOUNT2,
+        [target as usize, flags as usize, 0, 0, 0, 0],
+    )
+}
+
+pub fn sys_pipe2(fds: &mut [i32; 2], flags: u32) -> isize {
+    syscall(
+        SYSCALL_PIPE2,
+        [fds.as_mut_ptr() as usize, flags as usize, 0, 0, 0, 0],
+    )
+}
+
+pub fn sys_dup(fd: usize) -> isize {
+    syscall(SYSCALL_DUP, [fd, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall(SYSCALL_DUP3, [old_fd, new_fd, flags as usize, 0, 0, 0])
 }
 
-fn syscall(id: usize, args: [usize; 3]) -> isize {
+fn syscall(id: usize, args: [usize; 6]) -> isize {
     let mut ret: isize;
     unsafe {
         asm!(
@@ -69,6 +229,9 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
             inlateout("x10") args[0] => ret,
             in("x11") args[1],
             in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x15") args[5],
             in("x17") id
         )
     }